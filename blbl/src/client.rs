@@ -0,0 +1,477 @@
+use std::sync::Arc;
+use std::marker::PhantomData;
+use async_trait::async_trait;
+use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE};
+use serde_json::Value;
+use stream_core::live::QualityNumber;
+use tracing::{debug, warn};
+use utils::error::{ApiRequestError, LiveError};
+use crate::rate_limit::{BackoffState, RateLimiter, REQUEST_BLOCKED_CODE};
+
+pub static BASE_HEADERS: &[(&str, &str)] = &[
+    ("accept-encoding", "gzip, deflate, br"),
+    ("accept-language", "zh-CN,zh;q=0.8,zh-TW;q=0.7,zh-HK;q=0.5,en;q=0.3,en-US;q=0.2"),
+    ("accept", "application/json, text/plain, */*"),
+    ("cache-control", "no-cache"),
+    ("connection", "keep-alive"),
+    ("origin", "https://live.bilibili.com"),
+    ("pragma", "no-cache"),
+    ("user-agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36"),
+];
+
+/// 发送接口请求，返回状态码和解压后的响应体；测试时替换为 `MockHttp`，不访问网络
+#[async_trait]
+pub trait HttpGet: Send + Sync {
+    async fn get(&self, url: &str, headers: HeaderMap, params: &[(&str, &str)]) -> Result<(u16, Vec<u8>), ApiRequestError>;
+}
+
+#[async_trait]
+impl HttpGet for Client {
+    async fn get(&self, url: &str, headers: HeaderMap, params: &[(&str, &str)]) -> Result<(u16, Vec<u8>), ApiRequestError> {
+        let res = Client::get(self, url).headers(headers).query(params).send().await?;
+        let status = res.status().as_u16();
+        Ok((status, res.bytes().await?.to_vec()))
+    }
+}
+
+/// 决定如何处理接口返回的 JSON
+pub trait ResponseStrategy: Send + Sync {
+    fn parse(body: Value) -> Result<Value, ApiRequestError>;
+    /// 从 `parse` 的结果中取出 `data`
+    fn data(parsed: &Value) -> &Value;
+}
+
+/// 原样返回完整的响应
+pub struct RawJson;
+
+impl ResponseStrategy for RawJson {
+    fn parse(body: Value) -> Result<Value, ApiRequestError> {
+        Ok(body)
+    }
+
+    fn data(parsed: &Value) -> &Value {
+        &parsed["data"]
+    }
+}
+
+/// 检查 `code`，只返回 `data` 字段
+pub struct CheckedData;
+
+impl ResponseStrategy for CheckedData {
+    fn parse(mut body: Value) -> Result<Value, ApiRequestError> {
+        let code = body["code"].as_i64().unwrap_or_default() as i32;
+        if code != 0 {
+            let message = body["message"].as_str()
+                .or_else(|| body["msg"].as_str())
+                .unwrap_or_default()
+                .to_string();
+            return Err(ApiRequestError::ApiError(code, message));
+        }
+        Ok(body["data"].take())
+    }
+
+    fn data(parsed: &Value) -> &Value {
+        parsed
+    }
+}
+
+/// B 站接口的 HTTP 客户端，`S` 决定返回完整响应还是 `data`
+pub struct BiliClient<S> {
+    client: Arc<Client>,
+    /// 接口请求走这里，直播流等其它请求直接使用 `client`
+    http: Arc<dyn HttpGet>,
+    headers: HeaderMap,
+    pub base_api_urls: Vec<String>,
+    pub base_live_api_urls: Vec<String>,
+    pub base_play_info_api_urls: Vec<String>,
+    limiter: Arc<RateLimiter>,
+    strategy: PhantomData<S>,
+}
+
+/// 创建一个可在多个直播间之间共享的连接池
+pub fn build_http_client() -> Arc<Client> {
+    // 与 `accept-encoding` 保持一致
+    let client = Client::builder()
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .build()
+        .unwrap_or_default();
+    Arc::new(client)
+}
+
+impl<S: ResponseStrategy> Default for BiliClient<S> {
+    fn default() -> Self {
+        Self::new(build_http_client(), HeaderMap::new())
+    }
+}
+
+impl<S: ResponseStrategy> BiliClient<S> {
+    /// `headers` 与 `BASE_HEADERS` 合并，同名时 `headers` 优先，其余默认请求头保留；
+    /// 之后的 `update_heads`、`with_user_agent` 又会覆盖这里的值。传入同一个 `client` 的实例共用连接池
+    pub fn new(client: Arc<Client>, headers: HeaderMap) -> Self {
+        let mut base_headers = HeaderMap::new();
+        for &(name, value) in BASE_HEADERS {
+            base_headers.insert(HeaderName::from_static(name), HeaderValue::from_static(value));
+        }
+        let mut this = Self {
+            http: client.clone(),
+            client,
+            headers: base_headers,
+            base_api_urls: vec!["https://api.bilibili.com".to_string()],
+            base_live_api_urls: vec!["https://api.live.bilibili.com".to_string()],
+            base_play_info_api_urls: vec!["https://api.live.bilibili.com".to_string()],
+            limiter: Arc::new(RateLimiter::unlimited()),
+            strategy: PhantomData,
+        };
+        this.update_heads(headers);
+        this
+    }
+
+    /// 覆盖三组接口地址，例如只有地区镜像能返回 play info 时；空列表保留原有地址。
+    /// 每个接口依次尝试列表中的地址，直到成功
+    pub fn set_base_urls(&mut self, base_api_urls: &[String], base_live_api_urls: &[String], base_play_info_api_urls: &[String]) {
+        for (urls, configured) in [
+            (&mut self.base_api_urls, base_api_urls),
+            (&mut self.base_live_api_urls, base_live_api_urls),
+            (&mut self.base_play_info_api_urls, base_play_info_api_urls),
+        ] {
+            if !configured.is_empty() {
+                *urls = configured.to_vec();
+            }
+        }
+    }
+
+    /// 只替换 User-Agent，其它请求头不变
+    pub fn with_user_agent(mut self, user_agent: &str) -> Result<Self, ApiRequestError> {
+        self.headers.insert(reqwest::header::USER_AGENT, HeaderValue::from_str(user_agent)?);
+        Ok(self)
+    }
+
+    /// 替换接口请求的实现，例如返回固定数据的 `MockHttp`
+    pub fn with_http(mut self, http: Arc<dyn HttpGet>) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// 每秒最多发出 `per_second` 个请求
+    pub fn with_rate_limit(self, per_second: f64) -> Self {
+        self.with_limiter(Arc::new(RateLimiter::new(per_second)))
+    }
+
+    /// 与其它 `BiliClient` 共用限速和退避状态
+    pub fn with_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    pub fn limiter(&self) -> &Arc<RateLimiter> {
+        &self.limiter
+    }
+
+    /// 被 `-412` 拦截后处于退避中时返回当前状态
+    pub fn backoff(&self) -> Option<BackoffState> {
+        self.limiter.backoff()
+    }
+
+    pub fn http_client(&self) -> &Arc<Client> {
+        &self.client
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// `Cookie` 和 `Authorization` 标记为敏感，打印请求头时不会输出其内容
+    pub fn update_heads(&mut self, headers: HeaderMap) {
+        for (name, mut value) in headers {
+            if let Some(name) = name {
+                if name == COOKIE || name == AUTHORIZATION {
+                    value.set_sensitive(true);
+                }
+                self.headers.insert(name, value);
+            }
+        }
+    }
+
+    pub async fn get_json_res(&self, url: &str, params: &[(&str, &str)]) -> Result<Value, ApiRequestError> {
+        self.limiter.acquire().await;
+        let (status, body) = self.http.get(url, self.headers.clone(), params).await?;
+        let blocked = status == 412;
+        let body: Value = if blocked {
+            Value::Null
+        } else {
+            serde_json::from_slice(&body)?
+        };
+        // 请求头中的 Cookie 已标记为敏感，这里只记录地址和参数
+        debug!("Request: {:?} {:?}", url, params);
+        debug!("Response: {:?}", body);
+        if blocked || body["code"].as_i64() == Some(REQUEST_BLOCKED_CODE as i64) {
+            let state = self.limiter.on_blocked();
+            warn!("Request blocked by server, back off for {:?}", state.remaining());
+            return Err(ApiRequestError::ApiError(REQUEST_BLOCKED_CODE, "request was blocked".to_string()));
+        }
+        self.limiter.on_success();
+        S::parse(body)
+    }
+
+    /// 依次尝试 `base_urls`，返回第一个成功的结果
+    pub async fn get_json(&self, base_urls: &[String], path: &str, params: &[(&str, &str)]) -> Result<Value, ApiRequestError> {
+        let mut exception = None;
+        for base_url in base_urls {
+            let url = format!("{}{}", base_url, path);
+            match self.get_json_res(&url, params).await {
+                Ok(res) => return Ok(res),
+                Err(e) => {
+                    debug!("Failed to get json from {}: {}", url, e);
+                    exception = Some(e);
+                }
+            }
+        }
+        Err(exception.unwrap_or(ApiRequestError::NoBaseUrls))
+    }
+
+    pub async fn room_init(&self, room_id: i32) -> Result<Value, ApiRequestError> {
+        let path = "/room/v1/Room/room_init";
+        let id = room_id.to_string();
+        self.get_json(&self.base_live_api_urls, path, &[("id", id.as_str())]).await
+    }
+
+    /// 把短号解析为真实房间号，隐藏、上锁或加密的房间返回对应的错误
+    pub async fn resolve_room_id(&self, short_or_real: i32) -> Result<i32, LiveError> {
+        let body = self.room_init(short_or_real).await?;
+        room_id_from_init(S::data(&body))
+    }
+
+    pub async fn get_room_play_infos(&self, room_id: i32, qn: i32) -> Result<Value, ApiRequestError> {
+        let path = "/xlive/web-room/v2/index/getRoomPlayInfo";
+        let room_id = room_id.to_string();
+        let qn = qn.to_string();
+        let params = [
+            ("room_id", room_id.as_str()),
+            ("protocol", "0,1"),
+            ("format", "0,1,2"),
+            ("codec", "0,1"),
+            ("qn", qn.as_str()),
+            ("platform", "web"),
+            ("ptype", "8"),
+        ];
+        self.get_json(&self.base_play_info_api_urls, path, &params).await
+    }
+
+    /// 以最高画质请求一次，汇总所有 codec 的 `accept_qn`，按画质从高到低排序
+    pub async fn list_available_qualities(&self, room_id: i32) -> Result<Vec<QualityNumber>, ApiRequestError> {
+        let body = self.get_room_play_infos(room_id, QualityNumber::P20000.into()).await?;
+        Ok(accept_qualities(S::data(&body)))
+    }
+
+    pub async fn get_info_by_room(&self, room_id: i32) -> Result<Value, ApiRequestError> {
+        let path = "/xlive/web-room/v1/index/getInfoByRoom";
+        let room_id = room_id.to_string();
+        self.get_json(&self.base_live_api_urls, path, &[("room_id", room_id.as_str())]).await
+    }
+
+    pub async fn get_info(&self, room_id: i32) -> Result<Value, ApiRequestError> {
+        let path = "/room/v1/Room/get_info";
+        let room_id = room_id.to_string();
+        self.get_json(&self.base_live_api_urls, path, &[("room_id", room_id.as_str())]).await
+    }
+
+    pub async fn get_timestamp(&self) -> Result<Value, ApiRequestError> {
+        let path = "/av/v1/Time/getTimestamp";
+        self.get_json(&self.base_live_api_urls, path, &[("platform", "pc")]).await
+    }
+
+    pub async fn get_user_info(&self, uid: i32) -> Result<Value, ApiRequestError> {
+        let path = "/x/space/wbi/acc/info";
+        let uid = uid.to_string();
+        self.get_json(&self.base_api_urls, path, &[("mid", uid.as_str())]).await
+    }
+
+    pub async fn get_danmu_info(&self, room_id: i32) -> Result<Value, ApiRequestError> {
+        let path = "/xlive/web-room/v1/index/getDanmuInfo";
+        let room_id = room_id.to_string();
+        self.get_json(&self.base_live_api_urls, path, &[("id", room_id.as_str())]).await
+    }
+
+    pub async fn get_nav(&self) -> Result<Value, ApiRequestError> {
+        let path = "/x/web-interface/nav";
+        self.get_json(&self.base_api_urls, path, &[]).await
+    }
+}
+
+fn room_id_from_init(data: &Value) -> Result<i32, LiveError> {
+    if data["is_hidden"].as_bool() == Some(true) {
+        return Err(LiveError::LiveRoomHidden);
+    }
+    if data["is_locked"].as_bool() == Some(true) {
+        return Err(LiveError::LiveRoomLocked);
+    }
+    if data["encrypted"].as_bool() == Some(true) && data["pwd_verified"].as_bool() != Some(true) {
+        return Err(LiveError::LiveRoomEncrypted);
+    }
+    data["room_id"].as_i64()
+        .map(|room_id| room_id as i32)
+        .ok_or(LiveError::InvalidRoomInfoResponse)
+}
+
+fn accept_qualities(data: &Value) -> Vec<QualityNumber> {
+    let mut numbers: Vec<QualityNumber> = data["playurl_info"]["playurl"]["stream"].as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|stream| stream["format"].as_array().into_iter().flatten())
+        .flat_map(|format| format["codec"].as_array().into_iter().flatten())
+        .flat_map(|codec| codec["accept_qn"].as_array().into_iter().flatten())
+        .filter_map(|qn| qn.as_i64())
+        // 跳过未知的画质
+        .filter_map(|qn| QualityNumber::try_from(qn as i32).ok())
+        .collect();
+    numbers.sort_unstable_by(|a, b| b.cmp(a));
+    numbers.dedup();
+    numbers
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::Arc;
+    use reqwest::header::HeaderMap;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use utils::error::{ApiRequestError, LiveError};
+    use stream_core::live::QualityNumber;
+    use super::{accept_qualities, build_http_client, BASE_HEADERS, room_id_from_init, BiliClient, CheckedData, RawJson, ResponseStrategy};
+
+    #[test]
+    fn strategies() {
+        let body = json!({"code": 0, "message": "0", "data": {"uid": 1}});
+        assert_eq!(RawJson::parse(body.clone()).unwrap(), body);
+        assert_eq!(CheckedData::parse(body).unwrap(), json!({"uid": 1}));
+
+        let body = json!({"code": -400, "msg": "bad request"});
+        assert!(matches!(
+            CheckedData::parse(body),
+            Err(ApiRequestError::ApiError(-400, message)) if message == "bad request"
+        ));
+    }
+
+    #[test]
+    fn headers_merged_over_defaults() {
+        let mut headers = HeaderMap::new();
+        headers.insert(reqwest::header::REFERER, "https://live.bilibili.com/6".parse().unwrap());
+        let client = BiliClient::<RawJson>::new(build_http_client(), headers)
+            .with_user_agent("blzbj/0.1")
+            .unwrap();
+        let headers = client.headers();
+        assert_eq!(headers[reqwest::header::USER_AGENT], "blzbj/0.1");
+        assert_eq!(headers[reqwest::header::REFERER], "https://live.bilibili.com/6");
+        assert_eq!(headers[reqwest::header::ACCEPT], "application/json, text/plain, */*");
+        assert_eq!(headers.len(), BASE_HEADERS.len() + 1);
+        assert!(BiliClient::<RawJson>::default().with_user_agent("bad\nagent").is_err());
+    }
+
+    #[test]
+    fn cookie_not_in_debug_output() {
+        let mut headers = HeaderMap::new();
+        headers.insert(reqwest::header::COOKIE, "SESSDATA=abc123".parse().unwrap());
+        headers.insert(reqwest::header::AUTHORIZATION, "Bearer token".parse().unwrap());
+        let client = BiliClient::<RawJson>::new(build_http_client(), headers);
+        let output = format!("{:?}", client.headers());
+        assert!(!output.contains("abc123") && !output.contains("token"));
+        assert_eq!(client.headers()[reqwest::header::COOKIE], "SESSDATA=abc123");
+    }
+
+    #[test]
+    fn shared_http_client() {
+        let http_client = build_http_client();
+        let web = BiliClient::<RawJson>::new(http_client.clone(), HeaderMap::new());
+        let app = BiliClient::<CheckedData>::new(http_client.clone(), HeaderMap::new());
+        assert!(Arc::ptr_eq(web.http_client(), app.http_client()));
+        // 每个客户端同时作为 `client` 和默认的 `http` 持有
+        assert_eq!(Arc::strong_count(&http_client), 5);
+    }
+
+    #[tokio::test]
+    async fn brotli_response() {
+        let mut body = Vec::new();
+        {
+            let mut writer = brotli::CompressorWriter::new(&mut body, 4096, 5, 22);
+            writer.write_all(br#"{"code":0,"message":"0","data":{"room_id":5050}}"#).unwrap();
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await.unwrap();
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Encoding: br\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(header.as_bytes()).await.unwrap();
+            socket.write_all(&body).await.unwrap();
+        });
+
+        let client = BiliClient::<CheckedData>::default();
+        let data = client.get_json_res(&format!("http://{addr}/room/v1/Room/room_init"), &[]).await.unwrap();
+        assert_eq!(data["room_id"], 5050);
+    }
+
+    #[tokio::test]
+    async fn blocked_response_backs_off() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await.unwrap();
+            let body = r#"{"code":-412,"message":"request was banned"}"#;
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(header.as_bytes()).await.unwrap();
+            socket.write_all(body.as_bytes()).await.unwrap();
+        });
+
+        let client = BiliClient::<RawJson>::default().with_rate_limit(10.0);
+        let result = client.get_json_res(&format!("http://{addr}/xlive/web-room/v1/index/getInfoByRoom"), &[]).await;
+        assert!(matches!(result, Err(ApiRequestError::ApiError(-412, _))));
+        assert_eq!(client.backoff().unwrap().attempts, 1);
+    }
+
+    #[test]
+    fn resolve_short_id() {
+        let data = json!({"room_id": 5050, "short_id": 6, "is_hidden": false, "is_locked": false, "encrypted": false});
+        assert_eq!(room_id_from_init(&data).unwrap(), 5050);
+
+        let locked = json!({"room_id": 5050, "is_locked": true});
+        assert!(matches!(room_id_from_init(&locked), Err(LiveError::LiveRoomLocked)));
+        let encrypted = json!({"room_id": 5050, "encrypted": true, "pwd_verified": false});
+        assert!(matches!(room_id_from_init(&encrypted), Err(LiveError::LiveRoomEncrypted)));
+        let verified = json!({"room_id": 5050, "encrypted": true, "pwd_verified": true});
+        assert_eq!(room_id_from_init(&verified).unwrap(), 5050);
+        assert!(matches!(room_id_from_init(&json!(null)), Err(LiveError::InvalidRoomInfoResponse)));
+    }
+
+    #[test]
+    fn qualities_from_play_info() {
+        let data = json!({"playurl_info": {"playurl": {"stream": [
+            {"format": [{"codec": [{"accept_qn": [10000, 400, 250, 150]}]}]},
+            {"format": [{"codec": [{"accept_qn": [10000, 401, 30000]}]}]},
+        ]}}});
+        assert_eq!(
+            accept_qualities(&data),
+            [
+                QualityNumber::P10000,
+                QualityNumber::P401,
+                QualityNumber::P400,
+                QualityNumber::P250,
+                QualityNumber::P150,
+            ]
+        );
+        assert!(accept_qualities(&json!(null)).is_empty());
+    }
+}
@@ -1,4 +1,8 @@
 use serde::Deserialize;
 
-mod live;
+pub mod client;
+pub mod monitor;
+pub mod rate_limit;
+pub mod live;
+pub mod mock;
 mod api;
\ No newline at end of file
@@ -1,15 +1,24 @@
-use std::collections::HashMap;
-use stream_core::live::{LiveTrait, RoomInfo, LiveStatus, QualityNumber, StreamFormat};
+use std::sync::Arc;
+use async_trait::async_trait;
+use reqwest::Client;
+use reqwest::header::{HeaderMap, COOKIE, ORIGIN, REFERER, USER_AGENT};
+use serde_json::Value;
+use stream_core::live::{LiveTrait, RoomInfo, QualityNumber, StreamFormat};
+use flv::flv_donload::FlvConnection;
+use flv::probe::{probe_flv, StreamProbe};
 use crate::api::{WebClient};
 use anyhow::{anyhow, Result};
+use utils::secret::Secret;
+use utils::BResult;
 
 pub struct Live {
-    room_id: usize,
+    room_id: i32,
     user_agent: Option<String>,
-    cookie: Option<String>,
+    cookie: Option<Secret<String>>,
     client: WebClient,
     room_info: Option<RoomInfo>,
     no_flv_stream: bool,
+    quality_number: QualityNumber,
 }
 
 impl Default for Live {
@@ -18,90 +27,206 @@ impl Default for Live {
             room_id: 0,
             user_agent: None,
             cookie: None,
-            client: WebClient::new(None),
+            client: WebClient::default(),
             room_info: None,
             no_flv_stream: false,
+            quality_number: QualityNumber::P10000,
         }
     }
 }
 impl Live {
-    pub async fn init(mut self, room_id: usize) -> Result<Self> {
+    /// 与其它直播间共用同一个 `reqwest::Client` 的连接池
+    pub fn with_client(client: Arc<Client>) -> Self {
+        Self {
+            client: WebClient::new(client, HeaderMap::new()),
+            ..Self::default()
+        }
+    }
+
+    /// 直播中时请求一次播放地址，只有 fmp4 没有 flv 时之后改用 fmp4
+    pub async fn init(mut self, room_id: i32) -> Result<Self> {
         self.room_id = room_id;
-        self.room_info().await?;
-        self.no_flv_stream = true;
+        self.update_room_info().await?;
+        if self.room_info.as_ref().is_some_and(RoomInfo::is_living) {
+            let response = self.client.get_room_play_infos(self.room_id, self.quality_number.into()).await?;
+            let data = &response["data"];
+            self.no_flv_stream = stream_urls(data, StreamFormat::Flv.as_str()).is_empty()
+                && !stream_urls(data, StreamFormat::Fmp4.as_str()).is_empty();
+        }
         Ok(self)
     }
 
-    pub fn update_user_info(&mut self, user_agent: &str, cookie: &str) {
+    pub fn update_user_info(&mut self, user_agent: &str, cookie: &str) -> Result<()> {
         self.user_agent = Some(user_agent.to_string());
-        self.cookie = Some(cookie.to_string());
-        let mut heads = HashMap::new();
-        heads.insert("Referer".to_string(), format!("https://live.bilibili.com/{}", self.room_id));
-        heads.insert("User-Agent".to_string(), user_agent.to_string());
-        heads.insert("Cookie".to_string(), cookie.to_string());
+        self.cookie = Some(Secret::new(cookie.to_string()));
+        let mut heads = HeaderMap::new();
+        heads.insert(REFERER, format!("https://live.bilibili.com/{}", self.room_id).parse()?);
+        heads.insert(USER_AGENT, user_agent.parse()?);
+        heads.insert(COOKIE, cookie.parse()?);
         self.client.update_heads(heads);
+        Ok(())
     }
 
-    async fn room_info(&mut self) -> Result<()> {
-        let response = self.client.get_info_by_room(self.room_id).await?;
-        let room_info = parse_room_info(response)?;
-        self.room_info = Some(room_info);
-        Ok(())
+    /// 见 `BiliClient::set_base_urls`
+    pub fn set_base_urls(&mut self, base_api_urls: &[String], base_live_api_urls: &[String], base_play_info_api_urls: &[String]) {
+        self.client.set_base_urls(base_api_urls, base_live_api_urls, base_play_info_api_urls);
     }
 
-    fn is_living(&self) -> bool {
-      match self.room_info {
-          None => false,
-          Some(ref room_info) => room_info.is_living()
-      }
+    /// 最近一次 `update_room_info` 得到的房间信息
+    pub fn cached_room_info(&self) -> Option<&RoomInfo> {
+        self.room_info.as_ref()
     }
 
-    async fn get_live_streams(&self, qn: QualityNumber) -> Result<()>{
-        // 解释 得到url
-        let play_infos = self.client.get_room_play_infos(self.room_id, qn.into()).await?;
+    /// 打开指定画质的 FLV 流，只读取开头的 onMetaData 和 sequence header 得到编码、分辨率等参数后断开
+    pub async fn probe_stream(&self, qn: QualityNumber) -> Result<StreamProbe> {
+        let response = self.client.get_room_play_infos(self.room_id, qn.into()).await?;
+        let urls = stream_urls(&response["data"], StreamFormat::Flv.as_str());
+        let url = urls.first().ok_or_else(|| anyhow!("No flv stream for {qn:?}"))?;
+        let response = self.http_client().get(url).headers(self.http_headers()).send().await?.error_for_status()?;
+        Ok(probe_flv(&mut FlvConnection::new(response)).await?)
+    }
 
+    async fn update_room_info(&mut self) -> Result<()> {
+        self.room_info = Some(LiveTrait::room_info(self).await?);
         Ok(())
     }
 }
 
+#[async_trait]
+impl LiveTrait for Live {
+    async fn room_info(&self) -> BResult<RoomInfo> {
+        let response = self.client.get_info_by_room(self.room_id).await?;
+        Ok(parse_room_info(response)?)
+    }
+
+    fn stream_format(&self) -> BResult<StreamFormat> {
+        Ok(if self.no_flv_stream { StreamFormat::Fmp4 } else { StreamFormat::Flv })
+    }
+
+    async fn is_living(&self) -> BResult<bool> {
+        Ok(LiveTrait::room_info(self).await?.is_living())
+    }
+
+    async fn live_streams(&self) -> BResult<Vec<String>> {
+        let response = self.client.get_room_play_infos(self.room_id, self.quality_number.into()).await?;
+        Ok(stream_urls(&response["data"], self.stream_format()?.as_str()))
+    }
+
+    fn set_quality_number(&mut self, quality_number: QualityNumber) {
+        self.quality_number = quality_number;
+    }
+
+    fn http_client(&self) -> Arc<Client> {
+        self.client.http_client().clone()
+    }
+
+    /// 只带上 CDN 校验的请求头，`accept` 等接口请求头不适用于直播流
+    fn http_headers(&self) -> HeaderMap {
+        self.client.headers().iter()
+            .filter(|(name, _)| [REFERER, ORIGIN, USER_AGENT, COOKIE].contains(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+}
+
 fn parse_room_info(response: serde_json::Value) -> Result<RoomInfo> {
     let data = &response["data"]["room_info"];
     if data.is_null() {
         return Err(anyhow!("Missing room_info field"));
     }
-    let room_info = RoomInfo::new(
-        data["uid"].as_i64().unwrap_or_default() as i32,
-        data["room_id"].as_i64().unwrap_or_default() as i32,
-        data["short_id"].as_i64().unwrap_or_default() as i32,
-        data["area_id"].as_i64().unwrap_or_default() as i32,
-        data["area_name"].as_str().unwrap_or_default().to_string(),
-        data["parent_area_id"].as_i64().unwrap_or_default() as i32,
-        data["parent_area_name"].as_str().unwrap_or_default().to_string(),
-        LiveStatus::from(data["live_status"].as_i64().unwrap_or_default() as i32),
-        data["live_start_time"].as_i64().unwrap_or_default(),
-        data["online"].as_i64().unwrap_or_default() as i32,
-        data["title"].as_str().unwrap_or_default().to_string(),
-        data["cover"].as_str().unwrap_or_default().to_string(),
-        data["tags"].as_str().unwrap_or_default().to_string(),
-        data["description"].as_str().unwrap_or_default().to_string(),
-    );
-    Ok(room_info)
+    Ok(RoomInfo::try_from(data)?)
+}
+
+/// 从 `getRoomPlayInfo` 中取出指定封装格式的所有地址，只取每种封装的第一个编码
+fn stream_urls(data: &Value, format_name: &str) -> Vec<String> {
+    let empty = Vec::new();
+    let streams = data["playurl_info"]["playurl"]["stream"].as_array().unwrap_or(&empty);
+    streams.iter()
+        .flat_map(|stream| stream["format"].as_array().unwrap_or(&empty))
+        .filter(|format| format["format_name"].as_str() == Some(format_name))
+        .filter_map(|format| format["codec"].as_array().and_then(|codecs| codecs.first()))
+        .flat_map(|codec| {
+            let base_url = codec["base_url"].as_str().unwrap_or_default();
+            codec["url_info"].as_array().unwrap_or(&empty).iter().map(move |info| {
+                format!(
+                    "{}{}{}",
+                    info["host"].as_str().unwrap_or_default(),
+                    base_url,
+                    info["extra"].as_str().unwrap_or_default()
+                )
+            })
+        })
+        .collect()
 }
 
-// impl LiveTrait for Live {
-//     async fn room_info() -> Result<RoomInfo> {
-//         todo!()
-//     }
-//
-//     fn stream_format() -> Result<StreamFormat> {
-//         todo!()
-//     }
-//
-//     async fn is_living() -> Result<bool> {
-//         todo!()
-//     }
-//
-//     async fn live_streams() -> Result<Vec<String>> {
-//         todo!()
-//     }
-// }
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
+    use stream_core::live::{LiveStatus, LiveTrait, StreamFormat};
+    use crate::api::WebClient;
+    use crate::mock::MockHttp;
+    use reqwest::header::{ACCEPT, COOKIE, REFERER, USER_AGENT};
+    use super::{stream_urls, Live};
+
+    #[tokio::test]
+    async fn room_info_and_streams_from_fixtures() {
+        let http = MockHttp::new()
+            .with_json("/xlive/web-room/v1/index/getInfoByRoom", include_str!("../fixtures/get_info_by_room.json"))
+            .with_json("/xlive/web-room/v2/index/getRoomPlayInfo", include_str!("../fixtures/get_room_play_info.json"));
+        let live = Live { client: WebClient::default().with_http(Arc::new(http)), ..Live::default() };
+        let live = live.init(7734200).await.unwrap();
+
+        assert_eq!(live.stream_format().unwrap(), StreamFormat::Flv);
+        assert_eq!(live.live_streams().await.unwrap()[0], "https://cn-gddg-ct-01-01.bilivideo.com/live-bvc/123456/live_50329118_bs_7734200.flv?expires=1717003600&len=0&oi=0&pt=web");
+
+        let room_info = live.room_info().await.unwrap();
+        assert_eq!((room_info.room_id, room_info.short_room_id), (7734200, 6));
+        assert_eq!(room_info.live_status, LiveStatus::Live);
+        assert_eq!(room_info.description, "<p>赛事直播</p>\n每天更新");
+        assert!(live.is_living().await.unwrap());
+
+        let play_info: serde_json::Value = serde_json::from_str(include_str!("../fixtures/get_room_play_info.json")).unwrap();
+        assert_eq!(stream_urls(&play_info["data"], "flv"), [
+            "https://cn-gddg-ct-01-01.bilivideo.com/live-bvc/123456/live_50329118_bs_7734200.flv?expires=1717003600&len=0&oi=0&pt=web",
+            "https://d1--cn-gotcha03.bilivideo.com/live-bvc/123456/live_50329118_bs_7734200.flv?expires=1717003600&len=0&oi=0&pt=web",
+        ]);
+    }
+
+    #[test]
+    fn urls_of_format() {
+        let data = json!({"playurl_info": {"playurl": {"stream": [
+            {"format": [{"format_name": "flv", "codec": [{
+                "base_url": "/live-bvc/live.flv",
+                "url_info": [
+                    {"host": "https://a.bilivideo.com", "extra": "?expires=1"},
+                    {"host": "https://b.bilivideo.com", "extra": "?expires=2"},
+                ],
+            }]}]},
+            {"format": [{"format_name": "fmp4", "codec": [{
+                "base_url": "/live-bvc/index.m3u8",
+                "url_info": [{"host": "https://c.bilivideo.com", "extra": ""}],
+            }]}]},
+        ]}}});
+        assert_eq!(
+            stream_urls(&data, "flv"),
+            vec![
+                "https://a.bilivideo.com/live-bvc/live.flv?expires=1",
+                "https://b.bilivideo.com/live-bvc/live.flv?expires=2",
+            ]
+        );
+        assert_eq!(stream_urls(&data, "fmp4"), vec!["https://c.bilivideo.com/live-bvc/index.m3u8"]);
+        assert!(stream_urls(&json!({}), "flv").is_empty());
+    }
+
+    #[test]
+    fn stream_requests_carry_user_headers() {
+        let mut live = Live { room_id: 6, ..Live::default() };
+        live.update_user_info("test-agent", "SESSDATA=1").unwrap();
+        let headers = live.http_headers();
+        assert_eq!(headers[REFERER], "https://live.bilibili.com/6");
+        assert_eq!(headers[USER_AGENT], "test-agent");
+        assert_eq!(headers[COOKIE], "SESSDATA=1");
+        assert!(!headers.contains_key(ACCEPT));
+    }
+}
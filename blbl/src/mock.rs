@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use reqwest::header::HeaderMap;
+use url::Url;
+use utils::error::ApiRequestError;
+use crate::client::HttpGet;
+
+/// 按接口路径返回固定响应的 `HttpGet`，不访问网络，用于测试接口解析和录制流程；
+/// 同一路径在不同域名下的响应不同时，用带域名的地址注册
+#[derive(Default)]
+pub struct MockHttp {
+    responses: Mutex<HashMap<String, (u16, Vec<u8>)>>,
+    requests: Mutex<Vec<String>>,
+}
+
+impl MockHttp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `path` 为不含参数的接口路径，例如 `/room/v1/Room/room_init`，
+    /// 或带域名的地址，例如 `https://api.live.bilibili.com/room/v1/Room/room_init`
+    pub fn with_json(self, path: &str, body: &str) -> Self {
+        self.with_response(path, 200, body)
+    }
+
+    pub fn with_response(self, path: &str, status: u16, body: &str) -> Self {
+        self.responses.lock().insert(path.to_string(), (status, body.as_bytes().to_vec()));
+        self
+    }
+
+    /// 收到的请求，包含查询参数
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().clone()
+    }
+}
+
+#[async_trait]
+impl HttpGet for MockHttp {
+    async fn get(&self, url: &str, _: HeaderMap, params: &[(&str, &str)]) -> Result<(u16, Vec<u8>), ApiRequestError> {
+        let url = Url::parse_with_params(url, params).map_err(|e| ApiRequestError::InvalidResponse(e.to_string()))?;
+        self.requests.lock().push(url.to_string());
+        let without_query = format!("{}{}", url.origin().ascii_serialization(), url.path());
+        let responses = self.responses.lock();
+        responses
+            .get(&without_query)
+            .or_else(|| responses.get(url.path()))
+            .cloned()
+            .ok_or_else(|| ApiRequestError::InvalidResponse(format!("No mock response for {}", url.path())))
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::warn;
+use stream_core::live::{LiveMonitorTrait, LiveStatus, LiveStatusChange, RoomInfo};
+use utils::BResult;
+use crate::client::{BiliClient, ResponseStrategy};
+
+/// 轮询 `getInfoByRoom` 的直播状态，状态变化时广播 `LiveStatusChange`
+pub struct BiliLiveMonitor<S> {
+    client: Arc<BiliClient<S>>,
+    room_id: i32,
+    last_status: Mutex<Option<LiveStatus>>,
+    events: broadcast::Sender<LiveStatusChange>,
+}
+
+impl<S: ResponseStrategy + 'static> BiliLiveMonitor<S> {
+    pub fn new(client: Arc<BiliClient<S>>, room_id: i32) -> Self {
+        let (events, _) = broadcast::channel(16);
+        Self {
+            client,
+            room_id,
+            last_status: Mutex::new(None),
+            events,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LiveStatusChange> {
+        self.events.subscribe()
+    }
+
+    pub fn last_status(&self) -> Option<LiveStatus> {
+        *self.last_status.lock()
+    }
+
+    /// 后台按 `interval` 持续轮询，客户端退避期间暂停；丢弃 handle 不会停止任务，需要 `abort`
+    pub fn spawn(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Some(backoff) = self.client.backoff() {
+                    // 被拦截期间暂停轮询
+                    tokio::time::sleep(backoff.remaining()).await;
+                    continue;
+                }
+                if let Err(e) = self.poll_status().await {
+                    warn!("Failed to poll live status of room {}: {e}", self.room_id);
+                }
+            }
+        })
+    }
+
+    fn update(&self, current: LiveStatus) {
+        let previous = self.last_status.lock().replace(current);
+        if previous != Some(current) {
+            // 没有订阅者时发送失败，可以忽略
+            let _ = self.events.send(LiveStatusChange { previous, current });
+        }
+    }
+}
+
+#[async_trait]
+impl<S: ResponseStrategy + 'static> LiveMonitorTrait for BiliLiveMonitor<S> {
+    async fn poll_status(&self) -> BResult<LiveStatus> {
+        let body = self.client.get_info_by_room(self.room_id).await?;
+        let room_info = RoomInfo::try_from(&S::data(&body)["room_info"])?;
+        self.update(room_info.live_status);
+        Ok(room_info.live_status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use stream_core::live::{LiveStatus, LiveStatusChange};
+    use crate::api::WebClient;
+    use super::BiliLiveMonitor;
+
+    #[test]
+    fn emits_only_changes() {
+        let monitor = BiliLiveMonitor::new(Arc::new(WebClient::default()), 1);
+        let mut events = monitor.subscribe();
+        monitor.update(LiveStatus::Offline);
+        monitor.update(LiveStatus::Offline);
+        monitor.update(LiveStatus::Live);
+
+        assert_eq!(events.try_recv().unwrap(), LiveStatusChange { previous: None, current: LiveStatus::Offline });
+        assert_eq!(
+            events.try_recv().unwrap(),
+            LiveStatusChange { previous: Some(LiveStatus::Offline), current: LiveStatus::Live }
+        );
+        assert!(events.try_recv().is_err());
+        assert_eq!(monitor.last_status(), Some(LiveStatus::Live));
+    }
+}
@@ -0,0 +1,147 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use parking_lot::Mutex;
+use tokio::time::sleep;
+
+/// B 站拦截请求时返回的 `code`
+pub const REQUEST_BLOCKED_CODE: i32 = -412;
+
+const BACKOFF_BASE: Duration = Duration::from_secs(5);
+const BACKOFF_MAX: Duration = Duration::from_secs(600);
+
+/// 被 `-412` 拦截后的退避状态
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BackoffState {
+    /// 连续被拦截的次数
+    pub attempts: u32,
+    pub until: Instant,
+}
+
+impl BackoffState {
+    pub fn remaining(&self) -> Duration {
+        self.until.saturating_duration_since(Instant::now())
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// 令牌桶限速，同时记录 `-412` 退避；多个 `BiliClient` 共用一个实例即按 IP 整体限速
+pub struct RateLimiter {
+    per_second: Option<f64>,
+    bucket: Mutex<Bucket>,
+    backoff: Mutex<Option<BackoffState>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+impl RateLimiter {
+    /// 每秒最多 `per_second` 个请求，允许攒下最多一秒的突发
+    pub fn new(per_second: f64) -> Self {
+        Self {
+            per_second: Some(per_second.max(f64::MIN_POSITIVE)),
+            bucket: Mutex::new(Bucket { tokens: per_second.max(1.0), updated_at: Instant::now() }),
+            backoff: Mutex::new(None),
+        }
+    }
+
+    /// 不限速，只处理退避
+    pub fn unlimited() -> Self {
+        Self {
+            per_second: None,
+            bucket: Mutex::new(Bucket { tokens: 0.0, updated_at: Instant::now() }),
+            backoff: Mutex::new(None),
+        }
+    }
+
+    pub fn backoff(&self) -> Option<BackoffState> {
+        self.backoff.lock().filter(|state| state.until > Instant::now())
+    }
+
+    /// 等待退避结束并取得一个令牌
+    pub async fn acquire(&self) {
+        if let Some(state) = self.backoff() {
+            sleep(state.remaining()).await;
+        }
+        let Some(per_second) = self.per_second else {
+            return;
+        };
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock();
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * per_second).min(per_second.max(1.0));
+                bucket.updated_at = now;
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / per_second)
+            };
+            sleep(wait).await;
+        }
+    }
+
+    /// 记录一次拦截，退避时间按次数指数增长并加上最多 25% 的随机抖动
+    pub fn on_blocked(&self) -> BackoffState {
+        let mut backoff = self.backoff.lock();
+        let attempts = backoff.map_or(0, |state| state.attempts) + 1;
+        let delay = BACKOFF_BASE
+            .saturating_mul(1 << (attempts - 1).min(16))
+            .min(BACKOFF_MAX);
+        let state = BackoffState {
+            attempts,
+            until: Instant::now() + delay + jitter(delay / 4),
+        };
+        *backoff = Some(state);
+        state
+    }
+
+    pub fn on_success(&self) {
+        *self.backoff.lock() = None;
+    }
+}
+
+fn jitter(max: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    max.mul_f64(f64::from(nanos % 1000) / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::{RateLimiter, BACKOFF_BASE};
+
+    #[tokio::test]
+    async fn token_bucket() {
+        let limiter = RateLimiter::new(20.0);
+        let start = Instant::now();
+        for _ in 0..25 {
+            limiter.acquire().await;
+        }
+        // 桶里有 20 个令牌，剩下 5 个需要等待约 250ms
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn backoff_grows_and_resets() {
+        let limiter = RateLimiter::unlimited();
+        assert!(limiter.backoff().is_none());
+        let first = limiter.on_blocked();
+        let second = limiter.on_blocked();
+        assert_eq!(second.attempts, 2);
+        assert!(first.remaining() <= BACKOFF_BASE + BACKOFF_BASE / 4);
+        assert!(second.remaining() > BACKOFF_BASE);
+        assert_eq!(limiter.backoff(), Some(second));
+        limiter.on_success();
+        assert!(limiter.backoff().is_none());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::error::{FlvError, Result};
+use crate::flv_parser::{AudioSpecificConfig, AAC_SAMPLING_FREQUENCIES};
+
+/// 没有 CRC 时 ADTS 头部的长度
+pub const ADTS_HEADER_LENGTH: usize = 7;
+
+/// 生成不带 CRC 的 ADTS 头部，`payload_len` 为其后原始 AAC 帧的长度。
+/// ADTS 不支持任意采样率，不在表中的采样率使用最接近的一项
+pub fn asc_to_adts_header(asc: &AudioSpecificConfig, payload_len: usize) -> [u8; 7] {
+    let profile = asc.audio_object_type.saturating_sub(1) & 0x03;
+    let frequency_index = AAC_SAMPLING_FREQUENCIES
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, frequency)| frequency.abs_diff(asc.sampling_frequency))
+        .map_or(4, |(index, _)| index as u8);
+    let channel = asc.channel_configuration & 0x07;
+    let frame_length = (ADTS_HEADER_LENGTH + payload_len) as u32 & 0x1fff;
+    [
+        0xff,
+        0xf1, // MPEG-4，无 CRC
+        profile << 6 | frequency_index << 2 | channel >> 2,
+        (channel & 0x03) << 6 | (frame_length >> 11) as u8,
+        (frame_length >> 3) as u8,
+        ((frame_length & 0x07) as u8) << 5 | 0x1f,
+        0xfc,
+    ]
+}
+
+/// 带 CRC 时头部为 9 字节
+pub fn adts_header_length(adts: &[u8]) -> Result<usize> {
+    if adts.len() < ADTS_HEADER_LENGTH || adts[0] != 0xff || adts[1] & 0xf0 != 0xf0 {
+        return Err(FlvError::InvalidData("ADTS header".to_string()));
+    }
+    let length = if adts[1] & 0x01 == 0 { 9 } else { ADTS_HEADER_LENGTH };
+    if adts.len() < length {
+        return Err(FlvError::InvalidData("ADTS header".to_string()));
+    }
+    Ok(length)
+}
+
+pub fn adts_to_asc(adts: &[u8]) -> Result<AudioSpecificConfig> {
+    adts_header_length(adts)?;
+    let frequency_index = (adts[2] >> 2 & 0x0f) as usize;
+    let sampling_frequency = *AAC_SAMPLING_FREQUENCIES
+        .get(frequency_index)
+        .ok_or_else(|| FlvError::InvalidData("ADTS sampling frequency index".to_string()))?;
+    Ok(AudioSpecificConfig {
+        audio_object_type: (adts[2] >> 6) + 1,
+        sampling_frequency,
+        channel_configuration: (adts[2] & 0x01) << 2 | adts[3] >> 6,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adts_header_length, adts_to_asc, asc_to_adts_header};
+    use crate::flv_parser::parse_audio_specific_config;
+
+    #[test]
+    fn adts_round_trip() {
+        // AAC-LC 44100Hz 双声道
+        let asc = parse_audio_specific_config(&[0x12, 0x10]).unwrap();
+        let header = asc_to_adts_header(&asc, 371);
+        assert_eq!(header, [0xff, 0xf1, 0x50, 0x80, 0x2f, 0x5f, 0xfc]);
+        assert_eq!(adts_header_length(&header).unwrap(), 7);
+        assert_eq!(adts_to_asc(&header).unwrap(), asc);
+    }
+
+    #[test]
+    fn adts_with_crc() {
+        let mut header = [0xff, 0xf0, 0x50, 0x80, 0x2f, 0x5f, 0xfc, 0x12, 0x34];
+        assert_eq!(adts_header_length(&header).unwrap(), 9);
+        assert_eq!(adts_to_asc(&header).unwrap().sampling_frequency, 44100);
+        assert!(adts_to_asc(&header[..7]).is_err());
+
+        header[0] = 0;
+        assert!(adts_to_asc(&header).is_err());
+    }
+}
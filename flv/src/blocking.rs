@@ -0,0 +1,148 @@
+//! 基于 `std::io` 的同步读写，不需要 tokio 运行时。
+//!
+//! 与异步的 `flv_reader` / `flv_donload` 各自维护读取位置和缓冲，
+//! 不能交替用于同一个流。
+
+use crate::error::{FlvError, Result};
+use crate::flv_parser::{header, tag_data, tag_header, Header, OwnedTag, Tag, TagHeader};
+use crate::flv_writer::RawFlvTag;
+use bytes::Bytes;
+use std::io::{ErrorKind, Read, Write};
+
+const TAG_HEADER_LENGTH: usize = 11;
+
+pub struct BlockingFlvReader<R> {
+    reader: R,
+    header: Header,
+}
+
+impl<R: Read> BlockingFlvReader<R> {
+    /// 读取并校验文件头，跳过第一个 PreviousTagSize
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut bytes = [0u8; 9];
+        reader.read_exact(&mut bytes)?;
+        let (_, header) =
+            header(&bytes).map_err(|_| FlvError::InvalidData("flv header".to_string()))?;
+        let skip = (header.offset as u64).saturating_sub(bytes.len() as u64) + 4;
+        std::io::copy(&mut (&mut reader).take(skip), &mut std::io::sink())?;
+        Ok(Self { reader, header })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// 读取一个 tag 的头部和完整 body，流在 tag 边界结束时返回 `None`
+    pub fn read_raw_tag(&mut self) -> Result<Option<(TagHeader, Bytes)>> {
+        let mut bytes = [0u8; TAG_HEADER_LENGTH];
+        loop {
+            match self.reader.read(&mut bytes[..1]) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.reader.read_exact(&mut bytes[1..])?;
+        let (_, tag_header) =
+            tag_header(&bytes).map_err(|_| FlvError::InvalidData("flv tag header".to_string()))?;
+
+        let mut body = vec![0u8; tag_header.data_size as usize];
+        self.reader.read_exact(&mut body)?;
+        let mut previous_tag_size = [0u8; 4];
+        self.reader.read_exact(&mut previous_tag_size)?;
+
+        Ok(Some((tag_header, Bytes::from(body))))
+    }
+
+    /// 读取并解析下一个 tag，script tag 的内容需要用 `read_raw_tag` 获取
+    pub fn read_tag(&mut self) -> Result<Option<OwnedTag>> {
+        let Some((header, body)) = self.read_raw_tag()? else {
+            return Ok(None);
+        };
+        let (_, data) = tag_data(header.tag_type, body.len())(&body)
+            .map_err(|_| FlvError::InvalidData("flv tag data".to_string()))?;
+        Ok(Some(Tag { header, data }.to_owned()))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+pub struct BlockingFlvWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> BlockingFlvWriter<W> {
+    /// 写入文件头和第一个 PreviousTagSize
+    pub fn new(mut writer: W, has_audio: bool, has_video: bool) -> Result<Self> {
+        let flags = (has_audio as u8) << 2 | has_video as u8;
+        writer.write_all(&[b'F', b'L', b'V', 1, flags, 0, 0, 0, 9])?;
+        writer.write_all(&0u32.to_be_bytes())?;
+        Ok(Self { writer })
+    }
+
+    pub fn write_tag(&mut self, tag: &RawFlvTag) -> Result<()> {
+        self.writer.write_all(&tag.marshal())?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockingFlvReader, BlockingFlvWriter};
+    use crate::flv_parser::{video_data_header, OwnedTagData, TagType};
+    use crate::flv_writer::RawFlvTag;
+    use bytes::Bytes;
+
+    #[test]
+    fn write_then_read() {
+        let mut writer = BlockingFlvWriter::new(Vec::new(), false, true).unwrap();
+        writer
+            .write_tag(&RawFlvTag::script(0, Bytes::from_static(&[2, 0, 1, b'x', 5])))
+            .unwrap();
+        let (_, header) = video_data_header(&[0x17]).unwrap();
+        writer
+            .write_tag(&RawFlvTag::video(40, &header, Bytes::from_static(&[1, 0, 0, 0, 0xaa])))
+            .unwrap();
+        let bytes = writer.into_inner();
+
+        let mut reader = BlockingFlvReader::new(&bytes[..]).unwrap();
+        assert!(reader.header().has_video());
+        assert!(!reader.header().has_audio());
+
+        let (header, body) = reader.read_raw_tag().unwrap().unwrap();
+        assert_eq!(header.tag_type, TagType::Script);
+        assert_eq!(&body[..], &[2, 0, 1, b'x', 5]);
+
+        let tag = reader.read_tag().unwrap().unwrap();
+        assert_eq!(tag.header.timestamp, 40);
+        let OwnedTagData::Video(video) = tag.data else {
+            panic!("expected video data");
+        };
+        assert_eq!(&video.video_data[..], &[1, 0, 0, 0, 0xaa]);
+        assert!(reader.read_tag().unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_tag() {
+        let mut writer = BlockingFlvWriter::new(Vec::new(), true, false).unwrap();
+        writer
+            .write_tag(&RawFlvTag::script(0, Bytes::from_static(&[2, 0, 1, b'x', 5])))
+            .unwrap();
+        let bytes = writer.into_inner();
+
+        let mut reader = BlockingFlvReader::new(&bytes[..bytes.len() - 6]).unwrap();
+        assert!(reader.read_raw_tag().is_err());
+    }
+}
@@ -0,0 +1,37 @@
+use nom::Needed;
+use thiserror::Error;
+use utils::error::BError;
+use crate::flv_parser::CodecId;
+
+#[derive(Debug, Error)]
+pub enum FlvError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("HTTP request failed: {0}")]
+    HttpRequestError(#[from] reqwest::Error),
+    #[error("Invalid url: {0}")]
+    UrlError(#[from] url::ParseError),
+    #[error("Read timeout")]
+    ReadTimeout(#[from] tokio::time::error::Elapsed),
+    #[error("Incomplete {0}: {1:?}")]
+    NomIncomplete(String, Needed),
+    #[error("Invalid {0}")]
+    InvalidData(String),
+    #[error("Script data nested deeper than {0}")]
+    DepthLimitExceeded(usize),
+    #[error("Script data has more than {0} elements")]
+    TooLarge(usize),
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("Unsupported codec {0:?}")]
+    UnsupportedCodec(CodecId),
+}
+
+pub type Result<T> = std::result::Result<T, FlvError>;
+
+/// utils 不能引用 flv，放在 `BError::Other` 中，用 `BError::downcast_ref` 取回
+impl From<FlvError> for BError {
+    fn from(e: FlvError) -> Self {
+        BError::Other(e.into())
+    }
+}
@@ -1,21 +1,33 @@
-use crate::downloader::flv_parser::{
-    aac_audio_packet_header, avc_video_packet_header, script_data, tag_data, tag_header,
-    AACPacketType, AVCPacketType, CodecId, FrameType, SoundFormat, TagData, TagHeader,
+use crate::error::{FlvError, Result};
+use crate::flv_parser::{
+    aac_audio_packet_header, avc_video_packet_header, parse_script_data, tag_data, tag_header,
+    AACPacketType, AVCPacketType, CodecId, ExAudioPacketType, ExVideoPacketType, FrameType,
+    OwnedAudioData, OwnedTag, OwnedTagData, OwnedVideoData, ScriptDataLimits, ScriptDataObject,
+    ScriptDataValue, SoundFormat, TagData, TagHeader,
 };
-use crate::downloader::flv_writer::{FlvFile, FlvTag, TagDataHeader};
-use crate::downloader::util::{LifecycleFile, Segmentable};
+use crate::flv_writer::{FlvTag, FlvWriterMuxer, TagDataHeader};
+use crate::keyframe::KeyframeSampler;
+use crate::pipline::{CommentType, ProcessingComment, RepairPipeline};
+use crate::probe::resolution_from_video_tag;
+use utils::throughput::Throughput;
+use utils::{LifecycleFile, Segmentable};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use nom::{Err, IResult};
 use reqwest::Response;
 
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
-use reqwest::header::HeaderMap;
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::time::timeout;
-use tracing::{debug, error, info, warn};
+use tracing::{info, warn};
 
-pub async fn download(connection: Connection, file_name: &str, segment: Segmentable) {
+pub async fn download(connection: FlvConnection, file_name: &str, segment: Segmentable) {
     let file: LifecycleFile = LifecycleFile::new(file_name, "flv", None);
-    match parse_flv(connection, file, segment).await {
+    match parse_flv(connection, file, segment, RepairPipeline::standard(), None, &AtomicBool::new(false)).await {
         Ok(_) => {
             info!("Done... {file_name}");
         }
@@ -25,47 +37,89 @@ pub async fn download(connection: Connection, file_name: &str, segment: Segmenta
     }
 }
 
-pub(crate) async fn parse_flv(
-    mut connection: Connection,
+/// 每个 GOP 写入前经过 `pipeline` 修复，开始新文件时清空规则状态；
+/// 视频 sequence header 变化时由 `SplitOnSequenceHeaderChangeRule` 判断，从新的 sequence header 开始新文件。
+/// `keyframes` 不为空时同时按间隔取出关键帧。
+/// `cancel` 被置位后写完已缓存的 tag 并关闭文件，返回 `FlvError::Cancelled`。
+/// 服务端正常关闭连接时返回 `Ok`；连接中途出错、断在 tag 中间或遇到无法解析的 tag 时
+/// 同样写完已缓存的 tag，但返回错误，调用方据此区分直播可能已结束和需要重连的断流
+pub async fn parse_flv(
+    mut connection: FlvConnection,
     file: LifecycleFile,
     mut segment: Segmentable,
-) -> crate::downloader::error::Result<()>
+    pipeline: RepairPipeline,
+    mut keyframes: Option<KeyframeSampler>,
+    cancel: &AtomicBool,
+) -> Result<()>
 {
     let mut flv_tags_cache: Vec<(TagHeader, Bytes, Bytes)> = Vec::new();
 
     let _previous_tag_size = connection.read_frame(4).await?;
 
-    let mut out = FlvFile::new(file)?;
+    let mut out = FlvWriterMuxer::new(file)?;
     segment.set_size_position(9 + 4);
     // let mut downloaded_size = 9 + 4;
     let mut on_meta_data = None;
     let mut aac_sequence_header = None;
     let mut h264_sequence_header: Option<(TagHeader, Bytes, Bytes)> = None;
     let mut prev_timestamp = 0;
-    let mut create_new = false;
+    let mut first_keyframe = true;
+    let mut cancelled = false;
+    let mut truncated = false;
+    let mut invalid = None;
+    // 解析失败时记录错误并停止读取，已缓存的 tag 仍会写入
+    macro_rules! parse_or_break {
+        ($result:expr, $msg:expr) => {
+            match map_parse_err($result, $msg) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    invalid = Some(e);
+                    break;
+                }
+            }
+        };
+    }
     loop {
+        if cancel.load(Ordering::Relaxed) {
+            cancelled = true;
+            break;
+        }
         let tag_header_bytes = connection.read_frame(11).await?;
         if tag_header_bytes.is_empty() {
             // let mut rdr = Cursor::new(tag_header_bytes);
             // println!("{}", rdr.read_u32::<BigEndian>().unwrap());
             break;
         }
+        if tag_header_bytes.len() < 11 {
+            truncated = true;
+            break;
+        }
 
-        let (_, tag_header) = map_parse_err(tag_header(&tag_header_bytes), "tag header")?;
+        let (_, tag_header) = parse_or_break!(tag_header(&tag_header_bytes), "tag header");
         // write_tag_header(&mut out, &tag_header)?;
 
         let bytes = connection.read_frame(tag_header.data_size as usize).await?;
         let previous_tag_size = connection.read_frame(4).await?;
+        if bytes.len() < tag_header.data_size as usize || previous_tag_size.len() < 4 {
+            truncated = true;
+            break;
+        }
         // out.write(&bytes)?;
-        let (i, flv_tag_data) = map_parse_err(
+        let (i, flv_tag_data) = parse_or_break!(
             tag_data(tag_header.tag_type, tag_header.data_size as usize)(&bytes),
-            "tag data",
-        )?;
+            "tag data"
+        );
         let flv_tag = match flv_tag_data {
             TagData::Audio(audio_data) => {
-                let packet_type = if audio_data.sound_format == SoundFormat::AAC {
-                    let (_, packet_header) = aac_audio_packet_header(audio_data.sound_data)
-                        .expect("Error in parsing aac audio packet header.");
+                if audio_data.ex_packet_type == Some(ExAudioPacketType::SequenceStart) {
+                    aac_sequence_header =
+                        Some((tag_header, bytes.clone(), previous_tag_size.clone()))
+                }
+                let packet_type = if audio_data.ex_packet_type.is_none()
+                    && audio_data.sound_format == SoundFormat::AAC
+                {
+                    let (_, packet_header) =
+                        parse_or_break!(aac_audio_packet_header(audio_data.sound_data), "aac audio packet header");
                     if packet_header.packet_type == AACPacketType::SequenceHeader {
                         if aac_sequence_header.is_some() {
                             warn!("Unexpected aac sequence header tag. {tag_header:?}");
@@ -87,22 +141,26 @@ pub(crate) async fn parse_flv(
                         sound_rate: audio_data.sound_rate,
                         sound_size: audio_data.sound_size,
                         sound_type: audio_data.sound_type,
+                        ex_packet_type: audio_data.ex_packet_type,
                         packet_type,
                     },
                 }
             }
             TagData::Video(video_data) => {
-                let (packet_type, composition_time) = if CodecId::H264 == video_data.codec_id {
-                    let (_, avc_video_header) = avc_video_packet_header(video_data.video_data)
-                        .expect("Error in parsing avc video packet header.");
+                if let Some(sampler) = keyframes.as_mut() {
+                    sampler.feed(tag_header.timestamp, &bytes);
+                }
+                // E-RTMP 的 SequenceStart 与 AVC 序列头一样需要在切分时重新写入
+                if video_data.ex_packet_type == Some(ExVideoPacketType::SequenceStart) {
+                    h264_sequence_header =
+                        Some((tag_header, bytes.clone(), previous_tag_size.clone()))
+                }
+                let (packet_type, composition_time) = if video_data.ex_packet_type.is_none()
+                    && CodecId::H264 == video_data.codec_id
+                {
+                    let (_, avc_video_header) =
+                        parse_or_break!(avc_video_packet_header(video_data.video_data), "avc video packet header");
                     if avc_video_header.packet_type == AVCPacketType::SequenceHeader {
-                        if let Some((_, binary_data, _)) = &h264_sequence_header {
-                            warn!("Unexpected h264 sequence header tag. {tag_header:?}");
-                            if bytes != binary_data {
-                                create_new = true;
-                                warn!("Different h264 sequence header tag. {tag_header:?}");
-                            }
-                        }
                         h264_sequence_header =
                             Some((tag_header, bytes.clone(), previous_tag_size.clone()))
                     }
@@ -119,13 +177,20 @@ pub(crate) async fn parse_flv(
                     data: TagDataHeader::Video {
                         frame_type: video_data.frame_type,
                         codec_id: video_data.codec_id,
+                        ex_packet_type: video_data.ex_packet_type,
                         packet_type,
                         composition_time,
                     },
                 }
             }
             TagData::Script => {
-                let (_, tag_data) = script_data(i).expect("Error in parsing script tag.");
+                let tag_data = match parse_script_data(i, &ScriptDataLimits::default()) {
+                    Ok(tag_data) => tag_data,
+                    Err(e) => {
+                        warn!("Skip invalid script tag: {e}. {tag_header:?}");
+                        continue;
+                    }
+                };
                 if on_meta_data.is_some() {
                     warn!("Unexpected script tag. {tag_header:?}");
                 }
@@ -148,52 +213,65 @@ pub(crate) async fn parse_flv(
                 ..
             } => {
                 let timestamp = flv_tag.header.timestamp as u64;
+                if first_keyframe {
+                    segment.set_start_time(Duration::from_millis(timestamp));
+                    first_keyframe = false;
+                }
                 segment.set_time_position(Duration::from_millis(timestamp));
-                for (tag_header, flv_tag_data, previous_tag_size_bytes) in &flv_tags_cache {
+                let (mut group, comments) =
+                    repair(&pipeline, std::mem::take(&mut flv_tags_cache), &out.file.file_name);
+                let header_changed = comments
+                    .iter()
+                    .any(|comment| comment.comment_type == CommentType::DecodingHeader && comment.action_required);
+                if header_changed {
+                    // 新的 sequence header 是这组 tag 的第一个关键帧，从它开始写入新文件，
+                    // onMetaData 使用其中的宽高
+                    if let (Some(meta), Some((width, height))) = (&mut on_meta_data, group_resolution(&group)) {
+                        info!("Video resolution changed to {width}x{height}");
+                        match with_resolution(meta, width, height) {
+                            Ok(patched) => *meta = patched,
+                            Err(e) => warn!("Keep onMetaData unchanged: {e}"),
+                        }
+                    }
+                    segment.set_start_time(Duration::from_millis(timestamp));
+                    segment.set_size_position(9 + 4);
+                    info!("{} splitting.{segment:?}", out.file.file_name);
+                    out.create_new()?;
+                    pipeline.reset();
+                    // 重新经过修复规则，新文件的规则状态从新的 sequence header 开始
+                    let headers = on_meta_data.iter().chain(&aac_sequence_header).cloned();
+                    group = repair(&pipeline, headers.chain(group).collect(), &out.file.file_name).0;
+                }
+                for (tag_header, flv_tag_data, previous_tag_size_bytes) in &group {
                     if tag_header.timestamp < prev_timestamp {
                         warn!("Non-monotonous DTS in output stream; previous: {prev_timestamp}, current: {};", tag_header.timestamp);
                     }
                     out.write_tag(tag_header, flv_tag_data, previous_tag_size_bytes)?;
                     segment.increase_size((11 + tag_header.data_size + 4) as u64);
+                    connection.add_written((11 + tag_header.data_size + 4) as u64);
                     // downloaded_size += (11 + tag_header.data_size + 4) as u64;
                     prev_timestamp = tag_header.timestamp
                     // println!("{downloaded_size}");
                 }
-                flv_tags_cache.clear();
 
-                if segment.needed() || create_new {
+                if segment.needed() {
                     segment.set_start_time(Duration::from_millis(timestamp));
                     segment.set_size_position(9 + 4);
-
-                    let (meta_header, meta_bytes, previous_meta_tag_size) =
-                        on_meta_data.as_ref().expect("on_meta_data does not exist");
                     // onMetaData
-                    flv_tags_cache.push((
-                        *meta_header,
-                        meta_bytes.clone(),
-                        previous_meta_tag_size.clone(),
-                    ));
+                    if let Some(meta) = &on_meta_data {
+                        flv_tags_cache.push(meta.clone());
+                    }
                     // AACSequenceHeader
-                    let aac_sequence_header = aac_sequence_header
-                        .as_ref()
-                        .expect("aac_sequence_header does not exist");
-                    flv_tags_cache.push((
-                        aac_sequence_header.0,
-                        aac_sequence_header.1.clone(),
-                        aac_sequence_header.2.clone(),
-                    ));
-                    if !create_new {
-                        // H264SequenceHeader
-                        flv_tags_cache.push(
-                            h264_sequence_header
-                                .as_ref()
-                                .expect("h264_sequence_header does not exist")
-                                .clone(),
-                        );
+                    if let Some(aac) = &aac_sequence_header {
+                        flv_tags_cache.push(aac.clone());
+                    }
+                    // H264SequenceHeader
+                    if let Some(h264) = &h264_sequence_header {
+                        flv_tags_cache.push(h264.clone());
                     }
                     info!("{} splitting.{segment:?}", out.file.file_name);
                     out.create_new()?;
-                    create_new = false;
+                    pipeline.reset();
                 }
                 flv_tags_cache.push((tag_header, bytes.clone(), previous_tag_size.clone()));
             }
@@ -202,79 +280,312 @@ pub(crate) async fn parse_flv(
             }
         }
     }
+    // 断流或取消时写入最后一个 GOP，文件在 `out` 释放时关闭
+    let (group, _) = repair(&pipeline, flv_tags_cache, &out.file.file_name);
+    for (tag_header, flv_tag_data, previous_tag_size_bytes) in &group {
+        out.write_tag(tag_header, flv_tag_data, previous_tag_size_bytes)?;
+        connection.add_written((11 + tag_header.data_size + 4) as u64);
+    }
+    out.buf_writer.flush()?;
+    if cancelled {
+        return Err(FlvError::Cancelled);
+    }
+    if let Some(e) = invalid {
+        return Err(e);
+    }
+    if let Some(e) = connection.take_error() {
+        return Err(e);
+    }
+    if truncated {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
     Ok(())
 }
 
+/// 让一组 tag 经过修复规则；规则只会丢弃或调整 tag 的顺序，输出按内容对应回原始字节。
+/// 规则的说明写入日志，需要切分、丢弃数据等处理的用 `warn`，同时返回给调用方
+fn repair(
+    pipeline: &RepairPipeline,
+    group: Vec<(TagHeader, Bytes, Bytes)>,
+    file_name: &str,
+) -> (Vec<(TagHeader, Bytes, Bytes)>, Vec<ProcessingComment>) {
+    let tags: Vec<OwnedTag> = group.iter().map(|(header, body, _)| owned_tag(header, body)).collect();
+    let (repaired, comments) = pipeline.process(tags.clone());
+    for ProcessingComment { comment_type, action_required, comment } in &comments {
+        if *action_required {
+            warn!("{file_name} {comment_type:?}: {comment}");
+        } else {
+            info!("{file_name} {comment_type:?}: {comment}");
+        }
+    }
+    let mut group: Vec<_> = group.into_iter().map(Some).collect();
+    let repaired = repaired
+        .iter()
+        .filter_map(|tag| {
+            let index = tags.iter().zip(&group).position(|(t, g)| g.is_some() && t == tag)?;
+            group[index].take()
+        })
+        .collect();
+    (repaired, comments)
+}
+
+/// 与 `Tag::to_owned` 相同，但音视频数据直接引用 `body`，不复制
+fn owned_tag(header: &TagHeader, body: &Bytes) -> OwnedTag {
+    let data = match tag_data(header.tag_type, body.len())(body) {
+        Ok((_, TagData::Audio(audio))) => OwnedTagData::Audio(OwnedAudioData {
+            sound_format: audio.sound_format,
+            sound_rate: audio.sound_rate,
+            sound_size: audio.sound_size,
+            sound_type: audio.sound_type,
+            ex_packet_type: audio.ex_packet_type,
+            sound_data: body.slice_ref(audio.sound_data),
+        }),
+        Ok((_, TagData::Video(video))) => OwnedTagData::Video(OwnedVideoData {
+            frame_type: video.frame_type,
+            codec_id: video.codec_id,
+            ex_packet_type: video.ex_packet_type,
+            video_data: body.slice_ref(video.video_data),
+        }),
+        // 缓存中的 tag 都已解析过，不会失败；按 script 处理，规则不会丢弃
+        Ok((_, TagData::Script)) | Err(_) => OwnedTagData::Script,
+    };
+    OwnedTag { header: *header, data }
+}
+
+/// 这组 tag 中第一个视频 sequence header 的宽高
+fn group_resolution(group: &[(TagHeader, Bytes, Bytes)]) -> Option<(u32, u32)> {
+    group
+        .iter()
+        .find_map(|(header, body, _)| resolution_from_video_tag(header.tag_type, body).ok().flatten())
+}
+
+/// 解析 onMetaData，设置 `width`、`height`，没有这两个字段时添加，再重新编码；
+/// tag 长度可能改变，同时更新 tag 头和 PreviousTagSize
+fn with_resolution(
+    (header, body, _): &(TagHeader, Bytes, Bytes),
+    width: u32,
+    height: u32,
+) -> Result<(TagHeader, Bytes, Bytes)> {
+    let mut script = parse_script_data(body, &ScriptDataLimits::default())?;
+    let (ScriptDataValue::ECMAArray(properties) | ScriptDataValue::Object(properties)) = &mut script.arguments
+    else {
+        return Err(FlvError::InvalidData(format!("{} is not an object", script.name)));
+    };
+    for (name, value) in [("width", width), ("height", height)] {
+        let data = ScriptDataValue::Number(value.into());
+        match properties.iter_mut().find(|property| property.name == name) {
+            Some(property) => property.data = data,
+            None => properties.push(ScriptDataObject { name, data }),
+        }
+    }
+    let mut body = Vec::new();
+    script.marshal(&mut body)?;
+    let header = TagHeader {
+        data_size: body.len() as u32,
+        ..*header
+    };
+    let previous_tag_size = Bytes::copy_from_slice(&(11 + header.data_size).to_be_bytes());
+    Ok((header, body.into(), previous_tag_size))
+}
+
+/// 不解析、不修复 tag，把连接上的字节原样写入文件；取消时停在 chunk 边界，文件末尾可能不完整
+pub async fn copy_raw(
+    mut connection: FlvConnection,
+    mut file: LifecycleFile,
+    flv_header: &[u8],
+    cancel: &AtomicBool,
+) -> Result<()> {
+    let mut out = BufWriter::new(File::create(file.create()?)?);
+    out.write_all(flv_header)?;
+    let result = loop {
+        if cancel.load(Ordering::Relaxed) {
+            break Err(FlvError::Cancelled);
+        }
+        match connection.read_chunk().await {
+            Ok(Some(chunk)) => {
+                if let Err(e) = out.write_all(&chunk) {
+                    break Err(e.into());
+                }
+                connection.add_written(chunk.len() as u64);
+            }
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    out.flush()?;
+    drop(out);
+    file.rename();
+    result
+}
+
 pub fn map_parse_err<'a, T>(
     i_result: IResult<&'a [u8], T>,
     msg: &str,
-) -> core::result::Result<(&'a [u8], T), crate::downloader::error::Error> {
+) -> Result<(&'a [u8], T)> {
     match i_result {
         Ok((i, res)) => Ok((i, res)),
-        Err(nom::Err::Incomplete(needed)) => Err(crate::downloader::error::Error::NomIncomplete(
+        Err(nom::Err::Incomplete(needed)) => Err(FlvError::NomIncomplete(
             msg.to_string(),
             needed,
         )),
-        Err(Err::Error(e)) => {
-            panic!("parse {msg} err: {e:?}")
-        }
-        Err(Err::Failure(f)) => {
-            panic!("{msg} Failure: {f:?}")
+        // 来自网络的数据可能有未知的 FourCC、编码或 tag 类型，不能 panic
+        Err(Err::Error(e)) | Err(Err::Failure(e)) => {
+            Err(FlvError::InvalidData(format!("{msg}: {:?}", e.code)))
         }
     }
 }
 
-pub struct Connection {
-    resp: Response,
+/// 未调用 `FlvConnection::with_buffer_size` 时的缓冲区大小
+pub const DEFAULT_CHUNK_SIZE: usize = 8 * 1024;
+
+/// 未调用 `FlvConnection::with_read_timeout` 时 HTTP 连接的读取超时
+pub const DEFAULT_HTTP_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 连接的数据来源，HTTP 响应之外也可以是本地文件、管道等任意 `AsyncRead`
+enum FlvSource {
+    Http(Response),
+    Reader(Box<dyn AsyncRead + Unpin + Send>),
+}
+
+pub struct FlvConnection {
+    source: FlvSource,
     buffer: BytesMut,
+    /// 从 `AsyncRead` 每次读取的最大字节数
+    chunk_size: usize,
+    read_timeout: Option<Duration>,
+    throughput: Option<Arc<Throughput>>,
+    /// `read_frame` 遇到的读取错误，连接中途断开时不为空
+    error: Option<FlvError>,
 }
 
-impl Connection {
-    pub fn new(resp: Response) -> Connection {
-        Connection {
-            resp,
-            buffer: BytesMut::with_capacity(8 * 1024),
+impl FlvConnection {
+    /// 每个 chunk 的读取超时默认为 `DEFAULT_HTTP_READ_TIMEOUT`
+    pub fn new(resp: Response) -> FlvConnection {
+        Self::with_source(FlvSource::Http(resp), Some(DEFAULT_HTTP_READ_TIMEOUT))
+    }
+
+    /// 从本地文件或管道读取，用于以录制相同的流程修复已有的 FLV；默认没有读取超时
+    pub fn from_reader<R: AsyncRead + Unpin + Send + 'static>(reader: R) -> FlvConnection {
+        Self::with_source(FlvSource::Reader(Box::new(reader)), None)
+    }
+
+    fn with_source(source: FlvSource, read_timeout: Option<Duration>) -> FlvConnection {
+        FlvConnection {
+            source,
+            buffer: BytesMut::with_capacity(DEFAULT_CHUNK_SIZE),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            read_timeout,
+            throughput: None,
+            error: None,
+        }
+    }
+
+    /// 单次读取超过 `read_timeout` 没有数据时返回 `FlvError::ReadTimeout`，`None` 表示不限制
+    pub fn with_read_timeout(mut self, read_timeout: Option<Duration>) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// 设置缓冲区的初始容量和每次从 `AsyncRead` 读取的字节数；
+    /// HTTP 响应的 chunk 大小由服务端决定，只影响缓冲区
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.chunk_size = buffer_size.max(1);
+        self.buffer.reserve(buffer_size);
+        self
+    }
+
+    /// 统计收到的字节数，`parse_flv` 和 `copy_raw` 同时记录写入文件的字节数
+    pub fn with_throughput(mut self, throughput: Arc<Throughput>) -> Self {
+        self.throughput = Some(throughput);
+        self
+    }
+
+    fn add_written(&self, bytes: u64) {
+        if let Some(throughput) = &self.throughput {
+            throughput.add_written(bytes);
+        }
+    }
+
+    fn add_downloaded(&self, bytes: u64) {
+        if let Some(throughput) = &self.throughput {
+            throughput.add_downloaded(bytes);
+        }
+    }
+
+    /// 从数据来源读取下一块数据，结束时返回 `None`
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>> {
+        let limit = self.read_timeout;
+        let chunk = match &mut self.source {
+            FlvSource::Http(resp) => with_timeout(limit, resp.chunk()).await??,
+            FlvSource::Reader(reader) => {
+                let mut buf = BytesMut::with_capacity(self.chunk_size);
+                match with_timeout(limit, reader.read_buf(&mut buf)).await?? {
+                    0 => None,
+                    _ => Some(buf.freeze()),
+                }
+            }
+        };
+        if let Some(chunk) = &chunk {
+            self.add_downloaded(chunk.len() as u64);
+        }
+        Ok(chunk)
+    }
+
+    /// 先返回已缓冲的数据，再返回下一个 chunk，连接结束时返回 `None`
+    pub async fn read_chunk(&mut self) -> Result<Option<Bytes>> {
+        if !self.buffer.is_empty() {
+            return Ok(Some(self.buffer.split().freeze()));
         }
+        self.next_chunk().await
     }
 
-    pub async fn read_frame(&mut self, chunk_size: usize) -> crate::downloader::error::Result<Bytes> {
-        // let mut buf = [0u8; 8 * 1024];
+    /// `read_frame` 返回剩余数据时，区分连接是正常结束还是中途出错
+    pub fn take_error(&mut self) -> Option<FlvError> {
+        self.error.take()
+    }
+
+    /// 读取 `chunk_size` 字节，连接结束或出错时返回剩余的数据（可能为空），只有读取超时返回错误；
+    /// 其它错误由 `take_error` 取得
+    pub async fn read_frame(&mut self, chunk_size: usize) -> Result<Bytes> {
         loop {
             if chunk_size <= self.buffer.len() {
                 let bytes = Bytes::copy_from_slice(&self.buffer[..chunk_size]);
                 self.buffer.advance(chunk_size);
                 return Ok(bytes);
             }
-            // BytesMut::with_capacity(0).deref_mut()
-            // tokio::fs::File::open("").read()
-            // self.resp.chunk()
-            if let Ok(Some(chunk)) = timeout(Duration::from_secs(30), self.resp.chunk()).await? {
-                // let n = chunk.len();
-                // println!("Chunk: {:?}", chunk);
-                self.buffer.put(chunk);
-                // self.buffer.put_slice(&buf[..n]);
-            } else {
-                return Ok(self.buffer.split().freeze());
+            match self.next_chunk().await {
+                Ok(Some(chunk)) => self.buffer.put(chunk),
+                Err(e @ FlvError::ReadTimeout(_)) => return Err(e),
+                Ok(None) => return Ok(self.buffer.split().freeze()),
+                Err(e) => {
+                    self.error = Some(e);
+                    return Ok(self.buffer.split().freeze());
+                }
             }
-            // let n = match self.resp.read(&mut buf).await {
-            //     Ok(n) => n,
-            //     Err(e) if e.kind() == ErrorKind::Interrupted => continue,
-            //     Err(e) => return Err(e),
-            // };
-
-            // if n == 0 {
-            //     return Ok(self.buffer.split().freeze());
-            // }
-            // self.buffer.put_slice(&buf[..n]);
         }
     }
 }
 
+async fn with_timeout<T>(limit: Option<Duration>, future: impl Future<Output = T>) -> Result<T> {
+    match limit {
+        Some(limit) => Ok(timeout(limit, future).await?),
+        None => Ok(future.await),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
+    use super::{parse_flv, FlvConnection};
+    use crate::error::FlvError;
+    use crate::pipline::rules::RemoveRepeatingDataRule;
+    use crate::pipline::RepairPipeline;
+    use crate::flv_parser::{parse_script_data, ScriptDataLimits, ScriptDataValue};
     use anyhow::Result;
     use bytes::{Buf, BufMut, BytesMut};
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+    use utils::{LifecycleFile, Segmentable};
 
     #[test]
     fn byte_it_works() -> Result<()> {
@@ -314,40 +625,356 @@ mod tests {
         //     "test.flv")?;
         Ok(())
     }
-}
 
+    fn flv_tag(tag_type: u8, timestamp: u32, body: &[u8]) -> Vec<u8> {
+        let mut tag = vec![tag_type];
+        tag.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        tag.extend_from_slice(&(timestamp & 0xffffff).to_be_bytes()[1..]);
+        tag.push((timestamp >> 24) as u8);
+        tag.extend_from_slice(&[0, 0, 0]);
+        tag.extend_from_slice(body);
+        tag.extend_from_slice(&(11 + body.len() as u32).to_be_bytes());
+        tag
+    }
 
-#[tokio::main]
-pub async fn download(
-    url: &str,
-    headers: HeaderMap,
-    file_name: &str,
-    segment: Segmentable,
-) -> anyhow::Result<()> {
-    let client = StatelessClient::new(headers);
-    let response = client.retryable(url).await?;
-    let mut connection = Connection::new(response);
-    // let buf = &mut [0u8; 9];
-    let bytes = connection.read_frame(9).await?;
-    // response.read_exact(buf)?;
-    // let out = File::create(format!("{}.flv", file_name)).expect("Unable to create file.");
-    // let mut writer = BufWriter::new(out);
-    // let mut buf = [0u8; 8 * 1024];
-    // response.copy_to(&mut writer)?;
-    // io::copy(&mut resp, &mut out).expect("Unable to copy the content.");
-    match header(&bytes) {
-        Ok((_i, header)) => {
-            debug!("header: {header:#?}");
-            info!("Downloading {}...", url);
-            httpflv::download(connection, file_name, segment).await;
+    /// 5 秒的音视频流，每秒一个关键帧
+    fn synthetic_stream() -> Vec<u8> {
+        let mut stream = vec![0x46, 0x4c, 0x56, 0x01, 0x05, 0x00, 0x00, 0x00, 0x09, 0, 0, 0, 0];
+        let mut meta = vec![2, 0, 10];
+        meta.extend_from_slice(b"onMetaData");
+        meta.extend_from_slice(&[8, 0, 0, 0, 0, 0, 0, 9]);
+        stream.extend(flv_tag(18, 0, &meta));
+        stream.extend(flv_tag(8, 0, &[0xaf, 0x00, 0x12, 0x10]));
+        stream.extend(flv_tag(9, 0, &[0x17, 0x00, 0, 0, 0, 0x01, 0x64]));
+        for timestamp in (0..5000).step_by(100) {
+            stream.extend(video_frame(timestamp));
+            stream.extend(flv_tag(8, timestamp, &[0xaf, 0x01, (timestamp / 100) as u8]));
         }
-        Err(Err::Incomplete(needed)) => {
-            error!("needed: {needed:?}")
+        stream
+    }
+
+    /// 每秒一个关键帧，每帧内容不同，不会被当作重复数据丢弃
+    fn video_frame(timestamp: u32) -> Vec<u8> {
+        let frame_type = if timestamp.is_multiple_of(1000) { 0x17 } else { 0x27 };
+        flv_tag(9, timestamp, &[frame_type, 0x01, 0, 0, 0, (timestamp / 100) as u8, 0xbb])
+    }
+
+    /// 第一个 tag 是 onMetaData，返回其中的宽高，同时检查 PreviousTagSize
+    fn meta_resolution(file: &[u8]) -> (Option<f64>, Option<f64>) {
+        let size = u32::from_be_bytes([0, file[14], file[15], file[16]]) as usize;
+        let previous_tag_size = u32::from_be_bytes(file[24 + size..28 + size].try_into().unwrap());
+        assert_eq!(previous_tag_size as usize, 11 + size);
+        let meta = parse_script_data(&file[24..24 + size], &ScriptDataLimits::default()).unwrap();
+        let ScriptDataValue::ECMAArray(properties) = meta.arguments else {
+            panic!("{:?}", meta.arguments);
+        };
+        let number = |name| match properties.iter().find(|property| property.name == name) {
+            Some(property) => match property.data {
+                ScriptDataValue::Number(number) => Some(number),
+                ref data => panic!("{name}: {data:?}"),
+            },
+            None => None,
+        };
+        (number("width"), number("height"))
+    }
+
+    #[tokio::test]
+    async fn resolution_change_updates_metadata() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flv_resolution_{}", std::process::id()));
+        // x264 的 720p 和 1080p SPS
+        let sps_720p = [
+            0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50, 0x05, 0xbb, 0x01, 0x10, 0x00, 0x00, 0x03, 0x00,
+            0x10, 0x00, 0x00, 0x03, 0x03, 0xc0, 0xf1, 0x83, 0x19, 0x60,
+        ];
+        let sps_1080p = [
+            0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0xc0, 0x44, 0x00, 0x00, 0x03,
+            0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0xf0, 0x3c, 0x60, 0xc6, 0x58,
+        ];
+        let sequence_header = |sps: &[u8]| {
+            let mut body = vec![0x17, 0, 0, 0, 0, 0x01, sps[1], sps[2], sps[3], 0xff, 0xe1];
+            body.extend((sps.len() as u16).to_be_bytes());
+            body.extend(sps);
+            body.extend([1, 0, 2, 0x68, 0xee]);
+            body
+        };
+        // 已有宽高时改写，没有时添加
+        let cases = [
+            ("patch", vec![("width", 1280.0f64), ("height", 720.0)]),
+            ("insert", vec![("duration", 4.0)]),
+        ];
+        for (name, properties) in cases {
+            let mut stream = vec![0x46, 0x4c, 0x56, 0x01, 0x05, 0x00, 0x00, 0x00, 0x09, 0, 0, 0, 0];
+            let mut meta = b"\x02\x00\x0aonMetaData\x08".to_vec();
+            meta.extend((properties.len() as u32).to_be_bytes());
+            for (name, value) in &properties {
+                meta.extend((name.len() as u16).to_be_bytes());
+                meta.extend(name.as_bytes());
+                meta.push(0);
+                meta.extend(value.to_be_bytes());
+            }
+            meta.extend([0, 0, 9]);
+            stream.extend(flv_tag(18, 0, &meta));
+            stream.extend(flv_tag(9, 0, &sequence_header(&sps_720p)));
+            for timestamp in (0..4000).step_by(100) {
+                if timestamp == 2000 {
+                    stream.extend(flv_tag(9, timestamp, &sequence_header(&sps_1080p)));
+                }
+                // 相同的 sequence header 不切分
+                if timestamp == 3000 {
+                    stream.extend(flv_tag(9, timestamp, &sequence_header(&sps_1080p)));
+                }
+                stream.extend(video_frame(timestamp));
+            }
+
+            let mut connection = FlvConnection::from_reader(std::io::Cursor::new(stream));
+            connection.read_frame(9).await?;
+            let file_name = dir.join(name);
+            let file = LifecycleFile::new(file_name.to_str().unwrap(), "flv", None);
+            parse_flv(connection, file, Segmentable::new(None, None), RepairPipeline::standard(), None, &AtomicBool::new(false)).await?;
+
+            let first = std::fs::read(dir.join(format!("{name}.flv")))?;
+            let second = std::fs::read(dir.join(format!("{name}_1.flv")))?;
+            assert!(!dir.join(format!("{name}_2.flv")).exists());
+            if name == "patch" {
+                assert_eq!(meta_resolution(&first), (Some(1280.0), Some(720.0)));
+            } else {
+                assert_eq!(meta_resolution(&first), (None, None));
+            }
+            assert_eq!(meta_resolution(&second), (Some(1920.0), Some(1080.0)));
+            // 旧文件只有原来的 sequence header，新文件以 onMetaData 和新的 sequence header 开始
+            assert_eq!(tag_types(&first).iter().filter(|(_, byte)| *byte == 0x17).count(), 3);
+            assert_eq!(&tag_types(&second)[..3], [(18, 2), (9, 0x17), (9, 0x17)]);
+            // onMetaData、两个 1080p sequence header 和 20 帧
+            assert_eq!(tag_types(&second).len(), 1 + 2 + 20);
         }
-        Err(e) => {
-            error!("{e}");
-            hls::download(url, &client, file_name, segment).await?;
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    fn tag_types(file: &[u8]) -> Vec<(u8, u8)> {
+        let mut tags = Vec::new();
+        let mut i = 13;
+        while i < file.len() {
+            let size = u32::from_be_bytes([0, file[i + 1], file[i + 2], file[i + 3]]) as usize;
+            tags.push((file[i], file[i + 11]));
+            i += 11 + size + 4;
         }
+        tags
     }
-    Ok(())
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn split_on_duration_limit() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flv_split_{}", std::process::id()));
+        let response = reqwest::Response::from(http::Response::new(synthetic_stream()));
+        let mut connection = FlvConnection::new(response);
+        connection.read_frame(9).await?;
+        let file_name = dir.join("record");
+        let file = LifecycleFile::new(file_name.to_str().unwrap(), "flv", None);
+        let segment = Segmentable::new(Some(Duration::from_secs(2)), None);
+        parse_flv(connection, file, segment, RepairPipeline::standard(), None, &AtomicBool::new(false)).await?;
+
+        let mut parts = Vec::new();
+        for name in ["record.flv", "record_1.flv", "record_2.flv"] {
+            parts.push(std::fs::read(dir.join(name))?);
+        }
+        assert!(!dir.join("record_3.flv").exists());
+        for part in &parts {
+            assert_eq!(&part[..3], b"FLV");
+            let tags = tag_types(part);
+            // onMetaData, AAC 和 AVC sequence header，然后从关键帧开始
+            assert_eq!(tags[0].0, 18);
+            assert_eq!(tags[1], (8, 0xaf));
+            assert_eq!(tags[2], (9, 0x17));
+            assert!(tags[3..].iter().find(|(t, _)| *t == 9) == Some(&(9, 0x17)));
+        }
+        // 所有帧都被写入：50 个视频帧和 50 个音频帧
+        let frames: usize = parts.iter().map(|part| tag_types(part).len() - 3).sum();
+        assert_eq!(frames, 100);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn repair_from_reader() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flv_from_reader_{}", std::process::id()));
+        // 读取大小小于 tag 头时也要能拼出完整的 tag
+        for buffer_size in [7, 8 * 1024] {
+            let mut connection = FlvConnection::from_reader(std::io::Cursor::new(synthetic_stream()))
+                .with_buffer_size(buffer_size);
+            connection.read_frame(9).await?;
+            let file_name = dir.join(format!("record_{buffer_size}"));
+            let file = LifecycleFile::new(file_name.to_str().unwrap(), "flv", None);
+            parse_flv(connection, file, Segmentable::new(None, None), RepairPipeline::standard(), None, &AtomicBool::new(false)).await?;
+
+            let file = std::fs::read(file_name.with_extension("flv"))?;
+            assert_eq!(&file[..], &synthetic_stream()[..]);
+        }
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    /// 读完数据后连接被重置
+    struct Reset;
+
+    impl tokio::io::AsyncRead for Reset {
+        fn poll_read(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            _: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Err(std::io::ErrorKind::ConnectionReset.into()))
+        }
+    }
+
+    #[tokio::test]
+    async fn drop_mid_stream_is_an_error() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flv_drop_{}", std::process::id()));
+        let stream = synthetic_stream();
+        // 最后一个音频 tag 共 18 字节，断在它的中间
+        let cut = stream.len() - 5;
+        for (name, reset) in [("eof", false), ("reset", true)] {
+            let reader = std::io::Cursor::new(stream[..cut].to_vec());
+            let mut connection = if reset {
+                FlvConnection::from_reader(tokio::io::AsyncReadExt::chain(reader, Reset))
+            } else {
+                FlvConnection::from_reader(reader)
+            };
+            connection.read_frame(9).await?;
+            let file_name = dir.join(name);
+            let file = LifecycleFile::new(file_name.to_str().unwrap(), "flv", None);
+            let result = parse_flv(connection, file, Segmentable::new(None, None), RepairPipeline::standard(), None, &AtomicBool::new(false)).await;
+            let expected = if reset { std::io::ErrorKind::ConnectionReset } else { std::io::ErrorKind::UnexpectedEof };
+            assert!(matches!(result, Err(FlvError::Io(e)) if e.kind() == expected));
+
+            // 完整的 tag 都已写入
+            let file = std::fs::read(file_name.with_extension("flv"))?;
+            assert_eq!(&file[..], &stream[..stream.len() - 18]);
+        }
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unknown_fourcc_is_an_error() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flv_fourcc_{}", std::process::id()));
+        let mut stream = synthetic_stream();
+        // E-RTMP 扩展头的关键帧 SequenceStart，FourCC 未知
+        stream.extend(flv_tag(9, 5000, b"\x90xxxx\x01\x02"));
+        stream.extend(flv_tag(9, 5100, &[0x27, 0x01, 0, 0, 0, 0xaa]));
+        let mut connection = FlvConnection::from_reader(std::io::Cursor::new(stream.clone()));
+        connection.read_frame(9).await?;
+        let file_name = dir.join("record");
+        let file = LifecycleFile::new(file_name.to_str().unwrap(), "flv", None);
+        let result = parse_flv(connection, file, Segmentable::new(None, None), RepairPipeline::standard(), None, &AtomicBool::new(false)).await;
+        assert!(matches!(result, Err(FlvError::InvalidData(_))));
+
+        // 出错之前的 tag 都已写入
+        let file = std::fs::read(file_name.with_extension("flv"))?;
+        assert_eq!(&file[..], &synthetic_stream()[..]);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn truncated_packet_header_is_an_error() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flv_short_packet_{}", std::process::id()));
+        // 只有 SoundFormat 的 AAC tag，以及 composition time 不完整的 AVC tag
+        for (name, tag_type, body) in [("aac", 8, &[0xaf][..]), ("avc", 9, &[0x27, 0x01, 0][..])] {
+            let mut stream = synthetic_stream();
+            stream.extend(flv_tag(tag_type, 5000, body));
+            let mut connection = FlvConnection::from_reader(std::io::Cursor::new(stream));
+            connection.read_frame(9).await?;
+            let file_name = dir.join(name);
+            let file = LifecycleFile::new(file_name.to_str().unwrap(), "flv", None);
+            let result = parse_flv(connection, file, Segmentable::new(None, None), RepairPipeline::standard(), None, &AtomicBool::new(false)).await;
+            assert!(matches!(result, Err(FlvError::NomIncomplete(..))), "{name}: {result:?}");
+            let file = std::fs::read(file_name.with_extension("flv"))?;
+            assert_eq!(&file[..], &synthetic_stream()[..]);
+        }
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drop_repeating_frames_when_enabled() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flv_repeating_{}", std::process::id()));
+        let mut stream = synthetic_stream();
+        // 卡住的编码器以不变的时间戳重复推送同一帧；静音帧内容相同但时间戳前进
+        let repeated = flv_tag(8, 4900, &[0xaf, 0x01, 49]);
+        let silence = |timestamp| flv_tag(8, timestamp, &[0xaf, 0x01, 0x21, 0x10, 0x04, 0x60]);
+        for timestamp in [5000, 5100, 5200] {
+            stream.extend(video_frame(timestamp));
+            stream.extend(&repeated);
+            stream.extend(silence(timestamp));
+        }
+        for (name, remove_repeating) in [("standard", false), ("remove_repeating", true)] {
+            let mut pipeline = RepairPipeline::standard();
+            if remove_repeating {
+                pipeline = pipeline.add_rule(Box::new(RemoveRepeatingDataRule::default()));
+            }
+            let mut connection = FlvConnection::from_reader(std::io::Cursor::new(stream.clone()));
+            connection.read_frame(9).await?;
+            let file_name = dir.join(name);
+            let file = LifecycleFile::new(file_name.to_str().unwrap(), "flv", None);
+            parse_flv(connection, file, Segmentable::new(None, None), pipeline, None, &AtomicBool::new(false)).await?;
+
+            // 默认不去重；启用后只丢弃时间戳没有前进的重复帧
+            let mut expected = synthetic_stream();
+            for timestamp in [5000, 5100, 5200] {
+                expected.extend(video_frame(timestamp));
+                if !remove_repeating {
+                    expected.extend(&repeated);
+                }
+                expected.extend(silence(timestamp));
+            }
+            let file = std::fs::read(file_name.with_extension("flv"))?;
+            assert_eq!(&file[..], &expected[..], "{name}");
+        }
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_timeout_on_stalled_source() {
+        let (_writer, reader) = tokio::io::duplex(64);
+        let mut connection = FlvConnection::from_reader(reader).with_read_timeout(Some(Duration::from_millis(20)));
+        let result = connection.read_frame(9).await;
+        assert!(matches!(result, Err(FlvError::ReadTimeout(_))));
+    }
+
+    #[tokio::test]
+    async fn no_limit_keeps_one_file() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flv_no_split_{}", std::process::id()));
+        let response = reqwest::Response::from(http::Response::new(synthetic_stream()));
+        let mut connection = FlvConnection::new(response);
+        connection.read_frame(9).await?;
+        let file_name = dir.join("record");
+        let file = LifecycleFile::new(file_name.to_str().unwrap(), "flv", None);
+        parse_flv(connection, file, Segmentable::new(None, None), RepairPipeline::standard(), None, &AtomicBool::new(false)).await?;
+
+        let file = std::fs::read(dir.join("record.flv"))?;
+        assert_eq!(tag_types(&file).len(), 103);
+        assert!(!dir.join("record_1.flv").exists());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancel_closes_file() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flv_cancel_{}", std::process::id()));
+        let response = reqwest::Response::from(http::Response::new(synthetic_stream()));
+        let mut connection = FlvConnection::new(response);
+        connection.read_frame(9).await?;
+        let file_name = dir.join("record");
+        let file = LifecycleFile::new(file_name.to_str().unwrap(), "flv", None);
+        let result = parse_flv(connection, file, Segmentable::new(None, None), RepairPipeline::standard(), None, &AtomicBool::new(true)).await;
+        assert!(matches!(result, Err(FlvError::Cancelled)));
+
+        // 没有读取任何 tag，只剩文件头和第一个 PreviousTagSize，且 .part 已重命名
+        let file = std::fs::read(dir.join("record.flv"))?;
+        assert_eq!(file.len(), 13);
+        assert!(!dir.join("record.flv.part").exists());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
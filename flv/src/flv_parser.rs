@@ -1,15 +1,18 @@
 // source: https://github.com/rust-av/flavors/blob/master/src/parser.rs
-use nom::bits::bits;
-use nom::bits::streaming::take;
-use nom::bytes::streaming::tag;
+use bytes::Bytes;
+use nom::bytes::streaming::{tag, take as take_bytes};
 use nom::combinator::{flat_map, map, map_res};
 use nom::error::{Error, ErrorKind};
 use nom::multi::{length_data, many0, many_m_n};
 use nom::number::streaming::{be_f64, be_i16, be_i24, be_u16, be_u24, be_u32, be_u8};
-use nom::sequence::{pair, terminated, tuple};
+use nom::sequence::{pair, preceded, terminated, tuple};
 use nom::{Err, IResult, Needed};
 use serde::Serialize;
+use std::borrow::Cow;
+use std::fmt;
 use std::str::from_utf8;
+use std::time::SystemTime;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 pub struct Header {
@@ -19,6 +22,16 @@ pub struct Header {
     pub offset: u32,
 }
 
+impl Header {
+    pub fn has_audio(&self) -> bool {
+        self.audio
+    }
+
+    pub fn has_video(&self) -> bool {
+        self.video
+    }
+}
+
 pub fn header(input: &[u8]) -> IResult<&[u8], Header> {
     map(
         tuple((tag("FLV"), be_u8, be_u8, be_u32)),
@@ -38,6 +51,36 @@ pub enum TagType {
     Script = 18,
 }
 
+impl TryFrom<u8> for TagType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            8 => Ok(TagType::Audio),
+            9 => Ok(TagType::Video),
+            18 => Ok(TagType::Script),
+            _ => Err(value),
+        }
+    }
+}
+
+impl From<TagType> for u8 {
+    fn from(value: TagType) -> Self {
+        value as u8
+    }
+}
+
+impl fmt::Display for TagType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TagType::Audio => "audio",
+            TagType::Video => "video",
+            TagType::Script => "script",
+        };
+        f.write_str(name)
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
 pub struct TagHeader {
     pub tag_type: TagType,
@@ -59,14 +102,93 @@ pub struct Tag<'a> {
     pub data: TagData<'a>,
 }
 
+/// 不借用输入的 tag，可以跨越 `.await` 传递
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedTag {
+    pub header: TagHeader,
+    pub data: OwnedTagData,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OwnedTagData {
+    Audio(OwnedAudioData),
+    Video(OwnedVideoData),
+    Script,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedAudioData {
+    pub sound_format: SoundFormat,
+    pub sound_rate: SoundRate,
+    pub sound_size: SoundSize,
+    pub sound_type: SoundType,
+    pub ex_packet_type: Option<ExAudioPacketType>,
+    pub sound_data: Bytes,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedVideoData {
+    pub frame_type: FrameType,
+    pub codec_id: CodecId,
+    pub ex_packet_type: Option<ExVideoPacketType>,
+    pub video_data: Bytes,
+}
+
+impl Tag<'_> {
+    pub fn to_owned(&self) -> OwnedTag {
+        let data = match &self.data {
+            TagData::Audio(audio) => OwnedTagData::Audio(OwnedAudioData {
+                sound_format: audio.sound_format,
+                sound_rate: audio.sound_rate,
+                sound_size: audio.sound_size,
+                sound_type: audio.sound_type,
+                ex_packet_type: audio.ex_packet_type,
+                sound_data: Bytes::copy_from_slice(audio.sound_data),
+            }),
+            TagData::Video(video) => OwnedTagData::Video(OwnedVideoData {
+                frame_type: video.frame_type,
+                codec_id: video.codec_id,
+                ex_packet_type: video.ex_packet_type,
+                video_data: Bytes::copy_from_slice(video.video_data),
+            }),
+            TagData::Script => OwnedTagData::Script,
+        };
+        OwnedTag {
+            header: self.header,
+            data,
+        }
+    }
+}
+
+impl OwnedTag {
+    pub fn as_tag(&self) -> Tag<'_> {
+        let data = match &self.data {
+            OwnedTagData::Audio(audio) => TagData::Audio(AudioData {
+                sound_format: audio.sound_format,
+                sound_rate: audio.sound_rate,
+                sound_size: audio.sound_size,
+                sound_type: audio.sound_type,
+                ex_packet_type: audio.ex_packet_type,
+                sound_data: &audio.sound_data,
+            }),
+            OwnedTagData::Video(video) => TagData::Video(VideoData {
+                frame_type: video.frame_type,
+                codec_id: video.codec_id,
+                ex_packet_type: video.ex_packet_type,
+                video_data: &video.video_data,
+            }),
+            OwnedTagData::Script => TagData::Script,
+        };
+        Tag {
+            header: self.header,
+            data,
+        }
+    }
+}
+
 fn tag_type(input: &[u8]) -> IResult<&[u8], TagType> {
     map_res(be_u8, |tag_type| {
-        Ok(match tag_type {
-            8 => TagType::Audio,
-            9 => TagType::Video,
-            18 => TagType::Script,
-            _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-        })
+        TagType::try_from(tag_type).map_err(|_| Err::Error(Error::new(input, ErrorKind::Alt)))
     })(input)
 }
 
@@ -82,6 +204,19 @@ pub fn tag_header(input: &[u8]) -> IResult<&[u8], TagHeader> {
     )(input)
 }
 
+impl TagHeader {
+    /// 与 `tag_header` 相反：低 24 位写入 Timestamp，高 8 位写入 TimestampExtended
+    pub fn marshal(&self) -> [u8; 11] {
+        let mut bytes = [0u8; 11];
+        bytes[0] = self.tag_type.into();
+        bytes[1..4].copy_from_slice(&self.data_size.to_be_bytes()[1..]);
+        bytes[4..7].copy_from_slice(&self.timestamp.to_be_bytes()[1..]);
+        bytes[7] = (self.timestamp >> 24) as u8;
+        bytes[8..11].copy_from_slice(&self.stream_id.to_be_bytes()[1..]);
+        bytes
+    }
+}
+
 pub fn complete_tag(input: &[u8]) -> IResult<&[u8], Tag> {
     flat_map(pair(tag_type, be_u24), |(tag_type, data_size)| {
         map(
@@ -112,6 +247,8 @@ pub fn tag_data(tag_type: TagType, size: usize) -> impl Fn(&[u8]) -> IResult<&[u
     }
 }
 
+/// tag 头中 4 位的 SoundFormat，与字节值的转换见 `TryFrom<u8>`；
+/// 9 保留未用，12 保留，13 并非标准值而是部分服务器给 Opus 用的
 #[allow(non_camel_case_types)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
 pub enum SoundFormat {
@@ -126,6 +263,7 @@ pub enum SoundFormat {
     PCM_ULAW,
     AAC,
     SPEEX,
+    OPUS, // 非标准的 13，或 E-RTMP 的 Opus
     MP3_8KHZ,
     DEVICE_SPECIFIC,
 }
@@ -173,6 +311,20 @@ pub fn aac_audio_packet_header(input: &[u8]) -> IResult<&[u8], AACAudioPacketHea
     })(input)
 }
 
+/// 按 tag 声明的 `size` 切出包体，缓冲区不足时返回 `Incomplete` 而不是越界
+fn packet_body(input: &[u8], size: usize, minimum: usize) -> IResult<&[u8], &[u8]> {
+    if size < minimum {
+        return Err(Err::Incomplete(Needed::new(minimum - size)));
+    }
+
+    if input.len() < size {
+        return Err(Err::Incomplete(Needed::new(size - input.len())));
+    }
+
+    let (body, rest) = input.split_at(size);
+    Ok((rest, body))
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct AACAudioPacket<'a> {
     pub packet_type: AACPacketType,
@@ -180,96 +332,99 @@ pub struct AACAudioPacket<'a> {
 }
 
 pub fn aac_audio_packet(input: &[u8], size: usize) -> IResult<&[u8], AACAudioPacket> {
-    if input.len() < size {
-        return Err(Err::Incomplete(Needed::new(size)));
-    }
-
-    if size < 1 {
-        return Err(Err::Incomplete(Needed::new(1)));
-    }
-
-    be_u8(input).and_then(|(_, packet_type)| {
+    let (rest, body) = packet_body(input, size, 1)?;
+    be_u8(body).and_then(|(data, packet_type)| {
         Ok((
-            &input[size..],
+            rest,
             AACAudioPacket {
                 packet_type: match packet_type {
                     0 => AACPacketType::SequenceHeader,
                     1 => AACPacketType::Raw,
                     _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
                 },
-                aac_data: &input[1..size],
+                aac_data: data,
             },
         ))
     })
 }
 
+/// AAC 序列头中的 AudioSpecificConfig（ISO 14496-3 1.6.2.1）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct AudioSpecificConfig {
+    pub audio_object_type: u8,
+    pub sampling_frequency: u32,
+    pub channel_configuration: u8,
+}
+
+pub(crate) const AAC_SAMPLING_FREQUENCIES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// 解析 `AACPacketType::SequenceHeader` 的 `aac_data`，
+/// FLV 中 AAC 的 `SoundRate` 固定为 44KHz，实际参数要从这里获取
+pub fn parse_audio_specific_config(data: &[u8]) -> crate::error::Result<AudioSpecificConfig> {
+    let invalid = || crate::error::FlvError::InvalidData("AudioSpecificConfig".to_string());
+    let mut position = 0;
+    let mut read_bits = |count: usize| -> crate::error::Result<u32> {
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = data.get(position / 8).ok_or_else(invalid)?;
+            value = (value << 1) | u32::from(byte >> (7 - position % 8) & 1);
+            position += 1;
+        }
+        Ok(value)
+    };
+
+    let mut audio_object_type = read_bits(5)?;
+    if audio_object_type == 31 {
+        audio_object_type = 32 + read_bits(6)?;
+    }
+    let sampling_frequency = match read_bits(4)? {
+        0x0F => read_bits(24)?,
+        index => *AAC_SAMPLING_FREQUENCIES.get(index as usize).ok_or_else(invalid)?,
+    };
+    let channel_configuration = read_bits(4)?;
+    Ok(AudioSpecificConfig {
+        audio_object_type: audio_object_type as u8,
+        sampling_frequency,
+        channel_configuration: channel_configuration as u8,
+    })
+}
+
+/// E-RTMP 扩展音频头（SoundFormat 为 9）中的包类型
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ExAudioPacketType {
+    SequenceStart,
+    CodedFrames,
+    SequenceEnd,
+    MultichannelConfig,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct AudioData<'a> {
     pub sound_format: SoundFormat,
     pub sound_rate: SoundRate,
     pub sound_size: SoundSize,
     pub sound_type: SoundType,
+    /// 仅 E-RTMP 扩展头有，此时 `sound_data` 不包含 FourCC
+    pub ex_packet_type: Option<ExAudioPacketType>,
     pub sound_data: &'a [u8],
 }
 
 pub fn audio_data(input: &[u8], size: usize) -> IResult<&[u8], AudioData> {
-    if input.len() < size {
-        return Err(Err::Incomplete(Needed::new(size)));
-    }
-
-    if size < 1 {
-        return Err(Err::Incomplete(Needed::new(1)));
-    }
-
-    let take_bits = tuple((take(4usize), take(2usize), take(1usize), take(1usize)));
-    bits::<_, _, Error<_>, _, _>(take_bits)(input).and_then(
-        |(_, (sformat, srate, ssize, stype))| {
-            let sformat = match sformat {
-                0 => SoundFormat::PCM_NE,
-                1 => SoundFormat::ADPCM,
-                2 => SoundFormat::MP3,
-                3 => SoundFormat::PCM_LE,
-                4 => SoundFormat::NELLYMOSER_16KHZ_MONO,
-                5 => SoundFormat::NELLYMOSER_8KHZ_MONO,
-                6 => SoundFormat::NELLYMOSER,
-                7 => SoundFormat::PCM_ALAW,
-                8 => SoundFormat::PCM_ULAW,
-                10 => SoundFormat::AAC,
-                11 => SoundFormat::SPEEX,
-                14 => SoundFormat::MP3_8KHZ,
-                15 => SoundFormat::DEVICE_SPECIFIC,
-                _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-            };
-            let srate = match srate {
-                0 => SoundRate::_5_5KHZ,
-                1 => SoundRate::_11KHZ,
-                2 => SoundRate::_22KHZ,
-                3 => SoundRate::_44KHZ,
-                _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-            };
-            let ssize = match ssize {
-                0 => SoundSize::Snd8bit,
-                1 => SoundSize::Snd16bit,
-                _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-            };
-            let stype = match stype {
-                0 => SoundType::SndMono,
-                1 => SoundType::SndStereo,
-                _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-            };
-
-            Ok((
-                &input[size..],
-                AudioData {
-                    sound_format: sformat,
-                    sound_rate: srate,
-                    sound_size: ssize,
-                    sound_type: stype,
-                    sound_data: &input[1..size],
-                },
-            ))
+    let (remaining, body) = packet_body(input, size, 1)?;
+    let (rest, header) = audio_data_header(body)?;
+    Ok((
+        remaining,
+        AudioData {
+            sound_format: header.sound_format,
+            sound_rate: header.sound_rate,
+            sound_size: header.sound_size,
+            sound_type: header.sound_type,
+            ex_packet_type: header.ex_packet_type,
+            sound_data: rest,
         },
-    )
+    ))
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -278,59 +433,192 @@ pub struct AudioDataHeader {
     pub sound_rate: SoundRate,
     pub sound_size: SoundSize,
     pub sound_type: SoundType,
+    pub ex_packet_type: Option<ExAudioPacketType>,
+}
+
+const AUDIO_EX_HEADER: u8 = 9;
+
+fn sound_format(value: u8) -> Option<SoundFormat> {
+    Some(match value {
+        0 => SoundFormat::PCM_NE,
+        1 => SoundFormat::ADPCM,
+        2 => SoundFormat::MP3,
+        3 => SoundFormat::PCM_LE,
+        4 => SoundFormat::NELLYMOSER_16KHZ_MONO,
+        5 => SoundFormat::NELLYMOSER_8KHZ_MONO,
+        6 => SoundFormat::NELLYMOSER,
+        7 => SoundFormat::PCM_ALAW,
+        8 => SoundFormat::PCM_ULAW,
+        10 => SoundFormat::AAC,
+        11 => SoundFormat::SPEEX,
+        13 => SoundFormat::OPUS,
+        14 => SoundFormat::MP3_8KHZ,
+        15 => SoundFormat::DEVICE_SPECIFIC,
+        _ => return None,
+    })
+}
+
+fn sound_format_id(sound_format: SoundFormat) -> u8 {
+    match sound_format {
+        SoundFormat::PCM_NE => 0,
+        SoundFormat::ADPCM => 1,
+        SoundFormat::MP3 => 2,
+        SoundFormat::PCM_LE => 3,
+        SoundFormat::NELLYMOSER_16KHZ_MONO => 4,
+        SoundFormat::NELLYMOSER_8KHZ_MONO => 5,
+        SoundFormat::NELLYMOSER => 6,
+        SoundFormat::PCM_ALAW => 7,
+        SoundFormat::PCM_ULAW => 8,
+        SoundFormat::AAC => 10,
+        SoundFormat::SPEEX => 11,
+        SoundFormat::OPUS => 13,
+        SoundFormat::MP3_8KHZ => 14,
+        SoundFormat::DEVICE_SPECIFIC => 15,
+    }
+}
+
+impl TryFrom<u8> for SoundFormat {
+    type Error = crate::error::FlvError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        sound_format(value).ok_or_else(|| crate::error::FlvError::InvalidData(format!("sound format {value}")))
+    }
+}
+
+impl From<SoundFormat> for u8 {
+    fn from(value: SoundFormat) -> Self {
+        sound_format_id(value)
+    }
+}
+
+fn audio_fourcc(sound_format: SoundFormat) -> Option<&'static [u8; 4]> {
+    Some(match sound_format {
+        SoundFormat::OPUS => b"Opus",
+        SoundFormat::AAC => b"mp4a",
+        SoundFormat::MP3 => b".mp3",
+        _ => return None,
+    })
+}
+
+fn ex_audio_packet_type(value: u8) -> Option<ExAudioPacketType> {
+    Some(match value {
+        0 => ExAudioPacketType::SequenceStart,
+        1 => ExAudioPacketType::CodedFrames,
+        2 => ExAudioPacketType::SequenceEnd,
+        4 => ExAudioPacketType::MultichannelConfig,
+        _ => return None,
+    })
+}
+
+fn ex_audio_packet_type_id(packet_type: ExAudioPacketType) -> u8 {
+    match packet_type {
+        ExAudioPacketType::SequenceStart => 0,
+        ExAudioPacketType::CodedFrames => 1,
+        ExAudioPacketType::SequenceEnd => 2,
+        ExAudioPacketType::MultichannelConfig => 4,
+    }
 }
 
+/// SoundFormat 为 9 时是 E-RTMP 扩展头：低 4 位为 packet type，之后是 4 字节 FourCC。
+/// 扩展头没有采样率等字段，固定为 44KHz、16bit、立体声，实际参数在序列头中
 pub fn audio_data_header(input: &[u8]) -> IResult<&[u8], AudioDataHeader> {
-    if input.is_empty() {
-        return Err(Err::Incomplete(Needed::new(1)));
-    }
-
-    let take_bits = tuple((take(4usize), take(2usize), take(1usize), take(1usize)));
-    map_res(
-        bits::<_, _, Error<_>, _, _>(take_bits),
-        |(sformat, srate, ssize, stype)| {
-            let sformat = match sformat {
-                0 => SoundFormat::PCM_NE,
-                1 => SoundFormat::ADPCM,
-                2 => SoundFormat::MP3,
-                3 => SoundFormat::PCM_LE,
-                4 => SoundFormat::NELLYMOSER_16KHZ_MONO,
-                5 => SoundFormat::NELLYMOSER_8KHZ_MONO,
-                6 => SoundFormat::NELLYMOSER,
-                7 => SoundFormat::PCM_ALAW,
-                8 => SoundFormat::PCM_ULAW,
-                10 => SoundFormat::AAC,
-                11 => SoundFormat::SPEEX,
-                14 => SoundFormat::MP3_8KHZ,
-                15 => SoundFormat::DEVICE_SPECIFIC,
-                _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-            };
-            let srate = match srate {
-                0 => SoundRate::_5_5KHZ,
-                1 => SoundRate::_11KHZ,
-                2 => SoundRate::_22KHZ,
-                3 => SoundRate::_44KHZ,
-                _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-            };
-            let ssize = match ssize {
-                0 => SoundSize::Snd8bit,
-                1 => SoundSize::Snd16bit,
-                _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-            };
-            let stype = match stype {
-                0 => SoundType::SndMono,
-                1 => SoundType::SndStereo,
-                _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-            };
+    let error = || Err::Error(Error::new(input, ErrorKind::Alt));
+    let (rest, first) = be_u8(input)?;
+    if first >> 4 != AUDIO_EX_HEADER {
+        let sound_rate = match first >> 2 & 0x03 {
+            0 => SoundRate::_5_5KHZ,
+            1 => SoundRate::_11KHZ,
+            2 => SoundRate::_22KHZ,
+            _ => SoundRate::_44KHZ,
+        };
+        return Ok((
+            rest,
+            AudioDataHeader {
+                sound_format: sound_format(first >> 4).ok_or_else(error)?,
+                sound_rate,
+                sound_size: if first & 0x02 == 0 { SoundSize::Snd8bit } else { SoundSize::Snd16bit },
+                sound_type: if first & 0x01 == 0 { SoundType::SndMono } else { SoundType::SndStereo },
+                ex_packet_type: None,
+            },
+        ));
+    }
 
-            Ok(AudioDataHeader {
-                sound_format: sformat,
-                sound_rate: srate,
-                sound_size: ssize,
-                sound_type: stype,
-            })
+    let (rest, fourcc) = take_bytes(4usize)(rest)?;
+    let sound_format = [SoundFormat::OPUS, SoundFormat::AAC, SoundFormat::MP3]
+        .into_iter()
+        .find(|format| audio_fourcc(*format).is_some_and(|f| f.as_slice() == fourcc))
+        .ok_or_else(error)?;
+    Ok((
+        rest,
+        AudioDataHeader {
+            sound_format,
+            sound_rate: SoundRate::_44KHZ,
+            sound_size: SoundSize::Snd16bit,
+            sound_type: SoundType::SndStereo,
+            ex_packet_type: Some(ex_audio_packet_type(first & 0x0f).ok_or_else(error)?),
         },
-    )(input)
+    ))
+}
+
+impl AudioDataHeader {
+    /// 与 `audio_data_header` 相反，写回 tag body 开头的字节
+    pub fn marshal(&self) -> Vec<u8> {
+        if let Some(packet_type) = self.ex_packet_type {
+            let mut bytes = vec![AUDIO_EX_HEADER << 4 | ex_audio_packet_type_id(packet_type)];
+            bytes.extend_from_slice(audio_fourcc(self.sound_format).unwrap_or(b"Opus"));
+            return bytes;
+        }
+        let sound_rate = match self.sound_rate {
+            SoundRate::_5_5KHZ => 0,
+            SoundRate::_11KHZ => 1,
+            SoundRate::_22KHZ => 2,
+            SoundRate::_44KHZ => 3,
+        };
+        let sound_size = match self.sound_size {
+            SoundSize::Snd8bit => 0,
+            SoundSize::Snd16bit => 1,
+        };
+        let sound_type = match self.sound_type {
+            SoundType::SndMono => 0,
+            SoundType::SndStereo => 1,
+        };
+        vec![sound_format_id(self.sound_format) << 4 | sound_rate << 2 | sound_size << 1 | sound_type]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum AudioChannelOrder {
+    Unspecified,
+    /// 按声道位置的位掩码
+    Native(u32),
+    /// 每个声道对应的位置
+    Custom(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AudioMultichannelConfig {
+    pub channel_count: u8,
+    pub channel_order: AudioChannelOrder,
+}
+
+/// 解析 `ExAudioPacketType::MultichannelConfig` 的 `sound_data`
+pub fn audio_multichannel_config(input: &[u8]) -> IResult<&[u8], AudioMultichannelConfig> {
+    let (rest, (order, channel_count)) = pair(be_u8, be_u8)(input)?;
+    let (rest, channel_order) = match order {
+        0 => (rest, AudioChannelOrder::Unspecified),
+        1 => map(be_u32, AudioChannelOrder::Native)(rest)?,
+        2 => map(take_bytes(channel_count as usize), |mapping: &[u8]| {
+            AudioChannelOrder::Custom(mapping.to_vec())
+        })(rest)?,
+        _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
+    };
+    Ok((
+        rest,
+        AudioMultichannelConfig {
+            channel_count,
+            channel_order,
+        },
+    ))
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
@@ -342,6 +630,8 @@ pub enum FrameType {
     Command,
 }
 
+/// 视频编码，传统头中为 4 位的 CodecID（见 `TryFrom<u8>`），扩展头中为 FourCC（见 `fourcc`）。
+/// 标准只定义到 7（AVC），12 是国内 CDN 约定的 HEVC；AV1 与 VP9 没有数值，只能用 FourCC 表示
 #[allow(non_camel_case_types)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
 pub enum CodecId {
@@ -355,6 +645,20 @@ pub enum CodecId {
     // Not in FLV standard
     H263,
     MPEG4Part2, // MPEG-4 Part 2
+    HEVC,       // 国内 CDN 常用的 codec id 12，或 E-RTMP 的 hvc1
+    AV1,        // E-RTMP av01
+    VP9,        // E-RTMP vp09
+}
+
+/// E-RTMP 扩展视频头（`isExHeader`）中的包类型
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ExVideoPacketType {
+    SequenceStart,
+    CodedFrames,
+    SequenceEnd,
+    CodedFramesX, // 没有 composition time
+    Metadata,
+    MPEG2TSSequenceStart,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
@@ -391,6 +695,20 @@ pub fn avc_video_packet_header(input: &[u8]) -> IResult<&[u8], AVCVideoPacketHea
     )(input)
 }
 
+impl AVCVideoPacketHeader {
+    /// 与 `avc_video_packet_header` 相反，composition time 写为 24 位有符号数
+    pub fn marshal(&self) -> Vec<u8> {
+        let packet_type = match self.packet_type {
+            AVCPacketType::SequenceHeader => 0,
+            AVCPacketType::NALU => 1,
+            AVCPacketType::EndOfSequence => 2,
+        };
+        let mut bytes = vec![packet_type];
+        bytes.extend_from_slice(&self.composition_time.to_be_bytes()[1..]);
+        bytes
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct AVCVideoPacket<'a> {
     pub packet_type: AVCPacketType,
@@ -399,20 +717,14 @@ pub struct AVCVideoPacket<'a> {
 }
 
 pub fn avc_video_packet(input: &[u8], size: usize) -> IResult<&[u8], AVCVideoPacket> {
-    if input.len() < size {
-        return Err(Err::Incomplete(Needed::new(size)));
-    }
-
-    if size < 4 {
-        return Err(Err::Incomplete(Needed::new(4)));
-    }
-    pair(packet_type, be_i24)(input).map(|(_, (packet_type, composition_time))| {
+    let (rest, body) = packet_body(input, size, 4)?;
+    pair(packet_type, be_i24)(body).map(|(data, (packet_type, composition_time))| {
         (
-            &input[size..],
+            rest,
             AVCVideoPacket {
                 packet_type,
                 composition_time,
-                avc_data: &input[4..size],
+                avc_data: data,
             },
         )
     })
@@ -422,94 +734,187 @@ pub fn avc_video_packet(input: &[u8], size: usize) -> IResult<&[u8], AVCVideoPac
 pub struct VideoData<'a> {
     pub frame_type: FrameType,
     pub codec_id: CodecId,
+    /// 仅 E-RTMP 扩展头有，此时 `video_data` 不包含 FourCC
+    pub ex_packet_type: Option<ExVideoPacketType>,
     pub video_data: &'a [u8],
 }
 
 pub fn video_data(input: &[u8], size: usize) -> IResult<&[u8], VideoData> {
-    if input.len() < size {
-        return Err(Err::Incomplete(Needed::new(size)));
+    let (remaining, body) = packet_body(input, size, 1)?;
+    let (rest, header) = video_data_header(body)?;
+    Ok((
+        remaining,
+        VideoData {
+            frame_type: header.frame_type,
+            codec_id: header.codec_id,
+            ex_packet_type: header.ex_packet_type,
+            video_data: rest,
+        },
+    ))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VideoDataHeader {
+    pub frame_type: FrameType,
+    pub codec_id: CodecId,
+    pub ex_packet_type: Option<ExVideoPacketType>,
+}
+
+fn frame_type(value: u8) -> Option<FrameType> {
+    Some(match value {
+        1 => FrameType::Key,
+        2 => FrameType::Inter,
+        3 => FrameType::DisposableInter,
+        4 => FrameType::Generated,
+        5 => FrameType::Command,
+        _ => return None,
+    })
+}
+
+fn codec_id(value: u8) -> Option<CodecId> {
+    Some(match value {
+        1 => CodecId::JPEG,
+        2 => CodecId::SORENSON_H263,
+        3 => CodecId::SCREEN,
+        4 => CodecId::VP6,
+        5 => CodecId::VP6A,
+        6 => CodecId::SCREEN2,
+        7 => CodecId::H264,
+        8 => CodecId::H263,
+        9 => CodecId::MPEG4Part2,
+        12 => CodecId::HEVC,
+        _ => return None,
+    })
+}
+
+fn fourcc_codec_id(fourcc: &[u8]) -> Option<CodecId> {
+    Some(match fourcc {
+        b"avc1" => CodecId::H264,
+        b"hvc1" => CodecId::HEVC,
+        b"av01" => CodecId::AV1,
+        b"vp09" => CodecId::VP9,
+        _ => return None,
+    })
+}
+
+/// 传统头中 HEVC 使用的非标准 CodecID
+pub const HEVC_CODEC_ID: u8 = 12;
+
+impl TryFrom<u8> for CodecId {
+    type Error = crate::error::FlvError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        codec_id(value).ok_or_else(|| crate::error::FlvError::InvalidData(format!("codec id {value}")))
     }
+}
 
-    if size < 1 {
-        return Err(Err::Incomplete(Needed::new(1)));
+impl TryFrom<CodecId> for u8 {
+    type Error = crate::error::FlvError;
+
+    /// AV1 与 VP9 没有对应的 CodecID
+    fn try_from(value: CodecId) -> Result<Self, Self::Error> {
+        Ok(match value {
+            CodecId::JPEG => 1,
+            CodecId::SORENSON_H263 => 2,
+            CodecId::SCREEN => 3,
+            CodecId::VP6 => 4,
+            CodecId::VP6A => 5,
+            CodecId::SCREEN2 => 6,
+            CodecId::H264 => 7,
+            CodecId::H263 => 8,
+            CodecId::MPEG4Part2 => 9,
+            CodecId::HEVC => HEVC_CODEC_ID,
+            CodecId::AV1 | CodecId::VP9 => {
+                return Err(crate::error::FlvError::InvalidData(format!("codec id for {value:?}")))
+            }
+        })
     }
+}
 
-    let take_bits = pair(take(4usize), take(4usize));
-    bits::<_, _, Error<_>, _, _>(take_bits)(input).and_then(|(_, (frame_type, codec_id))| {
-        let frame_type = match frame_type {
-            1 => FrameType::Key,
-            2 => FrameType::Inter,
-            3 => FrameType::DisposableInter,
-            4 => FrameType::Generated,
-            5 => FrameType::Command,
-            _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-        };
-        let codec_id = match codec_id {
-            1 => CodecId::JPEG,
-            2 => CodecId::SORENSON_H263,
-            3 => CodecId::SCREEN,
-            4 => CodecId::VP6,
-            5 => CodecId::VP6A,
-            6 => CodecId::SCREEN2,
-            7 => CodecId::H264,
-            8 => CodecId::H263,
-            9 => CodecId::MPEG4Part2,
-            _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-        };
+impl CodecId {
+    /// E-RTMP 扩展头中使用的 FourCC，只有 AVC、HEVC、AV1、VP9 有定义
+    pub fn fourcc(&self) -> Option<&'static [u8; 4]> {
+        Some(match self {
+            CodecId::H264 => b"avc1",
+            CodecId::HEVC => b"hvc1",
+            CodecId::AV1 => b"av01",
+            CodecId::VP9 => b"vp09",
+            _ => return None,
+        })
+    }
 
-        Ok((
-            &input[size..],
-            VideoData {
-                frame_type,
-                codec_id,
-                video_data: &input[1..size],
-            },
-        ))
-    })
+    pub fn from_fourcc(fourcc: &[u8]) -> Option<CodecId> {
+        fourcc_codec_id(fourcc)
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct VideoDataHeader {
-    pub frame_type: FrameType,
-    pub codec_id: CodecId,
+fn ex_video_packet_type(value: u8) -> Option<ExVideoPacketType> {
+    Some(match value {
+        0 => ExVideoPacketType::SequenceStart,
+        1 => ExVideoPacketType::CodedFrames,
+        2 => ExVideoPacketType::SequenceEnd,
+        3 => ExVideoPacketType::CodedFramesX,
+        4 => ExVideoPacketType::Metadata,
+        5 => ExVideoPacketType::MPEG2TSSequenceStart,
+        _ => return None,
+    })
 }
 
+/// 第一个字节最高位为 1 时是 E-RTMP 扩展头：
+/// 3 位 frame type，4 位 packet type，之后是 4 字节 FourCC
 pub fn video_data_header(input: &[u8]) -> IResult<&[u8], VideoDataHeader> {
-    if input.is_empty() {
-        return Err(Err::Incomplete(Needed::new(1)));
-    }
-
-    let take_bits = pair(take(4usize), take(4usize));
-    map_res(
-        bits::<_, _, Error<_>, _, _>(take_bits),
-        |(frame_type, codec_id)| {
-            let frame_type = match frame_type {
-                1 => FrameType::Key,
-                2 => FrameType::Inter,
-                3 => FrameType::DisposableInter,
-                4 => FrameType::Generated,
-                5 => FrameType::Command,
-                _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-            };
-            let codec_id = match codec_id {
-                1 => CodecId::JPEG,
-                2 => CodecId::SORENSON_H263,
-                3 => CodecId::SCREEN,
-                4 => CodecId::VP6,
-                5 => CodecId::VP6A,
-                6 => CodecId::SCREEN2,
-                7 => CodecId::H264,
-                8 => CodecId::H263,
-                9 => CodecId::MPEG4Part2,
-                _ => return Err(Err::Error(Error::new(input, ErrorKind::Alt))),
-            };
+    let error = || Err::Error(Error::new(input, ErrorKind::Alt));
+    let (rest, first) = be_u8(input)?;
+    if first & 0x80 == 0 {
+        return Ok((
+            rest,
+            VideoDataHeader {
+                frame_type: frame_type(first >> 4).ok_or_else(error)?,
+                codec_id: codec_id(first & 0x0f).ok_or_else(error)?,
+                ex_packet_type: None,
+            },
+        ));
+    }
 
-            Ok(VideoDataHeader {
-                frame_type,
-                codec_id,
-            })
+    let (rest, fourcc) = take_bytes(4usize)(rest)?;
+    Ok((
+        rest,
+        VideoDataHeader {
+            frame_type: frame_type(first >> 4 & 0x07).ok_or_else(error)?,
+            codec_id: fourcc_codec_id(fourcc).ok_or_else(error)?,
+            ex_packet_type: Some(ex_video_packet_type(first & 0x0f).ok_or_else(error)?),
         },
-    )(input)
+    ))
+}
+
+impl VideoDataHeader {
+    /// 与 `video_data_header` 相反，写回 tag body 开头的字节
+    pub fn marshal(&self) -> Vec<u8> {
+        let frame_type = match self.frame_type {
+            FrameType::Key => 1,
+            FrameType::Inter => 2,
+            FrameType::DisposableInter => 3,
+            FrameType::Generated => 4,
+            FrameType::Command => 5,
+        };
+        if let Some(packet_type) = self.ex_packet_type {
+            let packet_type = match packet_type {
+                ExVideoPacketType::SequenceStart => 0,
+                ExVideoPacketType::CodedFrames => 1,
+                ExVideoPacketType::SequenceEnd => 2,
+                ExVideoPacketType::CodedFramesX => 3,
+                ExVideoPacketType::Metadata => 4,
+                ExVideoPacketType::MPEG2TSSequenceStart => 5,
+            };
+            let fourcc = self.codec_id.fourcc().unwrap_or(b"avc1");
+            let mut bytes = vec![0x80 | frame_type << 4 | packet_type];
+            bytes.extend_from_slice(fourcc);
+            return bytes;
+        }
+        // AV1 与 VP9 只能用扩展头表示
+        let codec_id = u8::try_from(self.codec_id).unwrap_or(HEVC_CODEC_ID);
+        vec![frame_type << 4 | codec_id]
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize)]
@@ -522,16 +927,22 @@ pub struct ScriptData<'a> {
 pub enum ScriptDataValue<'a> {
     Number(f64),
     Boolean(bool),
-    String(&'a str),
+    String(Cow<'a, str>),
     Object(Vec<ScriptDataObject<'a>>),
     MovieClip(&'a str),
     Null,
     Undefined,
+    /// 对象引用，保存被引用对象的序号，不做解析
     Reference(u16),
     ECMAArray(Vec<ScriptDataObject<'a>>),
     StrictArray(Vec<ScriptDataValue<'a>>),
     Date(ScriptDataDate),
-    LongString(&'a str),
+    LongString(Cow<'a, str>),
+    XmlDocument(&'a str),
+    TypedObject {
+        class_name: &'a str,
+        objects: Vec<ScriptDataObject<'a>>,
+    },
 }
 
 #[derive(Debug, PartialEq, Serialize)]
@@ -546,46 +957,365 @@ pub struct ScriptDataDate {
     pub local_date_time_offset: i16, // SI16
 }
 
+/// AMF0 Date 的取值范围，与 ECMAScript 相同，为 1970 年前后各 1e8 天的毫秒数
+pub const MAX_DATE_MILLIS: f64 = 8.64e15;
+
+impl ScriptDataDate {
+    /// UTC 时间，超出 `MAX_DATE_MILLIS` 时返回错误
+    pub fn from_system_time(time: SystemTime) -> crate::error::Result<Self> {
+        let millis = match time.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(duration) => duration.as_millis() as f64,
+            Err(e) => -(e.duration().as_millis() as f64),
+        };
+        let date = Self { date_time: millis, local_date_time_offset: 0 };
+        date.validate()?;
+        Ok(date)
+    }
+
+    /// NaN、无穷大和超出范围的毫秒数都无法被播放器还原为日期
+    pub fn validate(&self) -> crate::error::Result<()> {
+        if self.date_time.is_finite() && self.date_time.abs() <= MAX_DATE_MILLIS {
+            Ok(())
+        } else {
+            Err(crate::error::FlvError::InvalidData(format!("date {}", self.date_time)))
+        }
+    }
+}
+
+impl ScriptData<'_> {
+    /// 与 `script_data` 相反，写出完整的 script tag body
+    pub fn marshal(&self, buf: &mut Vec<u8>) -> crate::error::Result<()> {
+        buf.extend_from_slice(script_data_name_tag);
+        marshal_string(self.name, buf)?;
+        self.arguments.marshal(buf)
+    }
+
+    pub async fn write_to<W: AsyncWrite + Unpin + Send>(
+        &self,
+        w: &mut W,
+    ) -> crate::error::Result<()> {
+        let mut buf = Vec::new();
+        self.marshal(&mut buf)?;
+        w.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+impl<'a> ScriptDataValue<'a> {
+    /// 按 AMF0 编码追加到 `buf`，超过 u16 长度的 String 写为 LongString，超出范围的 Date 返回错误
+    pub fn marshal(&self, buf: &mut Vec<u8>) -> crate::error::Result<()> {
+        match self {
+            ScriptDataValue::Number(n) => {
+                buf.push(0);
+                buf.extend_from_slice(&n.to_be_bytes());
+            }
+            ScriptDataValue::Boolean(b) => buf.extend_from_slice(&[1, *b as u8]),
+            ScriptDataValue::String(s) if s.len() > u16::MAX as usize => {
+                buf.push(12);
+                marshal_long_string(s, buf)?;
+            }
+            ScriptDataValue::String(s) => {
+                buf.push(2);
+                marshal_string(s, buf)?;
+            }
+            ScriptDataValue::Object(objects) => {
+                buf.push(3);
+                marshal_objects(objects, buf)?;
+            }
+            ScriptDataValue::MovieClip(s) => {
+                buf.push(4);
+                marshal_string(s, buf)?;
+            }
+            ScriptDataValue::Null => buf.push(5),
+            ScriptDataValue::Undefined => buf.push(6),
+            ScriptDataValue::Reference(index) => {
+                buf.push(7);
+                buf.extend_from_slice(&index.to_be_bytes());
+            }
+            ScriptDataValue::ECMAArray(objects) => {
+                buf.push(8);
+                buf.extend_from_slice(&marshal_len::<u32>(objects.len())?.to_be_bytes());
+                marshal_objects(objects, buf)?;
+            }
+            ScriptDataValue::StrictArray(values) => {
+                buf.push(10);
+                buf.extend_from_slice(&marshal_len::<u32>(values.len())?.to_be_bytes());
+                for value in values {
+                    value.marshal(buf)?;
+                }
+            }
+            ScriptDataValue::Date(date) => {
+                date.validate()?;
+                buf.push(11);
+                buf.extend_from_slice(&date.date_time.to_be_bytes());
+                buf.extend_from_slice(&date.local_date_time_offset.to_be_bytes());
+            }
+            ScriptDataValue::LongString(s) => {
+                buf.push(12);
+                marshal_long_string(s, buf)?;
+            }
+            ScriptDataValue::XmlDocument(s) => {
+                buf.push(15);
+                marshal_long_string(s, buf)?;
+            }
+            ScriptDataValue::TypedObject {
+                class_name,
+                objects,
+            } => {
+                buf.push(16);
+                marshal_string(class_name, buf)?;
+                marshal_objects(objects, buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn write_to<W: AsyncWrite + Unpin + Send>(
+        &self,
+        w: &mut W,
+    ) -> crate::error::Result<()> {
+        let mut buf = Vec::new();
+        self.marshal(&mut buf)?;
+        w.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+fn marshal_len<T: TryFrom<usize>>(len: usize) -> crate::error::Result<T> {
+    T::try_from(len).map_err(|_| crate::error::FlvError::TooLarge(len))
+}
+
+fn marshal_string(s: &str, buf: &mut Vec<u8>) -> crate::error::Result<()> {
+    buf.extend_from_slice(&marshal_len::<u16>(s.len())?.to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn marshal_long_string(s: &str, buf: &mut Vec<u8>) -> crate::error::Result<()> {
+    buf.extend_from_slice(&marshal_len::<u32>(s.len())?.to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn marshal_objects(objects: &[ScriptDataObject], buf: &mut Vec<u8>) -> crate::error::Result<()> {
+    for object in objects {
+        marshal_string(object.name, buf)?;
+        object.data.marshal(buf)?;
+    }
+    buf.extend_from_slice(script_data_object_end_terminator);
+    Ok(())
+}
+
 #[allow(non_upper_case_globals)]
 static script_data_name_tag: &[u8] = &[2];
 
 pub fn script_data(input: &[u8]) -> IResult<&[u8], ScriptData> {
+    script_data_with(input, false)
+}
+
+fn script_data_with(input: &[u8], lossy: bool) -> IResult<&[u8], ScriptData<'_>> {
     // Must start with a string, i.e. 2
     map(
         tuple((
             tag(script_data_name_tag),
             script_data_string,
-            script_data_value,
+            |i| script_data_value_at(i, 0, lossy),
         )),
         |(_, name, arguments)| ScriptData { name, arguments },
     )(input)
 }
 
+/// `script_data_value` 允许的最大嵌套深度，超过时返回 `ErrorKind::TooLarge`
+pub const MAX_SCRIPT_DATA_DEPTH: usize = 32;
+
+/// 解析来自直播流的 script tag 时的限制，防止恶意数据导致栈溢出或大量分配
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScriptDataLimits {
+    pub max_depth: usize,
+    pub max_elements: usize,
+    /// String/LongString 不是合法 UTF-8（如 GBK 编码的标题）时用 U+FFFD 替换而不是报错
+    pub lossy: bool,
+}
+
+impl Default for ScriptDataLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: MAX_SCRIPT_DATA_DEPTH,
+            max_elements: 65536,
+            lossy: false,
+        }
+    }
+}
+
+/// 先检查嵌套深度与元素数量，再解析 script tag
+pub fn parse_script_data<'a>(
+    input: &'a [u8],
+    limits: &ScriptDataLimits,
+) -> crate::error::Result<ScriptData<'a>> {
+    check_script_data_limits(input, limits)?;
+    script_data_with(input, limits.lossy)
+        .map(|(_, data)| data)
+        .map_err(|_| crate::error::FlvError::InvalidData("script data".to_string()))
+}
+
+enum ScriptDataFrame {
+    Object,
+    StrictArray(u32),
+}
+
+fn take_checked<'a>(rest: &mut &'a [u8], length: usize) -> crate::error::Result<&'a [u8]> {
+    if rest.len() < length {
+        return Err(crate::error::FlvError::InvalidData("script data".to_string()));
+    }
+    let (bytes, remaining) = rest.split_at(length);
+    *rest = remaining;
+    Ok(bytes)
+}
+
+fn take_length(rest: &mut &[u8], size: usize) -> crate::error::Result<usize> {
+    let bytes = take_checked(rest, size)?;
+    Ok(bytes.iter().fold(0, |acc, byte| acc << 8 | *byte as usize))
+}
+
+/// 不递归地遍历 AMF0 数据，只检查结构
+fn check_script_data_limits(input: &[u8], limits: &ScriptDataLimits) -> crate::error::Result<()> {
+    let mut rest = input;
+    let mut stack: Vec<ScriptDataFrame> = Vec::new();
+    let mut elements = 0usize;
+    loop {
+        match stack.last_mut() {
+            Some(ScriptDataFrame::Object) => {
+                let length = take_length(&mut rest, 2)?;
+                if length == 0 && rest.first() == Some(&9) {
+                    take_checked(&mut rest, 1)?;
+                    stack.pop();
+                    continue;
+                }
+                take_checked(&mut rest, length)?;
+            }
+            Some(ScriptDataFrame::StrictArray(0)) => {
+                stack.pop();
+                continue;
+            }
+            Some(ScriptDataFrame::StrictArray(remaining)) => *remaining -= 1,
+            None if rest.is_empty() => return Ok(()),
+            None => {}
+        }
+        elements += 1;
+        if elements > limits.max_elements {
+            return Err(crate::error::FlvError::TooLarge(limits.max_elements));
+        }
+        let skip = match take_checked(&mut rest, 1)?[0] {
+            0 => 8,
+            1 => 1,
+            2 | 4 => take_length(&mut rest, 2)?,
+            3 => {
+                stack.push(ScriptDataFrame::Object);
+                0
+            }
+            5 | 6 => 0,
+            7 => 2,
+            8 => {
+                stack.push(ScriptDataFrame::Object);
+                4
+            }
+            10 => {
+                let count = take_length(&mut rest, 4)?;
+                if count > limits.max_elements {
+                    return Err(crate::error::FlvError::TooLarge(limits.max_elements));
+                }
+                stack.push(ScriptDataFrame::StrictArray(count as u32));
+                0
+            }
+            11 => 10,
+            12 | 15 => take_length(&mut rest, 4)?,
+            16 => {
+                let length = take_length(&mut rest, 2)?;
+                take_checked(&mut rest, length)?;
+                stack.push(ScriptDataFrame::Object);
+                0
+            }
+            _ => return Err(crate::error::FlvError::InvalidData("script data".to_string())),
+        };
+        take_checked(&mut rest, skip)?;
+        if stack.len() > limits.max_depth {
+            return Err(crate::error::FlvError::DepthLimitExceeded(limits.max_depth));
+        }
+    }
+}
+
 pub fn script_data_value(input: &[u8]) -> IResult<&[u8], ScriptDataValue> {
+    script_data_value_at(input, 0, false)
+}
+
+fn script_data_value_at(input: &[u8], depth: usize, lossy: bool) -> IResult<&[u8], ScriptDataValue> {
+    if depth > MAX_SCRIPT_DATA_DEPTH {
+        return Err(Err::Failure(Error::new(input, ErrorKind::TooLarge)));
+    }
+    let depth = depth + 1;
     be_u8(input).and_then(|v| match v {
         (i, 0) => map(be_f64, ScriptDataValue::Number)(i),
         (i, 1) => map(be_u8, |n| ScriptDataValue::Boolean(n != 0))(i),
-        (i, 2) => map(script_data_string, ScriptDataValue::String)(i),
-        (i, 3) => map(script_data_objects, ScriptDataValue::Object)(i),
+        (i, 2) => map(|i| script_data_text(i, be_u16_len, lossy), ScriptDataValue::String)(i),
+        (i, 3) => map(|i| script_data_objects_at(i, depth, lossy), ScriptDataValue::Object)(i),
         (i, 4) => map(script_data_string, ScriptDataValue::MovieClip)(i),
-        (i, 5) => Ok((i, ScriptDataValue::Null)), // to remove
-        (i, 6) => Ok((i, ScriptDataValue::Undefined)), // to remove
+        (i, 5) => Ok((i, ScriptDataValue::Null)),
+        (i, 6) => Ok((i, ScriptDataValue::Undefined)),
         (i, 7) => map(be_u16, ScriptDataValue::Reference)(i),
-        (i, 8) => map(script_data_ecma_array, ScriptDataValue::ECMAArray)(i),
-        (i, 10) => map(script_data_strict_array, ScriptDataValue::StrictArray)(i),
+        (i, 8) => map(
+            preceded(be_u32, |i| script_data_objects_at(i, depth, lossy)),
+            ScriptDataValue::ECMAArray,
+        )(i),
+        (i, 10) => map(
+            flat_map(be_u32, |o| {
+                many_m_n(1, o as usize, move |i| script_data_value_at(i, depth, lossy))
+            }),
+            ScriptDataValue::StrictArray,
+        )(i),
         (i, 11) => map(script_data_date, ScriptDataValue::Date)(i),
-        (i, 12) => map(script_data_long_string, ScriptDataValue::LongString)(i),
+        (i, 12) => map(|i| script_data_text(i, be_u32_len, lossy), ScriptDataValue::LongString)(i),
+        (i, 15) => map(script_data_long_string, ScriptDataValue::XmlDocument)(i),
+        (i, 16) => map(
+            pair(script_data_string, |i| script_data_objects_at(i, depth, lossy)),
+            |(class_name, objects)| ScriptDataValue::TypedObject {
+                class_name,
+                objects,
+            },
+        )(i),
         _ => Err(Err::Error(Error::new(input, ErrorKind::Alt))),
     })
 }
 
+/// 读取到输入结束为止的所有值，如 script tag 中的名称、元数据以及其后的值，
+/// 输入恰好结束时停止，值不完整时返回错误
+pub fn script_data_values(mut input: &[u8]) -> IResult<&[u8], Vec<ScriptDataValue>> {
+    let mut values = Vec::new();
+    while !input.is_empty() {
+        let (rest, value) = script_data_value(input)?;
+        values.push(value);
+        input = rest;
+    }
+    Ok((input, values))
+}
+
 pub fn script_data_objects(input: &[u8]) -> IResult<&[u8], Vec<ScriptDataObject>> {
-    terminated(many0(script_data_object), script_data_object_end)(input)
+    script_data_objects_at(input, 0, false)
+}
+
+fn script_data_objects_at(input: &[u8], depth: usize, lossy: bool) -> IResult<&[u8], Vec<ScriptDataObject>> {
+    terminated(
+        many0(|i| script_data_object_at(i, depth, lossy)),
+        script_data_object_end,
+    )(input)
 }
 
 pub fn script_data_object(input: &[u8]) -> IResult<&[u8], ScriptDataObject> {
+    script_data_object_at(input, 0, false)
+}
+
+fn script_data_object_at(input: &[u8], depth: usize, lossy: bool) -> IResult<&[u8], ScriptDataObject> {
     map(
-        pair(script_data_string, script_data_value),
+        pair(script_data_string, |i| script_data_value_at(i, depth, lossy)),
         |(name, data)| ScriptDataObject { name, data },
     )(input)
 }
@@ -605,6 +1335,28 @@ pub fn script_data_long_string(input: &[u8]) -> IResult<&[u8], &str> {
     map_res(length_data(be_u32), from_utf8)(input)
 }
 
+fn be_u16_len(input: &[u8]) -> IResult<&[u8], usize> {
+    map(be_u16, usize::from)(input)
+}
+
+fn be_u32_len(input: &[u8]) -> IResult<&[u8], usize> {
+    map(be_u32, |len| len as usize)(input)
+}
+
+/// 字符串值，`lossy` 时不合法的 UTF-8 会被替换而不是返回错误
+fn script_data_text(
+    input: &[u8],
+    length: fn(&[u8]) -> IResult<&[u8], usize>,
+    lossy: bool,
+) -> IResult<&[u8], Cow<'_, str>> {
+    let (rest, bytes) = length_data(length)(input)?;
+    match from_utf8(bytes) {
+        Ok(text) => Ok((rest, Cow::Borrowed(text))),
+        Err(_) if lossy => Ok((rest, String::from_utf8_lossy(bytes))),
+        Err(_) => Err(Err::Error(Error::new(input, ErrorKind::MapRes))),
+    }
+}
+
 pub fn script_data_date(input: &[u8]) -> IResult<&[u8], ScriptDataDate> {
     map(
         pair(be_f64, be_i16),
@@ -624,3 +1376,472 @@ pub fn script_data_ecma_array(input: &[u8]) -> IResult<&[u8], Vec<ScriptDataObje
 pub fn script_data_strict_array(input: &[u8]) -> IResult<&[u8], Vec<ScriptDataValue>> {
     flat_map(be_u32, |o| many_m_n(1, o as usize, script_data_value))(input)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        aac_audio_packet, audio_data, audio_data_header, audio_multichannel_config,
+        avc_video_packet, avc_video_packet_header, complete_tag, parse_audio_specific_config,
+        parse_script_data, script_data_value, script_data_values, tag_header, video_data,
+        video_data_header, AVCPacketType, AudioChannelOrder, CodecId, ExAudioPacketType,
+        ExVideoPacketType, FrameType, OwnedTagData, ScriptDataDate, ScriptDataObject, ScriptDataValue,
+        ScriptDataLimits, SoundFormat, TagHeader, TagType, MAX_DATE_MILLIS, MAX_SCRIPT_DATA_DEPTH,
+    };
+    use std::time::{Duration, SystemTime};
+    use crate::error::FlvError;
+    use nom::error::ErrorKind;
+    use nom::{Err, Needed};
+
+    #[test]
+    fn audio_specific_config() {
+        // AAC-LC 48000Hz 双声道
+        let config = parse_audio_specific_config(&[0x11, 0x90]).unwrap();
+        assert_eq!(config.audio_object_type, 2);
+        assert_eq!(config.sampling_frequency, 48000);
+        assert_eq!(config.channel_configuration, 2);
+
+        // 0x0F 之后是 24 位的采样率
+        let config = parse_audio_specific_config(&[0x17, 0x80, 0x5D, 0xC0, 0x10]).unwrap();
+        assert_eq!(config.audio_object_type, 2);
+        assert_eq!(config.sampling_frequency, 48000);
+        assert_eq!(config.channel_configuration, 2);
+
+        assert!(parse_audio_specific_config(&[0x11]).is_err());
+    }
+
+    #[test]
+    fn owned_tag_round_trip() {
+        // 视频关键帧 AVC NALU
+        let owned = {
+            let bytes = vec![9, 0, 0, 5, 0, 0, 10, 0, 0, 0, 0, 0x17, 1, 0, 0, 0];
+            complete_tag(&bytes).unwrap().1.to_owned()
+        };
+        let OwnedTagData::Video(video) = &owned.data else {
+            panic!("expected video data");
+        };
+        assert_eq!(video.frame_type, FrameType::Key);
+        assert_eq!(video.codec_id, CodecId::H264);
+        assert_eq!(&video.video_data[..], &[1, 0, 0, 0]);
+        assert_eq!(owned.as_tag().to_owned(), owned);
+    }
+
+    #[test]
+    fn enhanced_video_header() {
+        // isExHeader | 关键帧 | SequenceStart, av01
+        let (rest, header) = video_data_header(&[0x90, b'a', b'v', b'0', b'1', 0x81]).unwrap();
+        assert_eq!(header.frame_type, FrameType::Key);
+        assert_eq!(header.codec_id, CodecId::AV1);
+        assert_eq!(header.ex_packet_type, Some(ExVideoPacketType::SequenceStart));
+        assert_eq!(rest, &[0x81]);
+
+        let (_, video) = video_data(&[0xa3, b'v', b'p', b'0', b'9', 1, 2], 7).unwrap();
+        assert_eq!(video.frame_type, FrameType::Inter);
+        assert_eq!(video.codec_id, CodecId::VP9);
+        assert_eq!(video.ex_packet_type, Some(ExVideoPacketType::CodedFramesX));
+        assert_eq!(video.video_data, &[1, 2]);
+
+        let (_, video) = video_data(&[0x17, 1], 2).unwrap();
+        assert_eq!(video.codec_id, CodecId::H264);
+        assert_eq!(video.ex_packet_type, None);
+        assert!(video_data_header(&[0x90, b'x', b'x', b'x', b'x']).is_err());
+    }
+
+    #[test]
+    fn h264_keyframe_header_round_trip() {
+        let bytes = [0x17, 0x01, 0x00, 0x00, 0x28];
+        let (rest, header) = video_data_header(&bytes).unwrap();
+        assert_eq!(header.frame_type, FrameType::Key);
+        assert_eq!(header.codec_id, CodecId::H264);
+        let (_, packet_header) = avc_video_packet_header(rest).unwrap();
+        assert_eq!(packet_header.packet_type, AVCPacketType::NALU);
+        assert_eq!(packet_header.composition_time, 40);
+
+        let mut marshaled = header.marshal();
+        marshaled.extend(packet_header.marshal());
+        assert_eq!(marshaled, bytes);
+    }
+
+    #[test]
+    fn negative_composition_time() {
+        // -100 的 24 位补码
+        let bytes = [0x01, 0xff, 0xff, 0x9c];
+        let (_, packet_header) = avc_video_packet_header(&bytes).unwrap();
+        assert_eq!(packet_header.composition_time, -100);
+        assert_eq!(packet_header.marshal(), bytes);
+
+        let (_, packet_header) = avc_video_packet_header(&[0x01, 0x80, 0x00, 0x00]).unwrap();
+        assert_eq!(packet_header.composition_time, -(1 << 23));
+    }
+
+    #[test]
+    fn opus_audio_header_round_trip() {
+        let bytes = [0x90, b'O', b'p', b'u', b's'];
+        let (rest, header) = audio_data_header(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(header.sound_format, SoundFormat::OPUS);
+        assert_eq!(header.ex_packet_type, Some(ExAudioPacketType::SequenceStart));
+        assert_eq!(header.marshal(), bytes);
+
+        // 非标准的 SoundFormat 13
+        let (_, header) = audio_data_header(&[0xdf]).unwrap();
+        assert_eq!(header.sound_format, SoundFormat::OPUS);
+        assert_eq!(header.marshal(), [0xdf]);
+
+        let (_, audio) = audio_data(&[0x94, b'O', b'p', b'u', b's', 2, 3, 0, 1, 2], 10).unwrap();
+        assert_eq!(audio.ex_packet_type, Some(ExAudioPacketType::MultichannelConfig));
+        let (_, config) = audio_multichannel_config(audio.sound_data).unwrap();
+        assert_eq!(config.channel_count, 3);
+        assert_eq!(config.channel_order, AudioChannelOrder::Custom(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn script_data_sequence() {
+        let mut bytes = vec![2, 0, 10];
+        bytes.extend(b"onMetaData");
+        bytes.extend([8, 0, 0, 0, 1, 0, 8]);
+        bytes.extend(b"duration");
+        bytes.push(0);
+        bytes.extend(10.0f64.to_be_bytes());
+        bytes.extend([0, 0, 9]);
+        bytes.extend([1, 1]);
+
+        let (rest, values) = script_data_values(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            values,
+            vec![
+                ScriptDataValue::String("onMetaData".into()),
+                ScriptDataValue::ECMAArray(vec![ScriptDataObject {
+                    name: "duration",
+                    data: ScriptDataValue::Number(10.0),
+                }]),
+                ScriptDataValue::Boolean(true),
+            ]
+        );
+        assert!(script_data_values(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn references_are_per_value() {
+        // 解析是无状态的，每个值里的引用都原样保留，不会受前一个值影响
+        let bytes = [3, 0, 1, b'a', 7, 0, 0, 0, 0, 9, 3, 0, 1, b'b', 7, 0, 0, 0, 0, 9];
+        let (_, values) = script_data_values(&bytes).unwrap();
+        assert_eq!(values.len(), 2);
+        for (value, name) in values.iter().zip(["a", "b"]) {
+            assert_eq!(
+                value,
+                &ScriptDataValue::Object(vec![ScriptDataObject {
+                    name,
+                    data: ScriptDataValue::Reference(0),
+                }])
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_packets() {
+        assert_eq!(aac_audio_packet(&[1], 10), Err(Err::Incomplete(Needed::new(9))));
+        assert_eq!(aac_audio_packet(&[1], 0), Err(Err::Incomplete(Needed::new(1))));
+        assert_eq!(avc_video_packet(&[1, 0, 0], 8), Err(Err::Incomplete(Needed::new(5))));
+        assert_eq!(avc_video_packet(&[1, 0, 0, 0], 2), Err(Err::Incomplete(Needed::new(2))));
+        assert!(matches!(audio_data(&[0xaf], 10), Err(Err::Incomplete(_))));
+        assert!(matches!(video_data(&[0x17], 10), Err(Err::Incomplete(_))));
+
+        let (rest, packet) = aac_audio_packet(&[1, 0x21, 0xff], 2).unwrap();
+        assert_eq!(rest, &[0xff]);
+        assert_eq!(packet.aac_data, &[0x21]);
+    }
+
+    #[tokio::test]
+    async fn script_data_write_to() {
+        let mut bytes = vec![8, 0, 0, 0, 2, 0, 8];
+        bytes.extend(b"duration");
+        bytes.push(0);
+        bytes.extend(10.0f64.to_be_bytes());
+        bytes.extend([0, 5]);
+        bytes.extend(b"flags");
+        bytes.extend([10, 0, 0, 0, 2, 1, 1, 5]);
+        bytes.extend([0, 0, 9]);
+
+        let (_, value) = script_data_value(&bytes).unwrap();
+        let mut written = Vec::new();
+        value.write_to(&mut written).await.unwrap();
+        assert_eq!(written, bytes);
+
+        let long = "a".repeat(u16::MAX as usize + 1);
+        let mut buf = Vec::new();
+        ScriptDataValue::String(long.as_str().into()).marshal(&mut buf).unwrap();
+        assert_eq!(&buf[..5], &[12, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn typed_object_and_xml_round_trip() {
+        let mut bytes = vec![10, 0, 0, 0, 2, 16, 0, 5];
+        bytes.extend(b"Point");
+        bytes.extend([0, 1, b'x', 0]);
+        bytes.extend(1.5f64.to_be_bytes());
+        bytes.extend([0, 0, 9, 15, 0, 0, 0, 7]);
+        bytes.extend(b"<a></a>");
+
+        let (rest, value) = script_data_value(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            value,
+            ScriptDataValue::StrictArray(vec![
+                ScriptDataValue::TypedObject {
+                    class_name: "Point",
+                    objects: vec![ScriptDataObject {
+                        name: "x",
+                        data: ScriptDataValue::Number(1.5),
+                    }],
+                },
+                ScriptDataValue::XmlDocument("<a></a>"),
+            ])
+        );
+
+        let mut encoded = Vec::new();
+        value.marshal(&mut encoded).unwrap();
+        assert_eq!(encoded, bytes);
+        assert_eq!(script_data_value(&encoded).unwrap().1, value);
+
+        let mut tag = vec![2, 0, 1, b'x'];
+        tag.extend(&bytes);
+        assert!(parse_script_data(&tag, &ScriptDataLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn object_with_many_keys_borrows_input() {
+        let mut bytes = vec![3];
+        for index in 0..100 {
+            let name = format!("key{index}");
+            bytes.extend((name.len() as u16).to_be_bytes());
+            bytes.extend(name.as_bytes());
+            bytes.push(0);
+            bytes.extend((index as f64).to_be_bytes());
+        }
+        bytes.extend([0, 0, 9]);
+
+        let (_, value) = script_data_value(&bytes).unwrap();
+        let ScriptDataValue::Object(objects) = value else {
+            panic!("expected an object");
+        };
+        assert_eq!(objects.len(), 100);
+        let range = bytes.as_ptr_range();
+        for (index, object) in objects.iter().enumerate() {
+            assert_eq!(object.name, format!("key{index}"));
+            assert_eq!(object.data, ScriptDataValue::Number(index as f64));
+            assert!(range.contains(&object.name.as_ptr()));
+        }
+    }
+
+    #[test]
+    fn ecma_array_keeps_duplicate_keys() {
+        let mut bytes = vec![8, 0, 0, 0, 3];
+        for (name, value) in [("width", 1280.0f64), ("height", 720.0), ("width", 1920.0)] {
+            bytes.extend((name.len() as u16).to_be_bytes());
+            bytes.extend(name.as_bytes());
+            bytes.push(0);
+            bytes.extend(value.to_be_bytes());
+        }
+        bytes.extend([0, 0, 9]);
+
+        let (_, value) = script_data_value(&bytes).unwrap();
+        let ScriptDataValue::ECMAArray(objects) = value else {
+            panic!("expected an ECMA array");
+        };
+        let pairs: Vec<_> = objects.iter().map(|o| (o.name, &o.data)).collect();
+        assert_eq!(
+            pairs,
+            [
+                ("width", &ScriptDataValue::Number(1280.0)),
+                ("height", &ScriptDataValue::Number(720.0)),
+                ("width", &ScriptDataValue::Number(1920.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn script_data_recursion_guard() {
+        let nested = |depth: usize| {
+            let mut bytes = Vec::new();
+            for _ in 0..depth {
+                bytes.extend([3, 0, 1, b'a']);
+            }
+            bytes.push(5);
+            for _ in 0..depth {
+                bytes.extend([0, 0, 9]);
+            }
+            bytes
+        };
+
+        let bytes = nested(MAX_SCRIPT_DATA_DEPTH);
+        assert!(script_data_value(&bytes).is_ok());
+
+        let bytes = nested(10_000);
+        assert!(matches!(
+            script_data_value(&bytes),
+            Err(Err::Failure(e)) if e.code == ErrorKind::TooLarge
+        ));
+    }
+
+    #[test]
+    fn script_data_limits() {
+        let limits = ScriptDataLimits {
+            max_depth: 4,
+            max_elements: 16,
+            lossy: false,
+        };
+        let mut bytes = vec![2, 0, 10];
+        bytes.extend(b"onMetaData");
+        bytes.extend([8, 0, 0, 0, 1, 0, 8]);
+        bytes.extend(b"duration");
+        bytes.push(0);
+        bytes.extend(10.0f64.to_be_bytes());
+        bytes.extend([0, 0, 9]);
+        assert_eq!(parse_script_data(&bytes, &limits).unwrap().name, "onMetaData");
+
+        // 嵌套的对象
+        let mut nested = vec![2, 0, 1, b'x'];
+        for _ in 0..8 {
+            nested.extend([3, 0, 1, b'a']);
+        }
+        nested.push(5);
+        for _ in 0..8 {
+            nested.extend([0, 0, 9]);
+        }
+        assert!(matches!(
+            parse_script_data(&nested, &limits),
+            Err(FlvError::DepthLimitExceeded(4))
+        ));
+
+        // 数量虚假的 strict array
+        let bogus = [2, 0, 1, b'x', 10, 0xff, 0xff, 0xff, 0xff];
+        assert!(matches!(parse_script_data(&bogus, &limits), Err(FlvError::TooLarge(16))));
+    }
+
+    #[test]
+    fn codec_and_sound_format_ids() {
+        let codecs = [
+            (1, CodecId::JPEG),
+            (2, CodecId::SORENSON_H263),
+            (3, CodecId::SCREEN),
+            (4, CodecId::VP6),
+            (5, CodecId::VP6A),
+            (6, CodecId::SCREEN2),
+            (7, CodecId::H264),
+            (8, CodecId::H263),
+            (9, CodecId::MPEG4Part2),
+            (12, CodecId::HEVC),
+        ];
+        for (id, codec) in codecs {
+            assert_eq!(CodecId::try_from(id).unwrap(), codec);
+            assert_eq!(u8::try_from(codec).unwrap(), id);
+        }
+        for id in [0, 10, 11, 13, 14, 15] {
+            assert!(CodecId::try_from(id).is_err());
+        }
+        assert!(u8::try_from(CodecId::AV1).is_err());
+        assert!(u8::try_from(CodecId::VP9).is_err());
+        for codec in [CodecId::H264, CodecId::HEVC, CodecId::AV1, CodecId::VP9] {
+            assert_eq!(CodecId::from_fourcc(codec.fourcc().unwrap()), Some(codec));
+        }
+        assert_eq!(CodecId::JPEG.fourcc(), None);
+
+        let formats = [
+            (0, SoundFormat::PCM_NE),
+            (1, SoundFormat::ADPCM),
+            (2, SoundFormat::MP3),
+            (3, SoundFormat::PCM_LE),
+            (4, SoundFormat::NELLYMOSER_16KHZ_MONO),
+            (5, SoundFormat::NELLYMOSER_8KHZ_MONO),
+            (6, SoundFormat::NELLYMOSER),
+            (7, SoundFormat::PCM_ALAW),
+            (8, SoundFormat::PCM_ULAW),
+            (10, SoundFormat::AAC),
+            (11, SoundFormat::SPEEX),
+            (13, SoundFormat::OPUS),
+            (14, SoundFormat::MP3_8KHZ),
+            (15, SoundFormat::DEVICE_SPECIFIC),
+        ];
+        for (id, format) in formats {
+            assert_eq!(SoundFormat::try_from(id).unwrap(), format);
+            assert_eq!(u8::from(format), id);
+        }
+        assert!(SoundFormat::try_from(9).is_err());
+        assert!(SoundFormat::try_from(12).is_err());
+    }
+
+    #[tokio::test]
+    async fn metadata_round_trip() {
+        let body = include_bytes!("../assets/onmetadata.bin");
+        let data = parse_script_data(body, &ScriptDataLimits::default()).unwrap();
+        assert_eq!(data.name, "onMetaData");
+        let ScriptDataValue::ECMAArray(objects) = &data.arguments else {
+            panic!("onMetaData is not an ECMA array");
+        };
+        assert_eq!(objects.len(), 17);
+        assert_eq!(objects[13].data, ScriptDataValue::String("【直播】测试标题".into()));
+        assert!(matches!(objects[14].data, ScriptDataValue::Object(_)));
+
+        let mut written = Vec::new();
+        data.write_to(&mut written).await.unwrap();
+        assert_eq!(written, body);
+    }
+
+    #[test]
+    fn lossy_script_strings() {
+        // GBK 编码的 "标题"
+        let mut bytes = vec![2, 0, 10];
+        bytes.extend(b"onMetaData");
+        bytes.extend([3, 0, 5]);
+        bytes.extend(b"title");
+        bytes.extend([2, 0, 4, 0xb1, 0xea, 0xcc, 0xe2]);
+        bytes.extend([0, 0, 9]);
+
+        assert!(parse_script_data(&bytes, &ScriptDataLimits::default()).is_err());
+        let limits = ScriptDataLimits { lossy: true, ..Default::default() };
+        let data = parse_script_data(&bytes, &limits).unwrap();
+        let ScriptDataValue::Object(objects) = data.arguments else {
+            panic!("arguments is not an object");
+        };
+        let ScriptDataValue::String(title) = &objects[0].data else {
+            panic!("title is not a string");
+        };
+        assert!(title.contains('\u{fffd}'));
+    }
+
+    #[test]
+    fn date_range() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let date = ScriptDataDate::from_system_time(time).unwrap();
+        assert_eq!(date.date_time, 1_700_000_000_123.0);
+        let before = ScriptDataDate::from_system_time(SystemTime::UNIX_EPOCH - Duration::from_secs(1)).unwrap();
+        assert_eq!(before.date_time, -1000.0);
+        let mut buf = Vec::new();
+        ScriptDataValue::Date(date.clone()).marshal(&mut buf).unwrap();
+        assert_eq!(script_data_value(&buf).unwrap().1, ScriptDataValue::Date(date));
+
+        let far = SystemTime::UNIX_EPOCH + Duration::from_millis(MAX_DATE_MILLIS as u64 + 1);
+        assert!(matches!(ScriptDataDate::from_system_time(far), Err(FlvError::InvalidData(_))));
+        for date_time in [f64::NAN, f64::INFINITY, -MAX_DATE_MILLIS * 2.0] {
+            let date = ScriptDataDate { date_time, local_date_time_offset: 0 };
+            assert!(ScriptDataValue::Date(date).marshal(&mut Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn extended_timestamp_round_trip() {
+        for timestamp in [0, 0x00ff_ffff, 0x0100_0000, 0x0100_0001, 0x7fff_ffff, 0xff00_0000, u32::MAX] {
+            let header = TagHeader {
+                tag_type: TagType::Video,
+                data_size: 5,
+                timestamp,
+                stream_id: 0,
+            };
+            let bytes = header.marshal();
+            assert_eq!(tag_header(&bytes).unwrap().1, header);
+        }
+        let bytes = TagHeader { tag_type: TagType::Audio, data_size: 2, timestamp: 0x0100_0000, stream_id: 0 }.marshal();
+        assert_eq!(bytes, [8, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0]);
+    }
+}
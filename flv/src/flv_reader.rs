@@ -0,0 +1,224 @@
+use std::io::ErrorKind;
+use std::time::Duration;
+use bytes::Bytes;
+use crate::error::{FlvError, Result};
+use crate::flv_parser::{header, tag_header, Header, TagHeader, TagType};
+use crate::flv_split::read_or_eof;
+use crate::flv_writer::FlvData;
+use tokio::io::{sink, AsyncRead, AsyncReadExt};
+
+/// 读取并校验 9 字节的 FLV 文件头，读取流中的 tag 之前先调用
+pub async fn read_flv_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Header> {
+    let mut bytes = [0u8; 9];
+    reader.read_exact(&mut bytes).await?;
+    let (_, header) = header(&bytes).map_err(|_| FlvError::InvalidData("flv header".to_string()))?;
+    Ok(header)
+}
+
+/// 按顺序读取 tag，末尾不完整的 tag 视为文件结束
+pub struct FlvTagReader<R> {
+    reader: R,
+    header: Header,
+}
+
+impl<R: AsyncRead + Unpin> FlvTagReader<R> {
+    /// 读取并校验文件头，跳过扩展字节和 PreviousTagSize0
+    pub async fn new(mut reader: R) -> Result<Self> {
+        let header = read_flv_header(&mut reader).await?;
+        skip(&mut reader, u64::from(header.offset.saturating_sub(9)) + 4).await?;
+        Ok(Self { reader, header })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// 下一个 tag 的头部和完整 body，需要按编码解析时用 `flv_parser::tag_data`
+    pub async fn next_tag(&mut self) -> Result<Option<(TagHeader, Bytes)>> {
+        let mut bytes = [0u8; 11];
+        if !read_or_eof(&mut self.reader, &mut bytes).await? {
+            return Ok(None);
+        }
+        let (_, tag_header) =
+            tag_header(&bytes).map_err(|_| FlvError::InvalidData("tag header".to_string()))?;
+        let mut body = vec![0u8; tag_header.data_size as usize + 4];
+        if !read_or_eof(&mut self.reader, &mut body).await? {
+            return Ok(None);
+        }
+        body.truncate(tag_header.data_size as usize);
+        Ok(Some((tag_header, Bytes::from(body))))
+    }
+
+    /// 只区分音频、视频和 script tag，`data` 为完整的 tag body，可以直接交给 `write_flv_data`
+    pub async fn next_flv_data(&mut self) -> Result<Option<FlvData>> {
+        let Some((tag_header, data)) = self.next_tag().await? else {
+            return Ok(None);
+        };
+        let timestamp = tag_header.timestamp;
+        Ok(Some(match tag_header.tag_type {
+            TagType::Audio => FlvData::Audio { timestamp, data },
+            TagType::Video => FlvData::Video { timestamp, data },
+            TagType::Script => FlvData::MetaData { timestamp, data },
+        }))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// 逐个跳过 tag，返回最大的时间戳加上最后一个 tag 的帧间隔，不缓存整个文件；
+/// 处理 32 位毫秒时间戳的回绕，末尾不完整的 tag 视为文件结束
+pub async fn flv_duration<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Duration> {
+    let header = read_flv_header(reader).await?;
+    // header 之后可能的扩展字节以及 PreviousTagSize0
+    if !skip(reader, u64::from(header.offset.saturating_sub(9)) + 4).await? {
+        return Ok(Duration::ZERO);
+    }
+    let mut timeline = Timeline::default();
+    let mut bytes = [0u8; 11];
+    loop {
+        match reader.read_exact(&mut bytes).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let (_, tag_header) =
+            tag_header(&bytes).map_err(|_| FlvError::InvalidData("tag header".to_string()))?;
+        timeline.push(tag_header.tag_type, tag_header.timestamp);
+        if !skip(reader, u64::from(tag_header.data_size) + 4).await? {
+            break;
+        }
+    }
+    Ok(Duration::from_millis(timeline.duration()))
+}
+
+/// 数据不足 `length` 字节时返回 `false`
+async fn skip<R: AsyncRead + Unpin>(reader: &mut R, length: u64) -> Result<bool> {
+    let skipped = tokio::io::copy(&mut reader.take(length), &mut sink()).await?;
+    Ok(skipped == length)
+}
+
+#[derive(Default)]
+struct Timeline {
+    wraps: u64,
+    last_raw: Option<u32>,
+    max: u64,
+    /// 音频、视频各自的上一个时间戳与帧间隔
+    audio: (Option<u64>, u64),
+    video: (Option<u64>, u64),
+    last_type: Option<TagType>,
+}
+
+impl Timeline {
+    fn push(&mut self, tag_type: TagType, timestamp: u32) {
+        if let Some(last) = self.last_raw {
+            // 倒退超过半个周期认为是回绕，而不是乱序
+            if timestamp < last && last - timestamp > u32::MAX / 2 {
+                self.wraps += 1;
+            }
+        }
+        self.last_raw = Some(timestamp);
+        let timestamp = (self.wraps << 32) + u64::from(timestamp);
+        self.max = self.max.max(timestamp);
+        let track = match tag_type {
+            TagType::Audio => &mut self.audio,
+            TagType::Video => &mut self.video,
+            TagType::Script => return,
+        };
+        if let Some(previous) = track.0 {
+            track.1 = timestamp.saturating_sub(previous);
+        }
+        track.0 = Some(timestamp);
+        self.last_type = Some(tag_type);
+    }
+
+    fn duration(&self) -> u64 {
+        let frame = match self.last_type {
+            Some(TagType::Audio) => self.audio.1,
+            Some(TagType::Video) => self.video.1,
+            _ => 0,
+        };
+        self.max + frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bytes::Bytes;
+    use super::{flv_duration, read_flv_header, FlvTagReader, Timeline};
+    use crate::flv_parser::TagType;
+    use crate::flv_writer::FlvData;
+
+    #[tokio::test]
+    async fn read_header() {
+        let mut reader: &[u8] = &[b'F', b'L', b'V', 1, 5, 0, 0, 0, 9, 0, 0, 0, 0];
+        let header = read_flv_header(&mut reader).await.unwrap();
+        assert!(header.has_audio());
+        assert!(header.has_video());
+        assert_eq!(reader.len(), 4);
+
+        let mut reader: &[u8] = &[b'F', b'L', b'X', 1, 5, 0, 0, 0, 9];
+        assert!(read_flv_header(&mut reader).await.is_err());
+        let mut reader: &[u8] = b"FLV";
+        assert!(read_flv_header(&mut reader).await.is_err());
+    }
+
+    fn tag(tag_type: u8, timestamp: u32, body: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![tag_type];
+        bytes.extend(&(body.len() as u32).to_be_bytes()[1..]);
+        bytes.extend(&timestamp.to_be_bytes()[1..]);
+        bytes.push((timestamp >> 24) as u8);
+        bytes.extend([0, 0, 0]);
+        bytes.extend(body);
+        bytes.extend((11 + body.len() as u32).to_be_bytes());
+        bytes
+    }
+
+    #[tokio::test]
+    async fn duration_from_last_tag() {
+        let mut file = vec![b'F', b'L', b'V', 1, 5, 0, 0, 0, 9, 0, 0, 0, 0];
+        file.extend(tag(18, 0, b"\x02"));
+        for i in 0..5u32 {
+            file.extend(tag(9, i * 40, &[0x17, 1, 0, 0, 0]));
+            file.extend(tag(8, i * 23, &[0xaf, 1]));
+        }
+        // 末尾不完整的 tag
+        file.extend(&tag(9, 200, &[0x27, 1, 0, 0, 0])[..8]);
+
+        let duration = flv_duration(&mut file.as_slice()).await.unwrap();
+        // 最后一个完整的 tag 是音频，时间戳 92ms，最大时间戳 160ms 加上 23ms 的帧间隔
+        assert_eq!(duration, Duration::from_millis(183));
+    }
+
+    #[tokio::test]
+    async fn read_flv_data() {
+        let mut file = vec![b'F', b'L', b'V', 1, 5, 0, 0, 0, 9, 0, 0, 0, 0];
+        file.extend(tag(18, 0, b"\x02"));
+        file.extend(tag(8, 0, &[0xaf, 1, 0x21]));
+        file.extend(tag(9, 40, &[0x27, 1, 0, 0, 0, 0xbb]));
+        file.extend(&tag(9, 80, &[0x27, 1, 0, 0, 0, 0xcc])[..20]);
+
+        let mut reader = FlvTagReader::new(file.as_slice()).await.unwrap();
+        assert!(reader.header().has_video());
+        let mut data = Vec::new();
+        while let Some(item) = reader.next_flv_data().await.unwrap() {
+            data.push(item);
+        }
+        assert_eq!(data, [
+            FlvData::MetaData { timestamp: 0, data: Bytes::from_static(b"\x02") },
+            FlvData::Audio { timestamp: 0, data: Bytes::from_static(&[0xaf, 1, 0x21]) },
+            FlvData::Video { timestamp: 40, data: Bytes::from_static(&[0x27, 1, 0, 0, 0, 0xbb]) },
+        ]);
+    }
+
+    #[test]
+    fn timestamp_wraparound() {
+        let mut timeline = Timeline::default();
+        timeline.push(TagType::Video, u32::MAX - 40);
+        timeline.push(TagType::Video, u32::MAX - 6);
+        timeline.push(TagType::Video, 26);
+        assert_eq!(timeline.duration(), (1u64 << 32) + 26 + 33);
+    }
+}
@@ -0,0 +1,238 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use bytes::Bytes;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tracing::info;
+use crate::error::{FlvError, Result};
+use crate::flv_parser::{
+    aac_audio_packet_header, avc_video_packet_header, tag_data, tag_header, AACPacketType,
+    AVCPacketType, CodecId, ExAudioPacketType, ExVideoPacketType, FrameType, SoundFormat, TagData,
+    TagHeader, TagType,
+};
+use crate::flv_reader::read_flv_header;
+use crate::flv_writer::RawFlvTag;
+
+/// `out_template` 中替换为分段序号（从 1 开始）的占位符
+pub const PART_PLACEHOLDER: &str = "{part}";
+
+/// 按时长切分已录制的 FLV：超过 `part_duration` 后在下一个关键帧处开始新文件，
+/// 每个分段重新写入文件头、onMetaData 和音视频 sequence header，时间戳从 0 开始
+pub async fn split_flv(
+    input: impl AsRef<Path>,
+    out_template: &str,
+    part_duration: Duration,
+) -> Result<Vec<PathBuf>> {
+    if !out_template.contains(PART_PLACEHOLDER) {
+        return Err(FlvError::InvalidData(format!("out template without {PART_PLACEHOLDER}")));
+    }
+    let mut reader = BufReader::new(File::open(input).await?);
+    let header = read_flv_header(&mut reader).await?;
+    let mut skipped = vec![0u8; header.offset.saturating_sub(9) as usize + 4];
+    reader.read_exact(&mut skipped).await?;
+    let flags = u8::from(header.has_audio()) << 2 | u8::from(header.has_video());
+
+    let part_duration = part_duration.as_millis() as u32;
+    let mut headers = DecodingHeaders::default();
+    let mut parts = Vec::new();
+    let mut out: Option<BufWriter<File>> = None;
+    let mut part_start = 0;
+    while let Some((tag_header, body)) = read_tag(&mut reader).await? {
+        let kind = classify(&tag_header, &body);
+        let timestamp = tag_header.timestamp;
+        let split = kind == TagKind::KeyFrame
+            && out.is_some()
+            && timestamp.saturating_sub(part_start) >= part_duration;
+        if out.is_none() || split {
+            if let Some(mut previous) = out.take() {
+                previous.flush().await?;
+            }
+            let path = PathBuf::from(out_template.replace(PART_PLACEHOLDER, &(parts.len() + 1).to_string()));
+            info!("create flv part {}", path.display());
+            let mut writer = BufWriter::new(File::create(&path).await?);
+            writer.write_all(&[b'F', b'L', b'V', 1, flags, 0, 0, 0, 9, 0, 0, 0, 0]).await?;
+            if split {
+                for (tag_type, body) in headers.iter() {
+                    write_tag(&mut writer, tag_type, 0, body).await?;
+                }
+            }
+            parts.push(path);
+            part_start = timestamp;
+            out = Some(writer);
+        }
+        headers.update(kind, &body);
+        let writer = out.as_mut().expect("part file is created above");
+        write_tag(writer, tag_header.tag_type, timestamp.saturating_sub(part_start), &body).await?;
+    }
+    if let Some(mut writer) = out {
+        writer.flush().await?;
+    }
+    Ok(parts)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TagKind {
+    MetaData,
+    AudioHeader,
+    VideoHeader,
+    KeyFrame,
+    Other,
+}
+
+/// 切分时需要在新文件开头重放的 tag
+#[derive(Default)]
+struct DecodingHeaders {
+    metadata: Option<Bytes>,
+    audio: Option<Bytes>,
+    video: Option<Bytes>,
+}
+
+impl DecodingHeaders {
+    fn update(&mut self, kind: TagKind, body: &Bytes) {
+        let slot = match kind {
+            // 只保留第一个 script tag，通常是 onMetaData
+            TagKind::MetaData if self.metadata.is_none() => &mut self.metadata,
+            TagKind::AudioHeader => &mut self.audio,
+            TagKind::VideoHeader => &mut self.video,
+            _ => return,
+        };
+        *slot = Some(body.clone());
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (TagType, &Bytes)> {
+        [
+            (TagType::Script, &self.metadata),
+            (TagType::Audio, &self.audio),
+            (TagType::Video, &self.video),
+        ]
+        .into_iter()
+        .filter_map(|(tag_type, body)| body.as_ref().map(|body| (tag_type, body)))
+    }
+}
+
+pub(crate) fn classify(tag_header: &TagHeader, body: &[u8]) -> TagKind {
+    match tag_data(tag_header.tag_type, body.len())(body) {
+        Ok((_, data)) => classify_data(&data),
+        Err(_) => TagKind::Other,
+    }
+}
+
+pub(crate) fn classify_data(data: &TagData) -> TagKind {
+    match data {
+        TagData::Script => TagKind::MetaData,
+        TagData::Audio(audio) => {
+            let sequence_header = match audio.ex_packet_type {
+                Some(packet_type) => packet_type == ExAudioPacketType::SequenceStart,
+                None => {
+                    audio.sound_format == SoundFormat::AAC
+                        && aac_audio_packet_header(audio.sound_data)
+                            .is_ok_and(|(_, header)| header.packet_type == AACPacketType::SequenceHeader)
+                }
+            };
+            if sequence_header { TagKind::AudioHeader } else { TagKind::Other }
+        }
+        TagData::Video(video) => {
+            let sequence_header = match video.ex_packet_type {
+                Some(packet_type) => packet_type == ExVideoPacketType::SequenceStart,
+                None => {
+                    matches!(video.codec_id, CodecId::H264 | CodecId::HEVC)
+                        && avc_video_packet_header(video.video_data)
+                            .is_ok_and(|(_, header)| header.packet_type == AVCPacketType::SequenceHeader)
+                }
+            };
+            if sequence_header {
+                TagKind::VideoHeader
+            } else if video.frame_type == FrameType::Key {
+                TagKind::KeyFrame
+            } else {
+                TagKind::Other
+            }
+        }
+    }
+}
+
+/// 读取一个 tag 及其后的 PreviousTagSize，文件结束或最后一个 tag 不完整时返回 `None`
+async fn read_tag<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<(TagHeader, Bytes)>> {
+    let mut bytes = [0u8; 11];
+    if !read_or_eof(reader, &mut bytes).await? {
+        return Ok(None);
+    }
+    let (_, tag_header) =
+        tag_header(&bytes).map_err(|_| FlvError::InvalidData("tag header".to_string()))?;
+    let mut body = vec![0u8; tag_header.data_size as usize + 4];
+    if !read_or_eof(reader, &mut body).await? {
+        return Ok(None);
+    }
+    body.truncate(tag_header.data_size as usize);
+    Ok(Some((tag_header, Bytes::from(body))))
+}
+
+pub(crate) async fn read_or_eof<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf).await {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn write_tag(writer: &mut BufWriter<File>, tag_type: TagType, timestamp: u32, body: &Bytes) -> Result<()> {
+    let tag = RawFlvTag {
+        tag_type,
+        timestamp,
+        header: Bytes::new(),
+        payload: body.clone(),
+    };
+    writer.write_all(&tag.marshal()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bytes::Bytes;
+    use crate::flv_parser::TagType;
+    use crate::flv_reader::flv_duration;
+    use crate::flv_writer::RawFlvTag;
+    use super::split_flv;
+
+    fn tag(tag_type: TagType, timestamp: u32, body: &'static [u8]) -> Bytes {
+        RawFlvTag { tag_type, timestamp, header: Bytes::new(), payload: Bytes::from_static(body) }.marshal()
+    }
+
+    #[tokio::test]
+    async fn split_on_keyframes() {
+        let dir = std::env::temp_dir().join(format!("blzbj-split-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut file = vec![b'F', b'L', b'V', 1, 5, 0, 0, 0, 9, 0, 0, 0, 0];
+        file.extend(tag(TagType::Script, 0, b"\x02\x00\x0aonMetaData\x05"));
+        file.extend(tag(TagType::Audio, 0, &[0xaf, 0, 0x12, 0x10]));
+        file.extend(tag(TagType::Video, 0, &[0x17, 0, 0, 0, 0, 1, 0x64]));
+        for second in 0..4u32 {
+            file.extend(tag(TagType::Video, second * 1000, &[0x17, 1, 0, 0, 0, 0xaa]));
+            file.extend(tag(TagType::Audio, second * 1000 + 10, &[0xaf, 1, 0xbb]));
+            file.extend(tag(TagType::Video, second * 1000 + 500, &[0x27, 1, 0, 0, 0, 0xcc]));
+        }
+        let input = dir.join("input.flv");
+        std::fs::write(&input, &file).unwrap();
+
+        let template = dir.join("part_{part}.flv");
+        let parts = split_flv(&input, template.to_str().unwrap(), Duration::from_secs(2)).await.unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[1], dir.join("part_2.flv"));
+
+        let first = std::fs::read(&parts[0]).unwrap();
+        let second = std::fs::read(&parts[1]).unwrap();
+        // 第二段以文件头、onMetaData 和两个 sequence header 开始，关键帧时间戳归零
+        let headers = 13 + tag(TagType::Script, 0, b"\x02\x00\x0aonMetaData\x05").len()
+            + tag(TagType::Audio, 0, &[0xaf, 0, 0x12, 0x10]).len()
+            + tag(TagType::Video, 0, &[0x17, 0, 0, 0, 0, 1, 0x64]).len();
+        let mut expected = file[..headers].to_vec();
+        expected.extend(tag(TagType::Video, 0, &[0x17, 1, 0, 0, 0, 0xaa]));
+        assert_eq!(&second[..expected.len()], expected.as_slice());
+        assert_eq!(first.len() + second.len(), file.len() + headers);
+
+        assert_eq!(flv_duration(&mut first.as_slice()).await.unwrap(), Duration::from_millis(2000));
+        assert_eq!(flv_duration(&mut second.as_slice()).await.unwrap(), Duration::from_millis(2000));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
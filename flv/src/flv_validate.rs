@@ -0,0 +1,144 @@
+use bytes::Bytes;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use crate::error::Result;
+use crate::flv_parser::{tag_header, TagType};
+use crate::flv_reader::read_flv_header;
+use crate::flv_split::{classify, read_or_eof, TagKind};
+
+/// 校验中发现的问题，`offset` 为问题所在字段或 tag 在文件中的字节偏移
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum Anomaly {
+    /// PreviousTagSize 不等于前一个 tag 的 11 + data_size，第一个应为 0
+    PreviousTagSize { offset: u64, expected: u32, actual: u32 },
+    /// 同一轨道的时间戳倒退（32 位回绕不算）
+    TimestampJump { offset: u64, tag_type: TagType, previous: u32, current: u32 },
+    /// 已经出现过 sequence header 后再次出现，`changed` 表示内容是否不同
+    UnexpectedSequenceHeader { offset: u64, tag_type: TagType, changed: bool },
+    /// 无法解析的 tag 头，之后的数据无法定位，校验到此为止
+    InvalidTagHeader { offset: u64 },
+    /// 文件在 tag 中间结束
+    Truncated { offset: u64 },
+}
+
+/// 逐个检查 tag 的 PreviousTagSize、时间戳和 sequence header，不在第一个问题处停止，
+/// 返回发现的所有问题，用于判断异常中断的录制还能恢复多少；只有读取失败或文件头无效时返回错误
+pub async fn validate_flv<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<Anomaly>> {
+    let header = read_flv_header(reader).await?;
+    let mut extra = vec![0u8; header.offset.saturating_sub(9) as usize];
+    if !read_or_eof(reader, &mut extra).await? {
+        return Ok(vec![Anomaly::Truncated { offset: 9 }]);
+    }
+    let mut offset = u64::from(header.offset.max(9));
+    let mut anomalies = Vec::new();
+    let mut expected_size = 0;
+    let mut audio = Track::default();
+    let mut video = Track::default();
+    loop {
+        let mut size = [0u8; 4];
+        if !read_or_eof(reader, &mut size).await? {
+            anomalies.push(Anomaly::Truncated { offset });
+            break;
+        }
+        let actual = u32::from_be_bytes(size);
+        if actual != expected_size {
+            anomalies.push(Anomaly::PreviousTagSize { offset, expected: expected_size, actual });
+        }
+        offset += 4;
+
+        // 正常结束时最后一个 PreviousTagSize 之后没有数据
+        let mut bytes = [0u8; 11];
+        match reader.read(&mut bytes[..1]).await? {
+            0 => break,
+            _ if !read_or_eof(reader, &mut bytes[1..]).await? => {
+                anomalies.push(Anomaly::Truncated { offset });
+                break;
+            }
+            _ => {}
+        }
+        let Ok((_, tag_header)) = tag_header(&bytes) else {
+            anomalies.push(Anomaly::InvalidTagHeader { offset });
+            break;
+        };
+        let mut body = vec![0u8; tag_header.data_size as usize];
+        if !read_or_eof(reader, &mut body).await? {
+            anomalies.push(Anomaly::Truncated { offset });
+            break;
+        }
+        let body = Bytes::from(body);
+        let track = match tag_header.tag_type {
+            TagType::Audio => Some(&mut audio),
+            TagType::Video => Some(&mut video),
+            TagType::Script => None,
+        };
+        if let Some(track) = track {
+            if let Some(previous) = track.timestamp {
+                let current = tag_header.timestamp;
+                // 倒退超过半个周期认为是回绕
+                if current < previous && previous - current <= u32::MAX / 2 {
+                    anomalies.push(Anomaly::TimestampJump { offset, tag_type: tag_header.tag_type, previous, current });
+                }
+            }
+            track.timestamp = Some(tag_header.timestamp);
+            if matches!(classify(&tag_header, &body), TagKind::AudioHeader | TagKind::VideoHeader) {
+                if let Some(previous) = track.sequence_header.replace(body.clone()) {
+                    anomalies.push(Anomaly::UnexpectedSequenceHeader {
+                        offset,
+                        tag_type: tag_header.tag_type,
+                        changed: previous != body,
+                    });
+                }
+            }
+        }
+        offset += 11 + u64::from(tag_header.data_size);
+        expected_size = 11 + tag_header.data_size;
+    }
+    Ok(anomalies)
+}
+
+#[derive(Default)]
+struct Track {
+    timestamp: Option<u32>,
+    sequence_header: Option<Bytes>,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::flv_parser::TagType;
+    use crate::flv_writer::RawFlvTag;
+    use super::{validate_flv, Anomaly};
+
+    fn tag(tag_type: TagType, timestamp: u32, body: &'static [u8]) -> Vec<u8> {
+        RawFlvTag { tag_type, timestamp, header: Bytes::new(), payload: Bytes::from_static(body) }.marshal().to_vec()
+    }
+
+    #[tokio::test]
+    async fn report_all_anomalies() {
+        let mut file = vec![b'F', b'L', b'V', 1, 5, 0, 0, 0, 9, 0, 0, 0, 0];
+        file.extend(tag(TagType::Video, 0, &[0x17, 0, 0, 0, 0, 1, 0x64]));
+        file.extend(tag(TagType::Video, 40, &[0x17, 1, 0, 0, 0, 0xaa]));
+        // 损坏的 PreviousTagSize
+        let len = file.len();
+        file[len - 1] ^= 0xff;
+        file.extend(tag(TagType::Video, 20, &[0x27, 1, 0, 0, 0, 0xbb]));
+        let sequence_header = file.len() as u64;
+        file.extend(tag(TagType::Video, 80, &[0x17, 0, 0, 0, 0, 1, 0x4d]));
+        file.extend(&tag(TagType::Video, 120, &[0x27, 1, 0, 0, 0, 0xcc])[..8]);
+
+        let anomalies = validate_flv(&mut file.as_slice()).await.unwrap();
+        // 每个 tag 占 11 字节头、数据和 4 字节 PreviousTagSize
+        let broken = 13 + 22 + 21;
+        assert_eq!(anomalies, [
+            Anomaly::PreviousTagSize { offset: broken - 4, expected: 17, actual: 17 ^ 0xff },
+            Anomaly::TimestampJump { offset: broken, tag_type: TagType::Video, previous: 40, current: 20 },
+            Anomaly::UnexpectedSequenceHeader { offset: sequence_header, tag_type: TagType::Video, changed: true },
+            Anomaly::Truncated { offset: sequence_header + 22 },
+        ]);
+
+        let mut clean = vec![b'F', b'L', b'V', 1, 1, 0, 0, 0, 9, 0, 0, 0, 0];
+        clean.extend(tag(TagType::Video, 0, &[0x17, 1, 0, 0, 0, 0xaa]));
+        assert_eq!(validate_flv(&mut clean.as_slice()).await.unwrap(), []);
+    }
+}
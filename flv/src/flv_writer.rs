@@ -1,13 +1,16 @@
 use crate::flv_parser::{
-    AACPacketType, AVCPacketType, CodecId, FrameType, ScriptData, SoundFormat, SoundRate,
-    SoundSize, SoundType, TagHeader,
+    header, tag_header, AACPacketType, AVCPacketType, AudioDataHeader, CodecId, ExAudioPacketType,
+    ExVideoPacketType, FrameType, ScriptData, SoundFormat, SoundRate, SoundSize, SoundType,
+    TagHeader, TagType, VideoDataHeader,
 };
+use crate::error::{FlvError, Result};
 
-use crate::util::LifecycleFile;
-use byteorder::{BigEndian, WriteBytesExt};
+use utils::LifecycleFile;
+use bytes::{BufMut, Bytes, BytesMut};
 use serde::Serialize;
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 
 use tracing::info;
 
@@ -20,22 +23,56 @@ const FLV_HEADER: [u8; 9] = [
     0x00, 0x00, 0x00, 0x09, //flv header size
 ]; // 9
 
-pub struct FlvFile {
+pub struct FlvWriterMuxer {
     pub buf_writer: BufWriter<File>,
     pub file: LifecycleFile,
+    /// 续写时文件中最后一个完整 tag 的时间戳，写入第一个 tag 时据此计算偏移
+    resume_timestamp: Option<u32>,
+    timestamp_offset: u32,
 }
 
-impl FlvFile {
-    pub fn new(mut file: LifecycleFile) -> std::io::Result<Self> {
+impl FlvWriterMuxer {
+    pub fn new(mut file: LifecycleFile) -> Result<Self> {
         // let file_name = util::format_filename(file_name);
         let path = file.create()?;
         Ok(Self {
             buf_writer: Self::create(path)?,
             file,
+            resume_timestamp: None,
+            timestamp_offset: 0,
         })
     }
 
-    pub fn create_new(&mut self) -> std::io::Result<()> {
+    /// 打开异常中断的录制文件继续写入：截掉末尾不完整的 tag，不再写文件头，
+    /// 之后写入的 tag 时间戳接在最后一个完整 tag 之后。
+    /// `path` 以 `.part` 结尾时，关闭后去掉该后缀
+    pub fn open_append<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut out = OpenOptions::new().read(true).write(true).open(path)?;
+        let tail = last_complete_tag(&mut BufReader::new(&mut out))?;
+        out.set_len(tail.end)?;
+        out.seek(SeekFrom::Start(tail.end))?;
+        let mut buf_writer = BufWriter::new(out);
+        if !tail.previous_tag_size0 {
+            Self::write_previous_tag_size(&mut buf_writer, 0)?;
+        }
+        info!("append to flv file {}", path.display());
+
+        let path_str = path.to_string_lossy();
+        let file_name = path_str.strip_suffix(".part").unwrap_or(&path_str);
+        let mut file = LifecycleFile::new(file_name.strip_suffix(".flv").unwrap_or(file_name), "flv", None);
+        file.file_name = file_name.to_string();
+        file.path = path.to_path_buf();
+        Ok(Self {
+            buf_writer,
+            file,
+            resume_timestamp: tail.last_timestamp,
+            timestamp_offset: 0,
+        })
+    }
+
+    pub fn create_new(&mut self) -> Result<()> {
+        self.buf_writer.flush()?;
         self.file.rename();
         let path = self.file.create()?;
         self.buf_writer = Self::create(path)?;
@@ -65,21 +102,27 @@ impl FlvFile {
         tag_header: &TagHeader,
         body: &[u8],
         previous_tag_size: &[u8],
-    ) -> std::io::Result<usize> {
+    ) -> Result<usize> {
         self.write_tag_header(tag_header)?;
         self.buf_writer.write_all(body)?;
-        self.buf_writer.write(previous_tag_size)
+        Ok(self.buf_writer.write(previous_tag_size)?)
+    }
+
+    pub fn write_tag_header(&mut self, tag_header: &TagHeader) -> Result<()> {
+        let tag_header = TagHeader {
+            timestamp: self.shift_timestamp(tag_header.timestamp),
+            ..*tag_header
+        };
+        self.buf_writer.write_all(&tag_header.marshal())?;
+        Ok(())
     }
 
-    pub fn write_tag_header(&mut self, tag_header: &TagHeader) -> std::io::Result<()> {
-        self.buf_writer.write_u8(tag_header.tag_type as u8)?;
-        self.buf_writer
-            .write_u24::<BigEndian>(tag_header.data_size)?;
-        self.buf_writer
-            .write_u24::<BigEndian>(tag_header.timestamp & 0xffffff)?;
-        let timestamp_ext = (tag_header.timestamp >> 24 & 0xff) as u8;
-        self.buf_writer.write_u8(timestamp_ext)?;
-        self.buf_writer.write_u24::<BigEndian>(tag_header.stream_id)
+    /// 续写后的第一个 tag 排在原文件最后一个 tag 之后 1ms，之后保持相同的偏移
+    fn shift_timestamp(&mut self, timestamp: u32) -> u32 {
+        if let Some(last) = self.resume_timestamp.take() {
+            self.timestamp_offset = last.wrapping_add(1).wrapping_sub(timestamp);
+        }
+        timestamp.wrapping_add(self.timestamp_offset)
     }
 
     pub fn write_previous_tag_size(
@@ -88,10 +131,153 @@ impl FlvFile {
     ) -> std::io::Result<usize> {
         writer.write(&previous_tag_size.to_be_bytes())
     }
+
+    /// 写入完整的 tag，包括结尾的 PreviousTagSize
+    pub fn write_raw_tag(&mut self, tag: &RawFlvTag) -> Result<()> {
+        let timestamp = self.shift_timestamp(tag.timestamp);
+        let bytes = if timestamp == tag.timestamp {
+            tag.marshal()
+        } else {
+            RawFlvTag { timestamp, ..tag.clone() }.marshal()
+        };
+        self.buf_writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// 按变体确定 tag 类型，写入 tag 头、`data` 和 PreviousTagSize
+    pub fn write_flv_data(&mut self, data: FlvData) -> Result<()> {
+        self.write_raw_tag(&data.into())
+    }
+}
+
+struct Tail {
+    /// 最后一个完整 tag（含其 PreviousTagSize）结束的位置
+    end: u64,
+    /// 已有 tag 中最大的时间戳
+    last_timestamp: Option<u32>,
+    /// 文件中是否已有完整的 PreviousTagSize0
+    previous_tag_size0: bool,
+}
+
+/// 从头检查每个 tag 的长度和 PreviousTagSize，找到可以续写的位置
+fn last_complete_tag<R: Read + Seek>(reader: &mut R) -> Result<Tail> {
+    let mut header_bytes = [0u8; 9];
+    reader.read_exact(&mut header_bytes)?;
+    let (_, flv_header) = header(&header_bytes).map_err(|_| FlvError::InvalidData("flv header".to_string()))?;
+    let mut end = u64::from(flv_header.offset);
+    reader.seek(SeekFrom::Start(end))?;
+    let mut size = [0u8; 4];
+    if !read_or_eof(reader, &mut size)? {
+        return Ok(Tail { end, last_timestamp: None, previous_tag_size0: false });
+    }
+    end += 4;
+    let mut last_timestamp = None;
+    let mut bytes = [0u8; 11];
+    while read_or_eof(reader, &mut bytes)? {
+        let Ok((_, tag_header)) = tag_header(&bytes) else {
+            break;
+        };
+        reader.seek(SeekFrom::Current(i64::from(tag_header.data_size)))?;
+        if !read_or_eof(reader, &mut size)? || u32::from_be_bytes(size) != 11 + tag_header.data_size {
+            break;
+        }
+        end += 11 + u64::from(tag_header.data_size) + 4;
+        last_timestamp = last_timestamp.max(Some(tag_header.timestamp));
+    }
+    Ok(Tail { end, last_timestamp, previous_tag_size0: true })
+}
+
+fn read_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// 待写入的完整 tag，`header` 为音视频头部（script tag 为空），`payload` 为其后的数据
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawFlvTag {
+    pub tag_type: TagType,
+    pub timestamp: u32,
+    pub header: Bytes,
+    pub payload: Bytes,
 }
 
-impl Drop for FlvFile {
+impl RawFlvTag {
+    pub fn audio(timestamp: u32, header: &AudioDataHeader, payload: Bytes) -> Self {
+        Self {
+            tag_type: TagType::Audio,
+            timestamp,
+            header: header.marshal().into(),
+            payload,
+        }
+    }
+
+    pub fn video(timestamp: u32, header: &VideoDataHeader, payload: Bytes) -> Self {
+        Self {
+            tag_type: TagType::Video,
+            timestamp,
+            header: header.marshal().into(),
+            payload,
+        }
+    }
+
+    pub fn script(timestamp: u32, payload: Bytes) -> Self {
+        Self {
+            tag_type: TagType::Script,
+            timestamp,
+            header: Bytes::new(),
+            payload,
+        }
+    }
+
+    pub fn data_size(&self) -> u32 {
+        (self.header.len() + self.payload.len()) as u32
+    }
+
+    /// 11 字节 tag header + body + 4 字节 PreviousTagSize
+    pub fn marshal(&self) -> Bytes {
+        let data_size = self.data_size();
+        let mut bytes = BytesMut::with_capacity(11 + data_size as usize + 4);
+        let header = TagHeader {
+            tag_type: self.tag_type,
+            data_size,
+            timestamp: self.timestamp,
+            stream_id: 0,
+        };
+        bytes.put_slice(&header.marshal());
+        bytes.put_slice(&self.header);
+        bytes.put_slice(&self.payload);
+        bytes.put_u32(11 + data_size);
+        bytes.freeze()
+    }
+}
+
+/// 已编码好的一个 tag 的数据，`data` 为完整的 tag body（音视频头部加负载，或 AMF 编码的 script data）
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FlvData {
+    Video { timestamp: u32, data: Bytes },
+    Audio { timestamp: u32, data: Bytes },
+    MetaData { timestamp: u32, data: Bytes },
+}
+
+impl From<FlvData> for RawFlvTag {
+    fn from(value: FlvData) -> Self {
+        let (tag_type, timestamp, payload) = match value {
+            FlvData::Video { timestamp, data } => (TagType::Video, timestamp, data),
+            FlvData::Audio { timestamp, data } => (TagType::Audio, timestamp, data),
+            FlvData::MetaData { timestamp, data } => (TagType::Script, timestamp, data),
+        };
+        RawFlvTag { tag_type, timestamp, header: Bytes::new(), payload }
+    }
+}
+
+impl Drop for FlvWriterMuxer {
     fn drop(&mut self) {
+        if let Err(e) = self.buf_writer.flush() {
+            tracing::error!("{e}")
+        }
         self.file.rename()
     }
 }
@@ -114,13 +300,111 @@ pub enum TagDataHeader<'a> {
         sound_rate: SoundRate,
         sound_size: SoundSize,
         sound_type: SoundType,
+        ex_packet_type: Option<ExAudioPacketType>,
         packet_type: Option<AACPacketType>,
     },
     Video {
         frame_type: FrameType,
         codec_id: CodecId,
+        ex_packet_type: Option<ExVideoPacketType>,
         packet_type: Option<AVCPacketType>,
         composition_time: Option<i32>,
     },
     Script(ScriptData<'a>),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{FlvData, FlvWriterMuxer, RawFlvTag};
+    use crate::flv_parser::{complete_tag, tag_header, video_data_header, TagData, TagType};
+    use crate::flv_validate::validate_flv;
+    use bytes::Bytes;
+
+    #[test]
+    fn raw_tag_round_trip() {
+        let (_, header) = video_data_header(&[0x17]).unwrap();
+        let tag = RawFlvTag::video(0x01000010, &header, Bytes::from_static(&[1, 0, 0, 0, 0xaa]));
+        let bytes = tag.marshal();
+        assert_eq!(bytes.len(), 11 + 6 + 4);
+        assert_eq!(&bytes[bytes.len() - 4..], &17u32.to_be_bytes());
+
+        let (rest, parsed) = complete_tag(&bytes).unwrap();
+        assert_eq!(rest.len(), 4);
+        assert_eq!(parsed.header.tag_type, TagType::Video);
+        assert_eq!(parsed.header.data_size, 6);
+        assert_eq!(parsed.header.timestamp, 0x01000010);
+        let TagData::Video(video) = parsed.data else {
+            panic!("expected video data");
+        };
+        assert_eq!(video.video_data, &[1, 0, 0, 0, 0xaa]);
+    }
+
+    #[test]
+    fn write_each_flv_data_variant() {
+        let dir = std::env::temp_dir().join(format!("flv_data_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data.flv");
+        // 只有文件头和 PreviousTagSize0 的文件，续写时不改变时间戳
+        std::fs::write(&path, [b'F', b'L', b'V', 1, 5, 0, 0, 0, 9, 0, 0, 0, 0]).unwrap();
+        let meta = Bytes::from_static(b"\x02\x00\x0aonMetaData\x08\x00\x00\x00\x00\x00\x00\x09");
+        let mut muxer = FlvWriterMuxer::open_append(&path).unwrap();
+        muxer.write_flv_data(FlvData::MetaData { timestamp: 0, data: meta.clone() }).unwrap();
+        muxer.write_flv_data(FlvData::Audio { timestamp: 0, data: Bytes::from_static(&[0xaf, 1, 0x21]) }).unwrap();
+        muxer.write_flv_data(FlvData::Video { timestamp: 40, data: Bytes::from_static(&[0x27, 1, 0, 0, 0, 0xbb]) }).unwrap();
+        drop(muxer);
+
+        let file = std::fs::read(&path).unwrap();
+        let mut rest = &file[13..];
+        let mut tags = Vec::new();
+        while !rest.is_empty() {
+            let (_, header) = tag_header(rest).unwrap();
+            let size = 11 + header.data_size as usize;
+            assert_eq!(&rest[size..size + 4], &(size as u32).to_be_bytes());
+            tags.push((header.tag_type, header.timestamp, header.data_size));
+            rest = &rest[size + 4..];
+        }
+        assert_eq!(tags, [
+            (TagType::Script, 0, meta.len() as u32),
+            (TagType::Audio, 0, 3),
+            (TagType::Video, 40, 6),
+        ]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resume_after_truncated_tag() {
+        let dir = std::env::temp_dir().join(format!("flv_append_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("record.flv.part");
+        let keyframe = |timestamp| RawFlvTag {
+            tag_type: TagType::Video,
+            timestamp,
+            header: Bytes::new(),
+            payload: Bytes::from_static(&[0x17, 1, 0, 0, 0, 0xaa]),
+        };
+        let mut file = vec![b'F', b'L', b'V', 1, 1, 0, 0, 0, 9, 0, 0, 0, 0];
+        file.extend(keyframe(0).marshal());
+        file.extend(keyframe(40).marshal());
+        let complete = file.len();
+        // 崩溃时最后一个 tag 只写了一半
+        file.extend(&keyframe(80).marshal()[..10]);
+        std::fs::write(&path, &file).unwrap();
+
+        let mut muxer = FlvWriterMuxer::open_append(&path).unwrap();
+        // 重新连接后的流从 0 开始
+        muxer.write_raw_tag(&keyframe(0)).unwrap();
+        muxer.write_raw_tag(&keyframe(40)).unwrap();
+        drop(muxer);
+
+        assert!(!path.exists());
+        let resumed = std::fs::read(dir.join("record.flv")).unwrap();
+        assert_eq!(&resumed[..complete], &file[..complete]);
+        let timestamps: Vec<u32> = resumed[complete..]
+            .chunks(21)
+            .map(|tag| complete_tag(tag).unwrap().1.header.timestamp)
+            .collect();
+        assert_eq!(timestamps, [41, 81]);
+        assert_eq!(validate_flv(&mut resumed.as_slice()).await.unwrap(), []);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,499 @@
+use crate::error::{FlvError, Result};
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// AVCDecoderConfigurationRecord 中的 `lengthSizeMinusOne + 1`，只能是 1、2 或 4
+pub fn nal_length_size(avc_decoder_config: &[u8]) -> Result<u8> {
+    let size = avc_decoder_config
+        .get(4)
+        .map(|byte| (byte & 0x03) + 1)
+        .ok_or_else(|| FlvError::InvalidData("AVCDecoderConfigurationRecord".to_string()))?;
+    check_nal_length_size(size)?;
+    Ok(size)
+}
+
+fn check_nal_length_size(nal_length_size: u8) -> Result<()> {
+    match nal_length_size {
+        1 | 2 | 4 => Ok(()),
+        size => Err(FlvError::InvalidData(format!("NALU length size {size}"))),
+    }
+}
+
+/// 拆分长度前缀的 NALU，`nal_length_size` 来自 DecoderConfigurationRecord，
+/// 数据不完整时丢弃最后一个 NALU
+pub fn parse_nalus(data: &[u8], nal_length_size: u8) -> Result<Vec<&[u8]>> {
+    check_nal_length_size(nal_length_size)?;
+    let nal_length_size = nal_length_size as usize;
+    let mut nalus = Vec::new();
+    let mut rest = data;
+    while rest.len() >= nal_length_size {
+        let (length, body) = rest.split_at(nal_length_size);
+        let length = length.iter().fold(0usize, |acc, byte| acc << 8 | *byte as usize);
+        let Some(nalu) = body.get(..length) else {
+            break;
+        };
+        nalus.push(nalu);
+        rest = &body[length..];
+    }
+    Ok(nalus)
+}
+
+/// AVCDecoderConfigurationRecord 中所有的 SPS 和 PPS
+pub fn avc_parameter_sets(avc_decoder_config: &[u8]) -> Result<Vec<&[u8]>> {
+    let invalid = || FlvError::InvalidData("AVCDecoderConfigurationRecord".to_string());
+    let mut sets = Vec::new();
+    let mut rest = avc_decoder_config.get(5..).ok_or_else(invalid)?;
+    // numOfSequenceParameterSets 只占低 5 位，numOfPictureParameterSets 占整个字节
+    for mask in [0x1f, 0xff] {
+        let (&count, tail) = rest.split_first().ok_or_else(invalid)?;
+        rest = tail;
+        for _ in 0..count & mask {
+            let length = rest.get(..2).ok_or_else(invalid)?;
+            let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+            let set = rest.get(2..2 + length).ok_or_else(invalid)?;
+            sets.push(set);
+            rest = &rest[2 + length..];
+        }
+    }
+    Ok(sets)
+}
+
+/// 长度前缀的 NALU 转换为起始码分隔，数据不完整时丢弃最后一个 NALU
+pub fn avcc_to_annexb(data: &[u8], nal_length_size: u8) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() + 16);
+    for nalu in parse_nalus(data, nal_length_size)? {
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(nalu);
+    }
+    Ok(out)
+}
+
+/// 起始码分隔的 NALU 转换为长度前缀，支持 3 字节和 4 字节起始码
+pub fn annexb_to_avcc(data: &[u8], nal_length_size: u8) -> Vec<u8> {
+    let nal_length_size = nal_length_size as usize;
+    let mut out = Vec::with_capacity(data.len());
+    for nalu in annexb_nalus(data) {
+        let length = (nalu.len() as u32).to_be_bytes();
+        out.extend_from_slice(&length[4 - nal_length_size..]);
+        out.extend_from_slice(nalu);
+    }
+    out
+}
+
+fn annexb_nalus(data: &[u8]) -> Vec<&[u8]> {
+    let mut nalus = Vec::new();
+    let mut start = None;
+    let mut push = |start: usize, mut end: usize| {
+        // 去掉 trailing_zero_8bits 以及 4 字节起始码的第一个 0
+        while end > start && data[end - 1] == 0 {
+            end -= 1;
+        }
+        if end > start {
+            nalus.push(&data[start..end]);
+        }
+    };
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            if let Some(start) = start {
+                push(start, i);
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(start) = start {
+        push(start, data.len());
+    }
+    nalus
+}
+
+/// 按 `chroma_format_idc` 返回 (SubWidthC, SubHeightC)，用于计算 SPS 的裁剪，
+/// 单色（0）和 4:4:4 独立平面没有色度子采样，返回 None
+pub fn sub_wh(chroma_format_idc: u8) -> Option<(u8, u8)> {
+    match chroma_format_idc {
+        1 => Some((2, 2)), // 4:2:0
+        2 => Some((2, 1)), // 4:2:2
+        3 => Some((1, 1)), // 4:4:4
+        _ => None,
+    }
+}
+
+/// NAL header 之后去掉了防竞争字节的 RBSP
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NalUnit {
+    pub header: u8,
+    pub rbsp: Vec<u8>,
+}
+
+impl NalUnit {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let (&header, payload) = data
+            .split_first()
+            .ok_or_else(|| FlvError::InvalidData("empty NAL unit".to_string()))?;
+        let mut rbsp = Vec::with_capacity(payload.len());
+        let mut zeros = 0;
+        for &byte in payload {
+            if zeros >= 2 && byte == 0x03 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            rbsp.push(byte);
+        }
+        Ok(Self { header, rbsp })
+    }
+
+    pub fn nal_unit_type(&self) -> u8 {
+        self.header & 0x1f
+    }
+
+    /// 与 `parse` 相反，在 `00 00` 之后的 `00`~`03` 前插入 `0x03`
+    pub fn to_ebsp(&self) -> Vec<u8> {
+        let mut ebsp = Vec::with_capacity(self.rbsp.len() + self.rbsp.len() / 64 + 1);
+        ebsp.push(self.header);
+        let mut zeros = 0;
+        for &byte in &self.rbsp {
+            if zeros >= 2 && byte <= 0x03 {
+                ebsp.push(0x03);
+                zeros = 0;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            ebsp.push(byte);
+        }
+        ebsp
+    }
+}
+
+/// 按位读取 RBSP，`read_ue`/`read_se` 为指数哥伦布编码
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn read_bits(&mut self, count: usize) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = self
+                .data
+                .get(self.position / 8)
+                .ok_or_else(|| FlvError::InvalidData("RBSP too short".to_string()))?;
+            value = (value << 1) | u32::from(byte >> (7 - self.position % 8) & 1);
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    pub fn read_flag(&mut self) -> Result<bool> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    pub fn read_ue(&mut self) -> Result<u32> {
+        let mut leading_zeros = 0;
+        while !self.read_flag()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(FlvError::InvalidData("Exp-Golomb code".to_string()));
+            }
+        }
+        Ok(((1u64 << leading_zeros) - 1 + u64::from(self.read_bits(leading_zeros)?)) as u32)
+    }
+
+    pub fn read_se(&mut self) -> Result<i32> {
+        let value = self.read_ue()? as i64;
+        Ok(if value % 2 == 1 { (value + 1) / 2 } else { -(value / 2) } as i32)
+    }
+}
+
+/// SPS 中与分辨率和帧率有关的字段，宽高已减去裁剪
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sps {
+    pub profile_idc: u8,
+    pub level_idc: u8,
+    pub width: u32,
+    pub height: u32,
+    /// VUI 中有 timing info 时为 `time_scale / (2 * num_units_in_tick)`
+    pub fps: Option<f64>,
+}
+
+impl Sps {
+    /// `nalu` 为包含 NAL header 的完整 SPS（ITU-T H.264 7.3.2.1.1）
+    pub fn parse(nalu: &[u8]) -> Result<Self> {
+        let nalu = NalUnit::parse(nalu)?;
+        if nalu.nal_unit_type() != 7 {
+            return Err(FlvError::InvalidData(format!("NAL unit type {} is not SPS", nalu.nal_unit_type())));
+        }
+        let mut reader = BitReader::new(&nalu.rbsp);
+        let profile_idc = reader.read_bits(8)? as u8;
+        reader.read_bits(8)?; // constraint_set flags
+        let level_idc = reader.read_bits(8)? as u8;
+        reader.read_ue()?; // seq_parameter_set_id
+
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane = false;
+        if matches!(profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135) {
+            chroma_format_idc = reader.read_ue()?;
+            if chroma_format_idc == 3 {
+                separate_colour_plane = reader.read_flag()?;
+            }
+            reader.read_ue()?; // bit_depth_luma_minus8
+            reader.read_ue()?; // bit_depth_chroma_minus8
+            reader.read_flag()?; // qpprime_y_zero_transform_bypass_flag
+            if reader.read_flag()? {
+                let count = if chroma_format_idc == 3 { 12 } else { 8 };
+                for i in 0..count {
+                    if reader.read_flag()? {
+                        skip_scaling_list(&mut reader, if i < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+
+        reader.read_ue()?; // log2_max_frame_num_minus4
+        match reader.read_ue()? {
+            0 => {
+                reader.read_ue()?; // log2_max_pic_order_cnt_lsb_minus4
+            }
+            1 => {
+                reader.read_flag()?; // delta_pic_order_always_zero_flag
+                reader.read_se()?; // offset_for_non_ref_pic
+                reader.read_se()?; // offset_for_top_to_bottom_field
+                for _ in 0..reader.read_ue()? {
+                    reader.read_se()?;
+                }
+            }
+            _ => {}
+        }
+        reader.read_ue()?; // max_num_ref_frames
+        reader.read_flag()?; // gaps_in_frame_num_value_allowed_flag
+
+        let width_in_mbs = reader.read_ue()? + 1;
+        let height_in_map_units = reader.read_ue()? + 1;
+        let frame_mbs_only = reader.read_flag()?;
+        if !frame_mbs_only {
+            reader.read_flag()?; // mb_adaptive_frame_field_flag
+        }
+        reader.read_flag()?; // direct_8x8_inference_flag
+        let field_factor = if frame_mbs_only { 1 } else { 2 };
+        let mut width = width_in_mbs * 16;
+        let mut height = height_in_map_units * 16 * field_factor;
+        if reader.read_flag()? {
+            let chroma_array_type = if separate_colour_plane { 0 } else { chroma_format_idc as u8 };
+            let (crop_unit_x, crop_unit_y) = match sub_wh(chroma_array_type) {
+                Some((sub_width, sub_height)) => (sub_width as u32, sub_height as u32 * field_factor),
+                None => (1, field_factor),
+            };
+            let (left, right) = (reader.read_ue()?, reader.read_ue()?);
+            let (top, bottom) = (reader.read_ue()?, reader.read_ue()?);
+            width = width.saturating_sub(crop_unit_x * (left + right));
+            height = height.saturating_sub(crop_unit_y * (top + bottom));
+        }
+
+        let fps = if reader.read_flag()? { vui_fps(&mut reader)? } else { None };
+        Ok(Self { profile_idc, level_idc, width, height, fps })
+    }
+}
+
+fn skip_scaling_list(reader: &mut BitReader, size: usize) -> Result<()> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for _ in 0..size {
+        if next_scale != 0 {
+            next_scale = (last_scale + reader.read_se()? + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Ok(())
+}
+
+/// 读取 VUI 到 timing info 为止（E.1.1）
+fn vui_fps(reader: &mut BitReader) -> Result<Option<f64>> {
+    if reader.read_flag()? && reader.read_bits(8)? == 255 {
+        reader.read_bits(32)?; // sar_width, sar_height
+    }
+    if reader.read_flag()? {
+        reader.read_flag()?; // overscan_appropriate_flag
+    }
+    if reader.read_flag()? {
+        reader.read_bits(4)?; // video_format, video_full_range_flag
+        if reader.read_flag()? {
+            reader.read_bits(24)?; // colour_primaries, transfer_characteristics, matrix_coefficients
+        }
+    }
+    if reader.read_flag()? {
+        reader.read_ue()?; // chroma_sample_loc_type_top_field
+        reader.read_ue()?; // chroma_sample_loc_type_bottom_field
+    }
+    if !reader.read_flag()? {
+        return Ok(None);
+    }
+    let num_units_in_tick = reader.read_bits(32)?;
+    let time_scale = reader.read_bits(32)?;
+    Ok((num_units_in_tick > 0).then(|| time_scale as f64 / (2.0 * num_units_in_tick as f64)))
+}
+
+/// 解析 AVCDecoderConfigurationRecord 中的第一个 SPS
+pub fn config_sps(avc_decoder_config: &[u8]) -> Result<Sps> {
+    let sets = avc_parameter_sets(avc_decoder_config)?;
+    let sps = sets
+        .into_iter()
+        .find(|set| set.first().is_some_and(|header| header & 0x1f == 7))
+        .ok_or_else(|| FlvError::InvalidData("AVCDecoderConfigurationRecord without SPS".to_string()))?;
+    Sps::parse(sps)
+}
+
+/// 从 AVCDecoderConfigurationRecord 得到 (宽, 高)
+pub fn extract_resolution(avc_decoder_config: &[u8]) -> Result<(u32, u32)> {
+    config_sps(avc_decoder_config).map(|sps| (sps.width, sps.height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        annexb_to_avcc, avc_parameter_sets, avcc_to_annexb, extract_resolution, nal_length_size, parse_nalus,
+        NalUnit, Sps,
+    };
+
+    const SPS: [u8; 4] = [0x67, 0x64, 0x00, 0x1f];
+    const PPS: [u8; 3] = [0x68, 0xee, 0x3c];
+    const IDR: [u8; 5] = [0x65, 0x88, 0x84, 0x00, 0x21];
+
+    #[test]
+    fn avcc_annexb_round_trip() {
+        let mut avcc = vec![0, 0, 0, 4];
+        avcc.extend(SPS);
+        avcc.extend([0, 0, 0, 3]);
+        avcc.extend(PPS);
+        avcc.extend([0, 0, 0, 5]);
+        avcc.extend(IDR);
+
+        let mut annexb = vec![0, 0, 0, 1];
+        annexb.extend(SPS);
+        annexb.extend([0, 0, 0, 1]);
+        annexb.extend(PPS);
+        annexb.extend([0, 0, 0, 1]);
+        annexb.extend(IDR);
+
+        assert_eq!(avcc_to_annexb(&avcc, 4).unwrap(), annexb);
+        assert_eq!(annexb_to_avcc(&annexb, 4), avcc);
+    }
+
+    #[test]
+    fn short_length_prefix_and_start_code() {
+        let config = [0x01, 0x64, 0x00, 0x1f, 0xfd];
+        let size = nal_length_size(&config).unwrap();
+        assert_eq!(size, 2);
+
+        let mut annexb = vec![0, 0, 1];
+        annexb.extend(SPS);
+        annexb.extend([0, 0, 1]);
+        annexb.extend(IDR);
+        let avcc = annexb_to_avcc(&annexb, size);
+        assert_eq!(&avcc[..2], &[0, 4]);
+        assert_eq!(avcc.len(), 2 + SPS.len() + 2 + IDR.len());
+
+        let mut expected = vec![0, 0, 0, 1];
+        expected.extend(SPS);
+        expected.extend([0, 0, 0, 1]);
+        expected.extend(IDR);
+        assert_eq!(avcc_to_annexb(&avcc, size).unwrap(), expected);
+    }
+
+    #[test]
+    fn nalus_with_each_length_size() {
+        let avcc = [3, 0x67, 0x64, 0x00, 2, 0x68, 0xee];
+        assert_eq!(parse_nalus(&avcc, 1).unwrap(), [&[0x67, 0x64, 0x00][..], &[0x68, 0xee][..]]);
+        let avcc = [0, 3, 0x67, 0x64, 0x00, 0, 2, 0x68];
+        // 最后一个 NALU 不完整
+        assert_eq!(parse_nalus(&avcc, 2).unwrap(), [&[0x67, 0x64, 0x00][..]]);
+        // 同样的数据按 4 字节长度解析会得到错误的长度
+        assert!(parse_nalus(&avcc, 4).unwrap().is_empty());
+
+        assert!(parse_nalus(&avcc, 3).is_err());
+        assert!(nal_length_size(&[0x01, 0x64, 0x00, 0x1f, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn emulation_prevention_round_trip() {
+        let ebsp = [0x65, 0x88, 0x00, 0x00, 0x03, 0x00, 0x01, 0x00, 0x00, 0x03, 0x03, 0x21];
+        let nalu = NalUnit::parse(&ebsp).unwrap();
+        assert_eq!(nalu.nal_unit_type(), 5);
+        assert_eq!(nalu.rbsp, [0x88, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x03, 0x21]);
+        assert_eq!(nalu.to_ebsp(), ebsp);
+    }
+
+    #[test]
+    fn parameter_sets_from_config() {
+        let mut config = vec![0x01, 0x64, 0x00, 0x1f, 0xff, 0xe1, 0, 4];
+        config.extend(SPS);
+        config.extend([1, 0, 3]);
+        config.extend(PPS);
+        assert_eq!(avc_parameter_sets(&config).unwrap(), [&SPS[..], &PPS[..]]);
+        assert!(avc_parameter_sets(&config[..config.len() - 1]).is_err());
+    }
+
+    /// 测试用的按位写入，与 `BitReader` 相反
+    #[derive(Default)]
+    struct BitWriter {
+        bits: Vec<bool>,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: u32, count: usize) -> &mut Self {
+            self.bits.extend((0..count).rev().map(|i| value >> i & 1 == 1));
+            self
+        }
+
+        fn ue(&mut self, value: u32) -> &mut Self {
+            let code = value + 1;
+            let length = 32 - code.leading_zeros() as usize;
+            self.bits(0, length - 1).bits(code, length)
+        }
+
+        fn into_bytes(mut self) -> Vec<u8> {
+            self.bits.push(true); // rbsp_stop_one_bit
+            self.bits.chunks(8).map(|chunk| chunk.iter().enumerate().fold(0, |acc, (i, &bit)| acc | (bit as u8) << (7 - i))).collect()
+        }
+    }
+
+    #[test]
+    fn sps_resolution_and_fps() {
+        // 1920x1080 High profile，4:2:0，底部裁剪 8 行，30fps
+        let mut writer = BitWriter::default();
+        writer.bits(100, 8).bits(0, 8).bits(40, 8).ue(0);
+        writer.ue(1).ue(0).ue(0).bits(0, 1).bits(0, 1); // chroma_format_idc, bit depth, 无 scaling matrix
+        writer.ue(0).ue(0).ue(2).ue(4).bits(0, 1); // frame_num, poc type 0, ref frames
+        writer.ue(119).ue(67).bits(1, 1).bits(1, 1); // 120x68 个宏块，frame_mbs_only, direct_8x8
+        writer.bits(1, 1).ue(0).ue(0).ue(0).ue(4); // 裁剪
+        writer.bits(1, 1); // vui_parameters_present_flag
+        writer.bits(1, 1).bits(1, 8); // aspect_ratio_idc 1
+        writer.bits(0, 1).bits(0, 1).bits(0, 1); // overscan, video signal, chroma loc
+        writer.bits(1, 1).bits(1001, 32).bits(60000, 32).bits(1, 1);
+        let mut rbsp = writer.into_bytes();
+        // 插入需要防竞争的字节
+        rbsp.extend([0, 0, 1]);
+        let sps = NalUnit { header: 0x67, rbsp }.to_ebsp();
+
+        let parsed = Sps::parse(&sps).unwrap();
+        assert_eq!((parsed.profile_idc, parsed.level_idc), (100, 40));
+        assert_eq!((parsed.width, parsed.height), (1920, 1080));
+        assert!((parsed.fps.unwrap() - 29.97).abs() < 0.01);
+
+        let mut config = vec![0x01, 100, 0x00, 40, 0xff, 0xe1];
+        config.extend((sps.len() as u16).to_be_bytes());
+        config.extend(&sps);
+        config.extend([1, 0, 3]);
+        config.extend(PPS);
+        assert_eq!(extract_resolution(&config).unwrap(), (1920, 1080));
+        assert!(Sps::parse(&PPS).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use crate::error::{FlvError, Result};
+use crate::h264::{parse_nalus, sub_wh, BitReader, NalUnit};
+
+/// H.265 的 nal_unit_type，见 ITU-T H.265 表 7-1，保留和未定义的类型为 `Other`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HevcNaluType {
+    TrailN,
+    TrailR,
+    TsaN,
+    TsaR,
+    StsaN,
+    StsaR,
+    RadlN,
+    RadlR,
+    RaslN,
+    RaslR,
+    BlaWLp,
+    BlaWRadl,
+    BlaNLp,
+    IdrWRadl,
+    IdrNLp,
+    Cra,
+    Vps,
+    Sps,
+    Pps,
+    Aud,
+    Eos,
+    Eob,
+    Fd,
+    PrefixSei,
+    SuffixSei,
+    Other(u8),
+}
+
+impl From<u8> for HevcNaluType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => HevcNaluType::TrailN,
+            1 => HevcNaluType::TrailR,
+            2 => HevcNaluType::TsaN,
+            3 => HevcNaluType::TsaR,
+            4 => HevcNaluType::StsaN,
+            5 => HevcNaluType::StsaR,
+            6 => HevcNaluType::RadlN,
+            7 => HevcNaluType::RadlR,
+            8 => HevcNaluType::RaslN,
+            9 => HevcNaluType::RaslR,
+            16 => HevcNaluType::BlaWLp,
+            17 => HevcNaluType::BlaWRadl,
+            18 => HevcNaluType::BlaNLp,
+            19 => HevcNaluType::IdrWRadl,
+            20 => HevcNaluType::IdrNLp,
+            21 => HevcNaluType::Cra,
+            32 => HevcNaluType::Vps,
+            33 => HevcNaluType::Sps,
+            34 => HevcNaluType::Pps,
+            35 => HevcNaluType::Aud,
+            36 => HevcNaluType::Eos,
+            37 => HevcNaluType::Eob,
+            38 => HevcNaluType::Fd,
+            39 => HevcNaluType::PrefixSei,
+            40 => HevcNaluType::SuffixSei,
+            other => HevcNaluType::Other(other),
+        }
+    }
+}
+
+impl HevcNaluType {
+    /// IRAP 图像（BLA、IDR、CRA），解码可以从这里开始
+    pub fn is_keyframe(&self) -> bool {
+        matches!(
+            self,
+            HevcNaluType::BlaWLp
+                | HevcNaluType::BlaWRadl
+                | HevcNaluType::BlaNLp
+                | HevcNaluType::IdrWRadl
+                | HevcNaluType::IdrNLp
+                | HevcNaluType::Cra
+        )
+    }
+
+    pub fn is_parameter_set(&self) -> bool {
+        matches!(self, HevcNaluType::Vps | HevcNaluType::Sps | HevcNaluType::Pps)
+    }
+}
+
+/// 2 字节的 HEVC NAL unit header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HevcNalHeader {
+    pub forbidden_zero_bit: bool,
+    pub nal_unit_type: HevcNaluType,
+    pub nuh_layer_id: u8,
+    pub nuh_temporal_id_plus1: u8,
+}
+
+impl HevcNalHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let [first, second, ..] = *data else {
+            return Err(FlvError::InvalidData("HEVC NAL unit header".to_string()));
+        };
+        Ok(Self {
+            forbidden_zero_bit: first & 0x80 != 0,
+            nal_unit_type: HevcNaluType::from(first >> 1 & 0x3f),
+            nuh_layer_id: (first & 0x01) << 5 | second >> 3,
+            nuh_temporal_id_plus1: second & 0x07,
+        })
+    }
+}
+
+/// 长度前缀的 NALU 中是否有关键帧，用于不信任 tag 头中 frame_type 的场合
+pub fn contains_keyframe(data: &[u8], nal_length_size: u8) -> bool {
+    parse_nalus(data, nal_length_size).is_ok_and(|nalus| {
+        nalus
+            .into_iter()
+            .any(|nalu| HevcNalHeader::parse(nalu).is_ok_and(|header| header.nal_unit_type.is_keyframe()))
+    })
+}
+
+/// HEVCDecoderConfigurationRecord 中第一个 SPS 的分辨率
+pub fn extract_resolution(hevc_decoder_config: &[u8]) -> Result<(u32, u32)> {
+    let invalid = || FlvError::InvalidData("HEVCDecoderConfigurationRecord".to_string());
+    let (&num_of_arrays, mut rest) = hevc_decoder_config
+        .get(22..)
+        .and_then(<[u8]>::split_first)
+        .ok_or_else(invalid)?;
+    for _ in 0..num_of_arrays {
+        let header = rest.get(..3).ok_or_else(invalid)?;
+        let nalu_type = HevcNaluType::from(header[0] & 0x3f);
+        let num_nalus = u16::from_be_bytes([header[1], header[2]]);
+        rest = &rest[3..];
+        for _ in 0..num_nalus {
+            let length = rest.get(..2).ok_or_else(invalid)?;
+            let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+            let nalu = rest.get(2..2 + length).ok_or_else(invalid)?;
+            if nalu_type == HevcNaluType::Sps {
+                return sps_resolution(nalu);
+            }
+            rest = &rest[2 + length..];
+        }
+    }
+    Err(FlvError::InvalidData("HEVCDecoderConfigurationRecord without SPS".to_string()))
+}
+
+/// `nalu` 为包含 2 字节 NAL header 的完整 SPS（ITU-T H.265 7.3.2.2），宽高已减去 conformance window
+pub fn sps_resolution(nalu: &[u8]) -> Result<(u32, u32)> {
+    let header = HevcNalHeader::parse(nalu)?;
+    if header.nal_unit_type != HevcNaluType::Sps {
+        return Err(FlvError::InvalidData(format!("NAL unit type {:?} is not SPS", header.nal_unit_type)));
+    }
+    // NAL header 的第二个字节不会是 0，去掉防竞争字节时可以当作 RBSP 的第一个字节跳过
+    let nalu = NalUnit::parse(nalu)?;
+    let mut reader = BitReader::new(&nalu.rbsp[1..]);
+    reader.read_bits(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = reader.read_bits(3)?;
+    reader.read_flag()?; // sps_temporal_id_nesting_flag
+    skip_profile_tier_level(&mut reader, max_sub_layers_minus1)?;
+    reader.read_ue()?; // sps_seq_parameter_set_id
+    let chroma_format_idc = reader.read_ue()?;
+    let separate_colour_plane = chroma_format_idc == 3 && reader.read_flag()?;
+    let mut width = reader.read_ue()?;
+    let mut height = reader.read_ue()?;
+    if reader.read_flag()? {
+        let chroma_array_type = if separate_colour_plane { 0 } else { chroma_format_idc as u8 };
+        let (sub_width, sub_height) = sub_wh(chroma_array_type).unwrap_or((1, 1));
+        let (left, right) = (reader.read_ue()?, reader.read_ue()?);
+        let (top, bottom) = (reader.read_ue()?, reader.read_ue()?);
+        width = width.saturating_sub(sub_width as u32 * (left + right));
+        height = height.saturating_sub(sub_height as u32 * (top + bottom));
+    }
+    Ok((width, height))
+}
+
+/// profile_tier_level(1, sps_max_sub_layers_minus1)，只跳过不解析（7.3.3）
+fn skip_profile_tier_level(reader: &mut BitReader, max_sub_layers_minus1: u32) -> Result<()> {
+    // general profile 共 88 位，之后是 general_level_idc
+    reader.read_bits(32)?;
+    reader.read_bits(32)?;
+    reader.read_bits(32)?;
+    let mut present = Vec::new();
+    for _ in 0..max_sub_layers_minus1 {
+        present.push((reader.read_flag()?, reader.read_flag()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        for _ in max_sub_layers_minus1..8 {
+            reader.read_bits(2)?; // reserved_zero_2bits
+        }
+    }
+    for (profile_present, level_present) in present {
+        if profile_present {
+            reader.read_bits(32)?;
+            reader.read_bits(32)?;
+            reader.read_bits(24)?;
+        }
+        if level_present {
+            reader.read_bits(8)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{contains_keyframe, extract_resolution, HevcNalHeader, HevcNaluType};
+    use crate::h264::NalUnit;
+
+    /// 测试用的按位写入
+    #[derive(Default)]
+    struct BitWriter {
+        bits: Vec<bool>,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: u32, count: usize) -> &mut Self {
+            self.bits.extend((0..count).rev().map(|i| value >> i & 1 == 1));
+            self
+        }
+
+        fn ue(&mut self, value: u32) -> &mut Self {
+            let code = value + 1;
+            let length = 32 - code.leading_zeros() as usize;
+            self.bits(0, length - 1).bits(code, length)
+        }
+
+        fn into_bytes(mut self) -> Vec<u8> {
+            self.bits.push(true); // rbsp_stop_one_bit
+            self.bits.chunks(8).map(|chunk| chunk.iter().enumerate().fold(0, |acc, (i, &bit)| acc | (bit as u8) << (7 - i))).collect()
+        }
+    }
+
+    #[test]
+    fn parse_nal_header() {
+        // VPS: 0x40 0x01
+        let header = HevcNalHeader::parse(&[0x40, 0x01]).unwrap();
+        assert_eq!(header.nal_unit_type, HevcNaluType::Vps);
+        assert!(header.nal_unit_type.is_parameter_set());
+        assert_eq!((header.forbidden_zero_bit, header.nuh_layer_id, header.nuh_temporal_id_plus1), (false, 0, 1));
+
+        // IDR_W_RADL，nuh_layer_id 跨两个字节
+        let header = HevcNalHeader::parse(&[0x27, 0x0a]).unwrap();
+        assert_eq!(header.nal_unit_type, HevcNaluType::IdrWRadl);
+        assert!(header.nal_unit_type.is_keyframe());
+        assert_eq!((header.nuh_layer_id, header.nuh_temporal_id_plus1), (33, 2));
+
+        assert_eq!(HevcNaluType::from(48), HevcNaluType::Other(48));
+        assert!(HevcNalHeader::parse(&[0x26]).is_err());
+    }
+
+    #[test]
+    fn keyframe_in_access_unit() {
+        // AUD + TRAIL_R
+        let inter = [0, 0, 0, 3, 0x46, 0x01, 0x50, 0, 0, 0, 3, 0x02, 0x01, 0xaa];
+        assert!(!contains_keyframe(&inter, 4));
+        // prefix SEI + CRA
+        let key = [0, 0, 0, 3, 0x4e, 0x01, 0x05, 0, 0, 0, 3, 0x2a, 0x01, 0xbb];
+        assert!(contains_keyframe(&key, 4));
+        // 不完整的 NALU 被忽略
+        assert!(!contains_keyframe(&key[..12], 4));
+    }
+
+    #[test]
+    fn resolution_from_config() {
+        // 1920x1080 Main profile，两个子层，4:2:0，编码高度 1088 底部裁剪 8 行
+        let mut writer = BitWriter::default();
+        writer.bits(0, 4).bits(1, 3).bits(1, 1);
+        writer.bits(0x01, 8).bits(0x6000_0000, 32).bits(0, 32).bits(0, 16).bits(120, 8); // general profile, level 4
+        writer.bits(0, 1).bits(1, 1).bits(0, 2 * 7); // 子层只有 level
+        writer.bits(120, 8);
+        writer.ue(0).ue(1).ue(1920).ue(1088);
+        writer.bits(1, 1).ue(0).ue(0).ue(0).ue(4);
+        let mut rbsp = vec![0x01];
+        rbsp.extend(writer.into_bytes());
+        let sps = NalUnit { header: 0x42, rbsp }.to_ebsp();
+
+        let mut config = vec![0x01; 22];
+        config.push(2);
+        config.extend([0x20, 0, 1, 0, 4, 0x40, 0x01, 0x0c, 0x01]); // VPS
+        config.extend([0xa1, 0, 1]);
+        config.extend((sps.len() as u16).to_be_bytes());
+        config.extend(&sps);
+        assert_eq!(extract_resolution(&config).unwrap(), (1920, 1080));
+        assert!(extract_resolution(&config[..30]).is_err());
+    }
+}
@@ -1,92 +1,136 @@
 // let length = response.copy_to(out)?;
-use crate::downloader::error::Result;
-use crate::downloader::util::{format_filename, Segmentable};
-use m3u8_rs::Playlist;
+use crate::error::{FlvError, Result};
+use crate::hls_parser::{parse_media_playlist, parse_playlist};
+use crate::hls_playlist::{MediaPlaylist, Playlist};
+use bytes::Bytes;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Response};
+use utils::{LifecycleFile, Segmentable};
 
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
 use url::Url;
 
-use crate::client::StatelessClient;
-
+/// 拉取 m3u8 并按 media sequence 下载新分片，拼接写入文件。
+/// fMP4 的初始化分片（`EXT-X-MAP`）按地址缓存，写在每个文件的开头。
+/// 超过 `stream_timeout` 没有新分片或遇到 `EXT-X-ENDLIST` 时结束。
 pub async fn download(
     url: &str,
-    client: &StatelessClient,
+    client: &Client,
+    headers: &HeaderMap,
     file_name: &str,
     mut splitting: Segmentable,
+    stream_timeout: Duration,
 ) -> Result<()> {
     info!("Downloading {}...", url);
-    let resp = client.retryable(url).await?;
+    let resp = fetch(client, headers, url).await?;
     info!("{}", resp.status());
-    // let mut resp = resp.bytes_stream();
     let bytes = resp.bytes().await?;
-    let mut ts_file = TsFile::new(file_name);
 
     let mut media_url = Url::parse(url)?;
-    let mut pl = match m3u8_rs::parse_playlist(&bytes) {
+    let mut pl = match parse_playlist(&bytes) {
         Ok((_i, Playlist::MasterPlaylist(pl))) => {
             info!("Master playlist:\n{:#?}", pl);
-            media_url = media_url.join(&pl.variants[0].uri)?;
+            let variant = pl
+                .variants
+                .first()
+                .ok_or_else(|| FlvError::InvalidData("master playlist".to_string()))?;
+            media_url = media_url.join(&variant.uri)?;
             info!("media url: {media_url}");
-            let resp = client.retryable(media_url.as_str()).await?;
-            let bs = resp.bytes().await?;
-            // println!("{:?}", bs);
-            if let Ok((_, pl)) = m3u8_rs::parse_media_playlist(&bs) {
-                pl
-            } else {
-                let mut file = File::create("test.fmp4")?;
-                file.write_all(&bs)?;
-                panic!("Unable to parse the content.")
-            }
+            fetch_media_playlist(client, headers, &media_url).await?
         }
         Ok((_i, Playlist::MediaPlaylist(pl))) => {
             info!("Media playlist:\n{:#?}", pl);
             info!("index {}", pl.media_sequence);
             pl
         }
-        Err(e) => panic!("Parsing error: \n{}", e),
+        Err(e) => {
+            error!("Parsing error: {e}");
+            return Err(FlvError::InvalidData("m3u8 playlist".to_string()));
+        }
     };
-    let mut previous_last_segment = 0;
+    let extension = if pl.segments.iter().any(|segment| segment.map.is_some()) {
+        "m4s"
+    } else {
+        "ts"
+    };
+
+    let mut ts_file = TsFile::new(file_name, extension)?;
+    let mut init_section: Option<(Url, Bytes)> = None;
+    let mut previous_last_segment: Option<u64> = None;
+    let mut last_segment_at = Instant::now();
     loop {
         if pl.segments.is_empty() {
             info!("Segments array is empty - stream finished");
             break;
         }
-        let mut seq = pl.media_sequence;
-        for segment in &pl.segments {
-            if seq > previous_last_segment {
-                if (previous_last_segment > 0) && (seq > (previous_last_segment + 1)) {
-                    warn!("SEGMENT INFO SKIPPED");
+        for (index, segment) in pl.segments.iter().enumerate() {
+            let seq = pl.media_sequence + index as u64;
+            if previous_last_segment.is_none_or(|previous| seq > previous) {
+                if let Some(previous) = previous_last_segment {
+                    if seq > previous + 1 {
+                        warn!("SEGMENT INFO SKIPPED");
+                    }
                 }
                 debug!("Yield segment");
+                let mut needs_init = false;
                 if segment.discontinuity {
                     warn!("#EXT-X-DISCONTINUITY");
-                    ts_file = TsFile::new(file_name);
-                    // splitting = Segment::from_seg(splitting);
+                    ts_file.create_new()?;
                     splitting.reset();
+                    needs_init = true;
+                }
+                if let Some(map) = &segment.map {
+                    let map_url = media_url.join(&map.uri)?;
+                    // 每个分片都带有 `EXT-X-MAP`，地址不变时不重复下载
+                    if init_section.as_ref().is_none_or(|(url, _)| *url != map_url) {
+                        let init = fetch(client, headers, map_url.as_str()).await?.bytes().await?;
+                        if init_section.as_ref().map(|(_, section)| section) != Some(&init) {
+                            if ts_file.length > 0 {
+                                ts_file.create_new()?;
+                                splitting.reset();
+                            }
+                            needs_init = true;
+                        }
+                        init_section = Some((map_url, init));
+                    }
+                }
+                if needs_init {
+                    ts_file.write_init_opt(init_section.as_ref().map(|(_, init)| init))?;
                 }
                 let length = download_to_file(
                     media_url.join(&segment.uri)?,
                     client,
+                    headers,
                     &mut ts_file.buf_writer,
                 )
                     .await?;
+                ts_file.length += length;
                 splitting.increase_size(length);
-                splitting.increase_time(Duration::from_secs(segment.duration as u64));
+                splitting.increase_time(Duration::from_secs_f32(segment.duration));
                 if splitting.needed() {
-                    ts_file = TsFile::new(file_name);
-                    info!("{} splitting.{splitting:?}", ts_file.name);
+                    ts_file.create_new()?;
+                    ts_file.write_init_opt(init_section.as_ref().map(|(_, init)| init))?;
+                    info!("{} splitting.{splitting:?}", ts_file.file.file_name);
                     splitting.reset();
                 }
-                previous_last_segment = seq;
+                previous_last_segment = Some(seq);
+                last_segment_at = Instant::now();
             }
-            seq += 1;
         }
-        let resp = client.retryable(media_url.as_str()).await?;
-        let bs = resp.bytes().await?;
-        if let Ok((_, playlist)) = m3u8_rs::parse_media_playlist(&bs) {
+        if pl.end_list {
+            info!("#EXT-X-ENDLIST - stream finished");
+            break;
+        }
+        if last_segment_at.elapsed() > stream_timeout {
+            warn!("No new segment in {stream_timeout:?} - stream timeout");
+            break;
+        }
+        let refresh_interval = Duration::from_secs(pl.target_duration.max(2) / 2);
+        tokio::time::sleep(refresh_interval).await;
+        if let Ok(playlist) = fetch_media_playlist(client, headers, &media_url).await {
             pl = playlist;
         }
     }
@@ -94,9 +138,17 @@ pub async fn download(
     Ok(())
 }
 
-async fn download_to_file(url: Url, client: &StatelessClient, out: &mut impl Write) -> Result<u64> {
+async fn fetch_media_playlist(client: &Client, headers: &HeaderMap, media_url: &Url) -> Result<MediaPlaylist> {
+    let bs = fetch(client, headers, media_url.as_str()).await?.bytes().await?;
+    match parse_media_playlist(&bs) {
+        Ok((_, pl)) => Ok(pl),
+        Err(_) => Err(FlvError::InvalidData("media playlist".to_string())),
+    }
+}
+
+async fn download_to_file(url: Url, client: &Client, headers: &HeaderMap, out: &mut impl Write) -> Result<u64> {
     debug!("url: {url}");
-    let mut response = client.retryable(url.as_str()).await?;
+    let mut response = fetch(client, headers, url.as_str()).await?;
     let mut length: u64 = 0;
     while let Some(chunk) = response.chunk().await? {
         length += chunk.len() as u64;
@@ -109,38 +161,73 @@ async fn download_to_file(url: Url, client: &StatelessClient, out: &mut impl Wri
     Ok(length)
 }
 
+async fn fetch(client: &Client, headers: &HeaderMap, url: &str) -> Result<Response> {
+    Ok(client.get(url).headers(headers.clone()).send().await?.error_for_status()?)
+}
+
 pub struct TsFile {
     pub buf_writer: BufWriter<File>,
-    pub name: String,
+    pub file: LifecycleFile,
+    pub length: u64,
 }
 
 impl TsFile {
-    pub fn new(file_name: &str) -> Self {
-        let file_name = format_filename(file_name);
-        let out = File::create(format!("{file_name}.ts.part")).expect("Unable to create ts file.");
-        let buf_writer = BufWriter::new(out);
-        Self {
+    /// 与 FLV 相同由 `LifecycleFile` 创建上级目录，同一秒内的文件名加上序号
+    pub fn new(file_name: &str, extension: &'static str) -> std::io::Result<Self> {
+        let mut file = LifecycleFile::new(file_name, extension, None);
+        let buf_writer = BufWriter::new(File::create(file.create()?)?);
+        Ok(Self {
             buf_writer,
-            name: file_name,
+            file,
+            length: 0,
+        })
+    }
+
+    /// 先关闭并重命名当前文件，新文件才能按已存在的文件名加上序号
+    pub fn create_new(&mut self) -> std::io::Result<()> {
+        self.buf_writer.flush()?;
+        self.file.rename();
+        self.buf_writer = BufWriter::new(File::create(self.file.create()?)?);
+        self.length = 0;
+        Ok(())
+    }
+
+    pub fn write_init(&mut self, init: &[u8]) -> std::io::Result<()> {
+        self.buf_writer.write_all(init)?;
+        self.length += init.len() as u64;
+        Ok(())
+    }
+
+    fn write_init_opt(&mut self, init: Option<&Bytes>) -> std::io::Result<()> {
+        match init {
+            Some(init) => self.write_init(init),
+            None => Ok(()),
         }
     }
 }
 
 impl Drop for TsFile {
     fn drop(&mut self) {
-        std::fs::rename(
-            format!("{}.ts.part", self.name),
-            format!("{}.ts", self.name),
-        )
-            .unwrap_or_else(|e| error!("{e}"))
+        if let Err(e) = self.buf_writer.flush() {
+            error!("{e}")
+        }
+        self.file.rename();
     }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use super::download;
     use anyhow::Result;
-    use reqwest::Url;
+    use reqwest::header::HeaderMap;
+    use reqwest::{Client, Url};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use utils::Segmentable;
 
     #[test]
     fn test_url() -> Result<()> {
@@ -158,4 +245,62 @@ mod tests {
         //     "test.ts")?;
         Ok(())
     }
+
+    /// 按路径返回固定内容，记录每个路径的请求次数
+    async fn serve(files: HashMap<&'static str, &'static [u8]>) -> (String, Arc<Mutex<HashMap<String, usize>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests: Arc<Mutex<HashMap<String, usize>>> = Default::default();
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let n = socket.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..n]);
+                let path = request.split_whitespace().nth(1).unwrap_or_default().to_string();
+                *counter.lock().unwrap().entry(path.clone()).or_default() += 1;
+                let body = files.get(path.as_str()).copied().unwrap_or_default();
+                let mut response =
+                    format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len())
+                        .into_bytes();
+                response.extend(body);
+                let _ = socket.write_all(&response).await;
+            }
+        });
+        (base, requests)
+    }
+
+    #[tokio::test]
+    async fn discontinuity_and_cached_init_section() -> Result<()> {
+        let playlist = b"#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXT-X-MEDIA-SEQUENCE:0\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:1.0,\n0.m4s\n#EXTINF:1.0,\n1.m4s\n\
+            #EXT-X-DISCONTINUITY\n#EXTINF:1.0,\n2.m4s\n#EXT-X-ENDLIST\n";
+        let files = HashMap::from([
+            ("/live.m3u8", &playlist[..]),
+            ("/init.mp4", &b"init"[..]),
+            ("/0.m4s", &b"s0"[..]),
+            ("/1.m4s", &b"s1"[..]),
+            ("/2.m4s", &b"s2"[..]),
+        ]);
+        let (base, requests) = serve(files).await;
+        // 路径模板包含尚不存在的子目录
+        let dir = std::env::temp_dir().join(format!("hls_download_{}", std::process::id()));
+        let file_name = dir.join("room").join("record");
+        download(
+            &format!("{base}/live.m3u8"),
+            &Client::new(),
+            &HeaderMap::new(),
+            file_name.to_str().unwrap(),
+            Segmentable::default(),
+            Duration::from_secs(1),
+        )
+        .await?;
+
+        // 同一秒内因 discontinuity 切分的文件加上序号，不覆盖前一个文件
+        assert_eq!(std::fs::read(dir.join("room/record.m4s"))?, b"inits0s1");
+        assert_eq!(std::fs::read(dir.join("room/record_1.m4s"))?, b"inits2");
+        assert_eq!(requests.lock().unwrap()["/init.mp4"], 1);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
 }
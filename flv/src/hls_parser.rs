@@ -8,7 +8,7 @@ use nom::combinator::{complete, eof, map, map_res, opt, peek};
 use nom::multi::{fold_many0, many0};
 use nom::sequence::{delimited, pair, preceded, terminated, tuple};
 
-use crate::playlist::*;
+use crate::hls_playlist::*;
 use nom::IResult;
 use std::collections::HashMap;
 use std::f32;
@@ -23,7 +23,7 @@ use std::string;
 ///
 /// # Examples
 ///
-/// ```
+/// ```ignore
 /// use std::io::Read;
 /// use m3u8_rs::Playlist;
 ///
@@ -57,7 +57,7 @@ pub fn parse_playlist(input: &[u8]) -> IResult<&[u8], Playlist> {
 ///
 /// # Examples
 ///
-/// ```
+/// ```ignore
 /// use m3u8_rs::Playlist;
 /// use std::io::Read;
 ///
@@ -3,7 +3,7 @@
 //! The main type here is the `Playlist` enum.
 //! Which is either a `MasterPlaylist` or a `MediaPlaylist`.
 
-use crate::QuotedOrUnquoted;
+use crate::hls_parser::QuotedOrUnquoted;
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+use std::time::Duration;
+use bytes::Bytes;
+use tracing::warn;
+use crate::flv_parser::{avc_video_packet_header, video_data, AVCPacketType, CodecId, FrameType};
+use crate::h264::{avc_parameter_sets, avcc_to_annexb, nal_length_size};
+
+/// 参数为关键帧的时间戳（毫秒）和起始码分隔的 SPS、PPS 及关键帧 NALU
+pub type KeyframeCallback = Arc<dyn Fn(u32, Bytes) + Send + Sync>;
+
+/// 每隔 `every` 取出一个 H.264 关键帧交给回调，不解码，只拼上 SPS/PPS 使其可以单独解码。
+/// 收到 sequence header 之前的关键帧会被忽略，HEVC 和 E-RTMP 扩展头不处理
+pub struct KeyframeSampler {
+    every: u32,
+    last_timestamp: Option<u32>,
+    nal_length_size: u8,
+    parameter_sets: Option<Vec<u8>>,
+    callback: KeyframeCallback,
+}
+
+impl KeyframeSampler {
+    pub fn new(every: Duration, callback: KeyframeCallback) -> Self {
+        Self {
+            every: every.as_millis().min(u32::MAX as u128) as u32,
+            last_timestamp: None,
+            nal_length_size: 4,
+            parameter_sets: None,
+            callback,
+        }
+    }
+
+    /// `body` 为完整的视频 tag 数据
+    pub fn feed(&mut self, timestamp: u32, body: &[u8]) {
+        let Ok((_, video)) = video_data(body, body.len()) else {
+            return;
+        };
+        if video.ex_packet_type.is_some() || video.codec_id != CodecId::H264 {
+            return;
+        }
+        let Ok((data, header)) = avc_video_packet_header(video.video_data) else {
+            return;
+        };
+        match header.packet_type {
+            AVCPacketType::SequenceHeader => self.update_parameter_sets(data),
+            AVCPacketType::NALU if video.frame_type == FrameType::Key => {
+                let Some(parameter_sets) = &self.parameter_sets else {
+                    return;
+                };
+                // 时间戳回退（例如重连）时重新计时
+                if self.last_timestamp.is_some_and(|last| timestamp >= last && timestamp - last < self.every) {
+                    return;
+                }
+                self.last_timestamp = Some(timestamp);
+                let mut frame = parameter_sets.clone();
+                match avcc_to_annexb(data, self.nal_length_size) {
+                    Ok(nalus) => frame.extend(nalus),
+                    Err(e) => {
+                        warn!("Skip keyframe: {e}");
+                        return;
+                    }
+                }
+                (self.callback)(timestamp, Bytes::from(frame));
+            }
+            _ => {}
+        }
+    }
+
+    fn update_parameter_sets(&mut self, config: &[u8]) {
+        let result = nal_length_size(config).and_then(|size| Ok((size, avc_parameter_sets(config)?)));
+        match result {
+            Ok((size, sets)) => {
+                self.nal_length_size = size;
+                let mut annexb = Vec::new();
+                for set in sets {
+                    annexb.extend_from_slice(&[0, 0, 0, 1]);
+                    annexb.extend_from_slice(set);
+                }
+                self.parameter_sets = Some(annexb);
+            }
+            Err(e) => warn!("Skip invalid avc sequence header: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use utils::parking_lot::Mutex;
+    use super::KeyframeSampler;
+
+    #[test]
+    fn sample_keyframes_with_parameter_sets() {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let sink = frames.clone();
+        let mut sampler = KeyframeSampler::new(
+            Duration::from_secs(2),
+            Arc::new(move |timestamp, frame| sink.lock().push((timestamp, frame))),
+        );
+        let keyframe = [0x17, 1, 0, 0, 0, 0, 0, 0, 2, 0x65, 0x88];
+        // sequence header 之前的关键帧无法单独解码
+        sampler.feed(0, &keyframe);
+        sampler.feed(0, &[0x17, 0, 0, 0, 0, 0x01, 0x64, 0x00, 0x1f, 0xff, 0xe1, 0, 2, 0x67, 0x64, 1, 0, 2, 0x68, 0xee]);
+        for timestamp in (0..5000).step_by(1000) {
+            sampler.feed(timestamp, &keyframe);
+            sampler.feed(timestamp + 500, &[0x27, 1, 0, 0, 0, 0, 0, 0, 2, 0x41, 0x9a]);
+        }
+
+        let frames = frames.lock();
+        let timestamps: Vec<u32> = frames.iter().map(|(timestamp, _)| *timestamp).collect();
+        assert_eq!(timestamps, [0, 2000, 4000]);
+        assert_eq!(&frames[0].1[..], &[0, 0, 0, 1, 0x67, 0x64, 0, 0, 0, 1, 0x68, 0xee, 0, 0, 0, 1, 0x65, 0x88]);
+    }
+}
@@ -1,7 +1,19 @@
-
-mod flv_parser;
-mod flv_writer;
-mod flv_donload;
-mod hls_download;
+pub mod error;
+pub mod aac;
+pub mod h264;
+pub mod hevc;
+pub mod keyframe;
+pub mod flv_parser;
+pub mod flv_writer;
+pub mod flv_reader;
+pub mod flv_split;
+pub mod flv_validate;
+pub mod probe;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod flv_donload;
+pub mod pipline;
+pub mod hls_download;
 mod hls_playlist;
-mod hls_parser;
\ No newline at end of file
+mod hls_parser;
+pub use error::{FlvError, Result};
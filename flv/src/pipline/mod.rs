@@ -0,0 +1,22 @@
+pub mod processing_comment;
+pub mod repair_pipeline;
+pub mod rules;
+
+pub use processing_comment::ProcessingComment;
+pub use repair_pipeline::RepairPipeline;
+
+/// 修复 FLV 时记录的问题类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentType {
+    Other,
+    /// 无法修复，只能丢弃数据
+    Unrepairable,
+    TimestampJump,
+    TimestampOffset,
+    /// 解码参数（sequence header）变化
+    DecodingHeader,
+    RepeatingData,
+    /// 音视频 tag 没有按时间戳交错，已重新排序
+    Interleave,
+    OnMetaData,
+}
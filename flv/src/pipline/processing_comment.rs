@@ -0,0 +1,20 @@
+use crate::pipline::CommentType;
+
+/// 修复过程中产生的说明，汇总后写入日志或统计
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessingComment {
+    pub comment_type: CommentType,
+    /// 需要切分文件或丢弃数据等处理
+    pub action_required: bool,
+    pub comment: String,
+}
+
+impl ProcessingComment {
+    pub fn new(comment_type: CommentType, action_required: bool, comment: impl Into<String>) -> Self {
+        Self {
+            comment_type,
+            action_required,
+            comment: comment.into(),
+        }
+    }
+}
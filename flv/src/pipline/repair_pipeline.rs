@@ -0,0 +1,82 @@
+use utils::parking_lot::Mutex;
+use crate::flv_parser::OwnedTag;
+use crate::pipline::rules::{GroupingRule, SplitOnSequenceHeaderChangeRule};
+use crate::pipline::ProcessingComment;
+
+/// 按添加顺序依次执行修复规则，每个规则处理上一个规则的输出
+#[derive(Default)]
+pub struct RepairPipeline {
+    rules: Mutex<Vec<Box<dyn GroupingRule>>>,
+}
+
+impl RepairPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 只检查 sequence header 变化；`RemoveRepeatingDataRule` 可能误删内容相同的正常帧，
+    /// `InterleaveRule` 会改变 tag 顺序，需要时另外添加
+    pub fn standard() -> Self {
+        Self::new().add_rule(Box::new(SplitOnSequenceHeaderChangeRule::new()))
+    }
+
+    pub fn add_rule(self, rule: Box<dyn GroupingRule>) -> Self {
+        self.rules.lock().push(rule);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.lock().is_empty()
+    }
+
+    pub fn process(&self, group: Vec<OwnedTag>) -> (Vec<OwnedTag>, Vec<ProcessingComment>) {
+        let mut comments = Vec::new();
+        let mut rules = self.rules.lock();
+        let group = rules.iter_mut().fold(group, |group, rule| rule.process(group, &mut comments));
+        (group, comments)
+    }
+
+    /// 开始写入新文件后清空各规则的状态
+    pub fn reset(&self) {
+        self.rules.lock().iter_mut().for_each(|rule| rule.reset());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flv_parser::{OwnedTag, OwnedTagData, TagHeader, TagType};
+    use crate::pipline::rules::GroupingRule;
+    use crate::pipline::{CommentType, ProcessingComment};
+    use super::RepairPipeline;
+
+    /// 只保留指定类型的 tag
+    struct KeepOnly(TagType);
+
+    impl GroupingRule for KeepOnly {
+        fn process(&mut self, group: Vec<OwnedTag>, comments: &mut Vec<ProcessingComment>) -> Vec<OwnedTag> {
+            comments.push(ProcessingComment::new(CommentType::Other, false, format!("keep {:?}", self.0)));
+            group.into_iter().filter(|tag| tag.header.tag_type == self.0).collect()
+        }
+    }
+
+    #[test]
+    fn rules_run_in_order() {
+        let script = OwnedTag {
+            header: TagHeader { tag_type: TagType::Script, data_size: 0, timestamp: 0, stream_id: 0 },
+            data: OwnedTagData::Script,
+        };
+        let pipeline = RepairPipeline::new()
+            .add_rule(Box::new(KeepOnly(TagType::Script)))
+            .add_rule(Box::new(KeepOnly(TagType::Audio)));
+        assert_eq!(pipeline.len(), 2);
+
+        let (group, comments) = pipeline.process(vec![script.clone(), script]);
+        assert!(group.is_empty());
+        let comments: Vec<&str> = comments.iter().map(|c| c.comment.as_str()).collect();
+        assert_eq!(comments, vec!["keep Script", "keep Audio"]);
+    }
+}
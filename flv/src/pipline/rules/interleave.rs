@@ -0,0 +1,120 @@
+use crate::flv_parser::OwnedTag;
+use crate::flv_split::{classify_data, TagKind};
+use crate::pipline::rules::GroupingRule;
+use crate::pipline::{CommentType, ProcessingComment};
+
+/// 部分直播流在一个 GOP 内的音视频 tag 没有按时间戳交错，简单的播放器会出错；
+/// 按时间戳稳定排序，onMetaData 和 sequence header 保持原有顺序排在最前。
+/// 会改变 tag 的顺序，需要与输入逐字节一致时不要使用，`RepairPipeline::standard` 不包含这个规则
+#[derive(Debug, Default)]
+pub struct InterleaveRule;
+
+impl InterleaveRule {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl GroupingRule for InterleaveRule {
+    /// 有 tag 被移动时记录一条 `Interleave`
+    fn process(&mut self, group: Vec<OwnedTag>, comments: &mut Vec<ProcessingComment>) -> Vec<OwnedTag> {
+        let mut tags: Vec<_> = group.into_iter().enumerate().map(|(index, tag)| (sort_key(&tag), index, tag)).collect();
+        if tags.windows(2).all(|pair| pair[0].0 <= pair[1].0) {
+            return tags.into_iter().map(|(_, _, tag)| tag).collect();
+        }
+        tags.sort_by_key(|(key, _, _)| *key);
+        let moved = tags.iter().enumerate().filter(|(position, (_, index, _))| position != index).count();
+        comments.push(ProcessingComment::new(
+            CommentType::Interleave,
+            false,
+            format!("Reordered {moved} tags by timestamp"),
+        ));
+        tags.into_iter().map(|(_, _, tag)| tag).collect()
+    }
+}
+
+/// onMetaData 和 sequence header 不参与时间戳比较
+fn sort_key(tag: &OwnedTag) -> (bool, u32) {
+    match classify_data(&tag.as_tag().data) {
+        TagKind::MetaData | TagKind::AudioHeader | TagKind::VideoHeader => (false, 0),
+        TagKind::KeyFrame | TagKind::Other => (true, tag.header.timestamp),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::flv_parser::{
+        CodecId, FrameType, OwnedAudioData, OwnedTag, OwnedTagData, OwnedVideoData, SoundFormat, SoundRate,
+        SoundSize, SoundType, TagHeader, TagType,
+    };
+    use crate::pipline::rules::GroupingRule;
+    use crate::pipline::CommentType;
+    use super::InterleaveRule;
+
+    fn tag(tag_type: TagType, timestamp: u32, data: &'static [u8]) -> OwnedTag {
+        let data = match tag_type {
+            TagType::Audio => OwnedTagData::Audio(OwnedAudioData {
+                sound_format: SoundFormat::AAC,
+                sound_rate: SoundRate::_44KHZ,
+                sound_size: SoundSize::Snd16bit,
+                sound_type: SoundType::SndStereo,
+                ex_packet_type: None,
+                sound_data: Bytes::from_static(data),
+            }),
+            TagType::Video => OwnedTagData::Video(OwnedVideoData {
+                frame_type: if data[0] == 0 { FrameType::Key } else { FrameType::Inter },
+                codec_id: CodecId::H264,
+                ex_packet_type: None,
+                video_data: Bytes::from_static(data),
+            }),
+            TagType::Script => OwnedTagData::Script,
+        };
+        OwnedTag {
+            header: TagHeader { tag_type, data_size: 0, timestamp, stream_id: 0 },
+            data,
+        }
+    }
+
+    fn order(tags: &[OwnedTag]) -> Vec<(TagType, u32)> {
+        tags.iter().map(|tag| (tag.header.tag_type, tag.header.timestamp)).collect()
+    }
+
+    #[test]
+    fn reorder_shuffled_group() {
+        let mut rule = InterleaveRule::new();
+        let mut comments = Vec::new();
+        // sequence header 的时间戳晚于后面的帧，仍然保持在最前
+        let shuffled = vec![
+            tag(TagType::Video, 40, &[0, 0, 0, 0, 1, 0x64]),
+            tag(TagType::Audio, 0, &[0, 0x12, 0x10]),
+            tag(TagType::Video, 0, &[0, 1, 0, 0, 0, 0xaa]),
+            tag(TagType::Video, 80, &[1, 1, 0, 0, 0, 0xbb]),
+            tag(TagType::Audio, 46, &[1, 0xcc]),
+            tag(TagType::Audio, 23, &[1, 0xdd]),
+            tag(TagType::Video, 40, &[1, 1, 0, 0, 0, 0xee]),
+            tag(TagType::Audio, 40, &[1, 0xff]),
+        ];
+        let output = rule.process(shuffled, &mut comments);
+        assert_eq!(order(&output), [
+            (TagType::Video, 40),
+            (TagType::Audio, 0),
+            (TagType::Video, 0),
+            (TagType::Audio, 23),
+            (TagType::Video, 40),
+            (TagType::Audio, 40),
+            (TagType::Audio, 46),
+            (TagType::Video, 80),
+        ]);
+        // 相同时间戳保持原有顺序
+        assert_eq!(output[4].data, tag(TagType::Video, 40, &[1, 1, 0, 0, 0, 0xee]).data);
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].comment_type, CommentType::Interleave);
+        assert_eq!(comments[0].comment, "Reordered 5 tags by timestamp");
+
+        comments.clear();
+        let output = rule.process(output, &mut comments);
+        assert_eq!(output.len(), 8);
+        assert!(comments.is_empty());
+    }
+}
@@ -0,0 +1,18 @@
+use crate::flv_parser::OwnedTag;
+use crate::pipline::ProcessingComment;
+
+pub mod interleave;
+pub mod remove_repeating_data;
+pub mod split_on_sequence_header_change;
+
+pub use interleave::InterleaveRule;
+pub use remove_repeating_data::RemoveRepeatingDataRule;
+pub use split_on_sequence_header_change::SplitOnSequenceHeaderChangeRule;
+
+/// 以 tag 组（通常从一个关键帧开始）为单位的修复规则
+pub trait GroupingRule: Send {
+    fn process(&mut self, group: Vec<OwnedTag>, comments: &mut Vec<ProcessingComment>) -> Vec<OwnedTag>;
+
+    /// 开始新文件时清空状态
+    fn reset(&mut self) {}
+}
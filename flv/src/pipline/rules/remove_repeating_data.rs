@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+use bytes::Bytes;
+use crate::flv_parser::{OwnedTag, OwnedTagData, TagHeader, TagType};
+use crate::pipline::rules::GroupingRule;
+use crate::pipline::{CommentType, ProcessingComment};
+
+/// 默认与前 4 个同类型 tag 比较
+pub const DEFAULT_REPEATING_WINDOW: usize = 4;
+
+/// 编码器卡住时会以相同或倒退的时间戳反复推送同一帧，丢弃与最近 `window` 个同类型 tag 内容相同、
+/// 时间戳又没有前进的音视频 tag；静音等内容相同但时间戳正常前进的帧保留
+#[derive(Debug)]
+pub struct RemoveRepeatingDataRule {
+    window: usize,
+    recent: VecDeque<(TagType, u32, Bytes)>,
+}
+
+impl Default for RemoveRepeatingDataRule {
+    fn default() -> Self {
+        Self::new(DEFAULT_REPEATING_WINDOW)
+    }
+}
+
+impl RemoveRepeatingDataRule {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            recent: VecDeque::with_capacity(window * 2),
+        }
+    }
+}
+
+impl GroupingRule for RemoveRepeatingDataRule {
+    /// 返回去重后的 tag，有丢弃时记录一条带数量的 `RepeatingData`
+    fn process(&mut self, tags: Vec<OwnedTag>, comments: &mut Vec<ProcessingComment>) -> Vec<OwnedTag> {
+        let mut dropped = 0;
+        let mut output = Vec::with_capacity(tags.len());
+        for tag in tags {
+            let payload = match &tag.data {
+                OwnedTagData::Audio(audio) => &audio.sound_data,
+                OwnedTagData::Video(video) => &video.video_data,
+                OwnedTagData::Script => {
+                    output.push(tag);
+                    continue;
+                }
+            };
+            let TagHeader { tag_type, timestamp, .. } = tag.header;
+            let repeated = self.recent.iter()
+                .filter(|(recent_type, ..)| *recent_type == tag_type)
+                .take(self.window)
+                .any(|(_, recent_timestamp, recent)| timestamp <= *recent_timestamp && recent == payload);
+            if repeated {
+                dropped += 1;
+                continue;
+            }
+            self.recent.push_front((tag_type, timestamp, payload.clone()));
+            // 音视频交错，每种类型各保留 `window` 个
+            self.recent.truncate(self.window * 2);
+            output.push(tag);
+        }
+        if dropped > 0 {
+            comments.push(ProcessingComment::new(
+                CommentType::RepeatingData,
+                true,
+                format!("Dropped {dropped} repeating tags"),
+            ));
+        }
+        output
+    }
+
+    fn reset(&mut self) {
+        self.recent.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::flv_parser::{
+        OwnedAudioData, OwnedTag, OwnedTagData, SoundFormat, SoundRate, SoundSize, SoundType, TagHeader, TagType,
+    };
+    use crate::pipline::rules::GroupingRule;
+    use crate::pipline::CommentType;
+    use super::RemoveRepeatingDataRule;
+
+    fn audio(timestamp: u32, data: &'static [u8]) -> OwnedTag {
+        OwnedTag {
+            header: TagHeader {
+                tag_type: TagType::Audio,
+                data_size: data.len() as u32 + 1,
+                timestamp,
+                stream_id: 0,
+            },
+            data: OwnedTagData::Audio(OwnedAudioData {
+                sound_format: SoundFormat::AAC,
+                sound_rate: SoundRate::_44KHZ,
+                sound_size: SoundSize::Snd16bit,
+                sound_type: SoundType::SndStereo,
+                ex_packet_type: None,
+                sound_data: Bytes::from_static(data),
+            }),
+        }
+    }
+
+    #[test]
+    fn drop_repeats_within_window() {
+        let mut rule = RemoveRepeatingDataRule::new(2);
+        let mut comments = Vec::new();
+        // 卡住的编码器重复推送时间戳不变的帧
+        let tags = vec![
+            audio(0, b"\x01a"),
+            audio(0, b"\x01a"),
+            audio(23, b"\x01b"),
+            audio(0, b"\x01a"),
+            audio(46, b"\x01c"),
+            audio(69, b"\x01d"),
+            audio(0, b"\x01a"),
+        ];
+        let output = rule.process(tags, &mut comments);
+        let timestamps: Vec<u32> = output.iter().map(|tag| tag.header.timestamp).collect();
+        // 第 4 个仍在窗口内，最后一个已经超出窗口
+        assert_eq!(timestamps, vec![0, 23, 46, 69, 0]);
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].comment_type, CommentType::RepeatingData);
+        assert_eq!(comments[0].comment, "Dropped 2 repeating tags");
+
+        comments.clear();
+        assert_eq!(rule.process(vec![audio(161, b"\x01e")], &mut comments).len(), 1);
+        assert!(comments.is_empty());
+    }
+
+    #[test]
+    fn keep_identical_frames_at_advancing_timestamps() {
+        // 静音时 AAC 编码器输出的帧完全相同，但时间戳正常前进
+        let mut rule = RemoveRepeatingDataRule::default();
+        let mut comments = Vec::new();
+        let tags: Vec<_> = (0..10).map(|i| audio(i * 23, b"\x01\x21\x10\x04\x60\x8c\x1c")).collect();
+        assert_eq!(rule.process(tags, &mut comments).len(), 10);
+        assert!(comments.is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use bytes::Bytes;
+use crate::flv_parser::{avc_video_packet_header, AVCPacketType, CodecId, ExVideoPacketType, OwnedTag, Tag, TagData};
+use crate::pipline::rules::GroupingRule;
+use crate::pipline::{CommentType, ProcessingComment};
+
+/// 直播中途切换分辨率等会推送新的 sequence header，SPS/PPS 真正改变时需要切分文件；
+/// 内容相同的重复 sequence header 不处理
+#[derive(Debug, Default)]
+pub struct SplitOnSequenceHeaderChangeRule {
+    last_header: Option<Bytes>,
+}
+
+impl SplitOnSequenceHeaderChangeRule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 返回 `true` 表示应在这个 tag 之前开始新文件
+    pub fn check(&mut self, tag: &Tag, comments: &mut Vec<ProcessingComment>) -> bool {
+        let Some(record) = decoder_configuration_record(tag) else {
+            return false;
+        };
+        let changed = match &self.last_header {
+            Some(last) if last.as_ref() == record => return false,
+            Some(_) => true,
+            None => false,
+        };
+        self.last_header = Some(Bytes::copy_from_slice(record));
+        if changed {
+            comments.push(ProcessingComment::new(
+                CommentType::DecodingHeader,
+                true,
+                format!("Video sequence header changed at {}ms, split file", tag.header.timestamp),
+            ));
+        }
+        changed
+    }
+
+}
+
+/// 只记录需要切分的 `DecodingHeader`，由调用方在这组 tag 之前开始新文件
+impl GroupingRule for SplitOnSequenceHeaderChangeRule {
+    fn process(&mut self, group: Vec<OwnedTag>, comments: &mut Vec<ProcessingComment>) -> Vec<OwnedTag> {
+        for tag in &group {
+            self.check(&tag.as_tag(), comments);
+        }
+        group
+    }
+
+    /// 切分后新文件重新开始比较
+    fn reset(&mut self) {
+        self.last_header = None;
+    }
+}
+
+/// 视频 sequence header 中的 AVC/HEVC DecoderConfigurationRecord
+fn decoder_configuration_record<'a>(tag: &Tag<'a>) -> Option<&'a [u8]> {
+    let TagData::Video(video) = &tag.data else {
+        return None;
+    };
+    match video.ex_packet_type {
+        Some(ExVideoPacketType::SequenceStart) => Some(video.video_data),
+        Some(_) => None,
+        None if matches!(video.codec_id, CodecId::H264 | CodecId::HEVC) => {
+            let (record, header) = avc_video_packet_header(video.video_data).ok()?;
+            (header.packet_type == AVCPacketType::SequenceHeader).then_some(record)
+        }
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::flv_parser::{
+        CodecId, FrameType, OwnedTag, OwnedTagData, OwnedVideoData, TagHeader, TagType,
+    };
+    use crate::pipline::rules::GroupingRule;
+    use crate::pipline::CommentType;
+    use super::SplitOnSequenceHeaderChangeRule;
+
+    fn sequence_header(timestamp: u32, sps: &[u8]) -> OwnedTag {
+        // AVCPacketType + CompositionTime，之后是 AVCDecoderConfigurationRecord
+        let mut data = vec![0, 0, 0, 0, 1, sps[1], sps[2], sps[3], 0xff, 0xe1];
+        data.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        data.extend_from_slice(sps);
+        data.extend_from_slice(&[1, 0, 4, 0x68, 0xee, 0x3c, 0x80]);
+        OwnedTag {
+            header: TagHeader {
+                tag_type: TagType::Video,
+                data_size: data.len() as u32 + 1,
+                timestamp,
+                stream_id: 0,
+            },
+            data: OwnedTagData::Video(OwnedVideoData {
+                frame_type: FrameType::Key,
+                codec_id: CodecId::H264,
+                ex_packet_type: None,
+                video_data: Bytes::from(data),
+            }),
+        }
+    }
+
+    #[test]
+    fn split_only_on_change() {
+        let sps_720p = [0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50, 0x05, 0xbb, 0x01];
+        let sps_1080p = [0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5];
+        let mut rule = SplitOnSequenceHeaderChangeRule::new();
+        let mut comments = Vec::new();
+
+        assert!(!rule.check(&sequence_header(0, &sps_720p).as_tag(), &mut comments));
+        assert!(!rule.check(&sequence_header(1000, &sps_720p).as_tag(), &mut comments));
+        assert!(comments.is_empty());
+
+        assert!(rule.check(&sequence_header(2000, &sps_1080p).as_tag(), &mut comments));
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].comment_type, CommentType::DecodingHeader);
+        assert!(!rule.check(&sequence_header(3000, &sps_1080p).as_tag(), &mut comments));
+
+        rule.reset();
+        assert!(!rule.check(&sequence_header(4000, &sps_720p).as_tag(), &mut comments));
+        assert_eq!(comments.len(), 1);
+    }
+}
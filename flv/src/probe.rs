@@ -0,0 +1,246 @@
+use serde::Serialize;
+use crate::error::{FlvError, Result};
+use crate::flv_donload::{map_parse_err, FlvConnection};
+use crate::flv_parser::{
+    aac_audio_packet, avc_video_packet_header, header, parse_audio_specific_config,
+    parse_script_data, tag_data, tag_header, video_data, AACPacketType, AVCPacketType, AudioData,
+    CodecId, ExAudioPacketType, ExVideoPacketType, ScriptDataLimits, ScriptDataObject,
+    ScriptDataValue, SoundFormat, SoundRate, SoundType, TagData, TagType, VideoData,
+};
+use crate::h264::config_sps;
+use crate::hevc;
+
+/// 最多读取的 tag 数，之后仍没有视频 sequence header 就放弃
+pub const PROBE_MAX_TAGS: usize = 200;
+
+/// 录制前探测到的流参数
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StreamProbe {
+    pub codec: CodecId,
+    /// H.264 取自 SPS，其它编码取自 onMetaData，都没有时为 0
+    pub width: u32,
+    pub height: u32,
+    pub fps: Option<f64>,
+    pub audio: Option<AudioProbe>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AudioProbe {
+    pub sound_format: SoundFormat,
+    pub sample_rate: u32,
+    pub channels: u8,
+}
+
+#[derive(Default)]
+struct Metadata {
+    width: Option<f64>,
+    height: Option<f64>,
+    framerate: Option<f64>,
+}
+
+impl Metadata {
+    fn update(&mut self, objects: &[ScriptDataObject]) {
+        for object in objects {
+            let ScriptDataValue::Number(value) = object.data else {
+                continue;
+            };
+            match object.name {
+                "width" => self.width = Some(value),
+                "height" => self.height = Some(value),
+                "framerate" => self.framerate = Some(value),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// 从连接开头读取 FLV 头、onMetaData 和音视频 sequence header，读到足够信息后立即返回，
+/// 不会读取整个流；调用方随后丢弃连接即可
+pub async fn probe_flv(connection: &mut FlvConnection) -> Result<StreamProbe> {
+    let header_bytes = connection.read_frame(9).await?;
+    let (_, flv_header) = header(&header_bytes).map_err(|_| FlvError::InvalidData("flv header".to_string()))?;
+    connection.read_frame(flv_header.offset.saturating_sub(9) as usize + 4).await?;
+
+    let mut metadata = Metadata::default();
+    let mut video: Option<StreamProbe> = None;
+    let mut audio: Option<AudioProbe> = None;
+    for _ in 0..PROBE_MAX_TAGS {
+        let tag_header_bytes = connection.read_frame(11).await?;
+        if tag_header_bytes.len() < 11 {
+            break;
+        }
+        let (_, tag_header) = map_parse_err(tag_header(&tag_header_bytes), "tag header")?;
+        let bytes = connection.read_frame(tag_header.data_size as usize).await?;
+        connection.read_frame(4).await?;
+        let Ok((i, data)) = tag_data(tag_header.tag_type, bytes.len())(&bytes) else {
+            continue;
+        };
+        match data {
+            TagData::Script => match parse_script_data(i, &ScriptDataLimits::default()) {
+                Ok(script) => match &script.arguments {
+                    ScriptDataValue::ECMAArray(objects) | ScriptDataValue::Object(objects) => metadata.update(objects),
+                    _ => {}
+                },
+                Err(_) => continue,
+            },
+            TagData::Audio(data) if audio.is_none() => audio = audio_probe(&data),
+            TagData::Video(data) if video.is_none() => {
+                let Some(config) = sequence_header(&data) else {
+                    continue;
+                };
+                let (resolution, fps) = match data.codec_id {
+                    CodecId::H264 => match config_sps(config) {
+                        Ok(sps) => (Some((sps.width, sps.height)), sps.fps),
+                        Err(_) => (None, None),
+                    },
+                    CodecId::HEVC => (hevc::extract_resolution(config).ok(), None),
+                    _ => (None, None),
+                };
+                let (width, height) = resolution.unwrap_or_default();
+                video = Some(StreamProbe { codec: data.codec_id, width, height, fps, audio: None });
+            }
+            _ => {}
+        }
+        if video.is_some() && (audio.is_some() || !flv_header.has_audio()) {
+            break;
+        }
+    }
+
+    let mut probe = video.ok_or_else(|| FlvError::InvalidData("stream without video sequence header".to_string()))?;
+    if probe.width == 0 || probe.height == 0 {
+        probe.width = metadata.width.unwrap_or_default() as u32;
+        probe.height = metadata.height.unwrap_or_default() as u32;
+    }
+    probe.fps = probe.fps.or(metadata.framerate);
+    probe.audio = audio;
+    Ok(probe)
+}
+
+/// 视频 sequence header 中的 AVC/HEVC DecoderConfigurationRecord，其它 tag 返回 `None`
+fn sequence_header<'a>(data: &VideoData<'a>) -> Option<&'a [u8]> {
+    match data.ex_packet_type {
+        Some(packet_type) => (packet_type == ExVideoPacketType::SequenceStart).then_some(data.video_data),
+        None => matches!(data.codec_id, CodecId::H264 | CodecId::HEVC)
+            .then(|| avc_video_packet_header(data.video_data).ok())
+            .flatten()
+            .filter(|(_, header)| header.packet_type == AVCPacketType::SequenceHeader)
+            .map(|(config, _)| config),
+    }
+}
+
+/// 从完整的视频 tag 数据（不含 11 字节 tag 头）得到 H.264/HEVC 的 (宽, 高)；
+/// 不是视频 tag、不是 sequence header 或其它编码时返回 `Ok(None)`，sequence header 无法解析时返回错误
+pub fn resolution_from_video_tag(tag_type: TagType, body: &[u8]) -> Result<Option<(u32, u32)>> {
+    if tag_type != TagType::Video {
+        return Ok(None);
+    }
+    let Ok((_, data)) = video_data(body, body.len()) else {
+        return Ok(None);
+    };
+    let Some(config) = sequence_header(&data) else {
+        return Ok(None);
+    };
+    match data.codec_id {
+        CodecId::H264 => config_sps(config).map(|sps| Some((sps.width, sps.height))),
+        CodecId::HEVC => hevc::extract_resolution(config).map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn audio_probe(data: &AudioData) -> Option<AudioProbe> {
+    if data.sound_format == SoundFormat::AAC {
+        // AAC 的参数只在 sequence header 中，tag 头中的采样率固定为 44KHz
+        let config = match data.ex_packet_type {
+            Some(ExAudioPacketType::SequenceStart) => data.sound_data,
+            Some(_) => return None,
+            None => {
+                let (_, packet) = aac_audio_packet(data.sound_data, data.sound_data.len()).ok()?;
+                if packet.packet_type != AACPacketType::SequenceHeader {
+                    return None;
+                }
+                packet.aac_data
+            }
+        };
+        let config = parse_audio_specific_config(config).ok()?;
+        return Some(AudioProbe {
+            sound_format: SoundFormat::AAC,
+            sample_rate: config.sampling_frequency,
+            channels: config.channel_configuration,
+        });
+    }
+    if data.ex_packet_type.is_some() {
+        return None;
+    }
+    Some(AudioProbe {
+        sound_format: data.sound_format,
+        sample_rate: match data.sound_rate {
+            SoundRate::_5_5KHZ => 5512,
+            SoundRate::_11KHZ => 11025,
+            SoundRate::_22KHZ => 22050,
+            SoundRate::_44KHZ => 44100,
+        },
+        channels: match data.sound_type {
+            SoundType::SndMono => 1,
+            SoundType::SndStereo => 2,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::flv_donload::FlvConnection;
+    use crate::flv_parser::{CodecId, SoundFormat, TagType};
+    use crate::flv_writer::RawFlvTag;
+    use super::{probe_flv, resolution_from_video_tag, AudioProbe};
+
+    fn tag(tag_type: TagType, body: Vec<u8>) -> Bytes {
+        RawFlvTag { tag_type, timestamp: 0, header: Bytes::new(), payload: body.into() }.marshal()
+    }
+
+    #[tokio::test]
+    async fn probe_hevc_from_metadata() {
+        let mut stream = vec![b'F', b'L', b'V', 1, 5, 0, 0, 0, 9, 0, 0, 0, 0];
+        let mut meta = b"\x02\x00\x0aonMetaData\x08\x00\x00\x00\x02".to_vec();
+        for (name, value) in [("width", 1280.0f64), ("height", 720.0)] {
+            meta.extend((name.len() as u16).to_be_bytes());
+            meta.extend(name.as_bytes());
+            meta.push(0);
+            meta.extend(value.to_be_bytes());
+        }
+        meta.extend([0, 0, 9]);
+        stream.extend(tag(TagType::Script, meta));
+        // AAC LC，48kHz，双声道
+        stream.extend(tag(TagType::Audio, vec![0xaf, 0, 0x11, 0x90]));
+        stream.extend(tag(TagType::Video, vec![0x1c, 0, 0, 0, 0, 1, 2, 3]));
+        stream.extend(tag(TagType::Video, vec![0x1c, 1, 0, 0, 0, 0xaa]));
+
+        let response = reqwest::Response::from(http::Response::new(stream));
+        let probe = probe_flv(&mut FlvConnection::new(response)).await.unwrap();
+        assert_eq!(probe.codec, CodecId::HEVC);
+        assert_eq!((probe.width, probe.height), (1280, 720));
+        assert_eq!(probe.fps, None);
+        assert_eq!(probe.audio, Some(AudioProbe { sound_format: SoundFormat::AAC, sample_rate: 48000, channels: 2 }));
+    }
+
+    #[test]
+    fn resolution_from_sequence_header_tag() {
+        // x264 的 1280x720 High profile SPS
+        let sps = [
+            0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50, 0x05, 0xbb, 0x01, 0x10, 0x00, 0x00, 0x03, 0x00,
+            0x10, 0x00, 0x00, 0x03, 0x03, 0xc0, 0xf1, 0x83, 0x19, 0x60,
+        ];
+        let mut body = vec![0x17, 0, 0, 0, 0, 0x01, 0x64, 0x00, 0x1f, 0xff, 0xe1];
+        body.extend((sps.len() as u16).to_be_bytes());
+        body.extend(sps);
+        body.extend([1, 0, 2, 0x68, 0xee]);
+        assert_eq!(resolution_from_video_tag(TagType::Video, &body).unwrap(), Some((1280, 720)));
+
+        // 普通帧、音频 tag 和其它编码都不是错误
+        assert_eq!(resolution_from_video_tag(TagType::Video, &[0x17, 1, 0, 0, 0, 0xaa]).unwrap(), None);
+        assert_eq!(resolution_from_video_tag(TagType::Audio, &body).unwrap(), None);
+        assert_eq!(resolution_from_video_tag(TagType::Video, &[0x14, 0, 0, 0, 0, 1]).unwrap(), None);
+        // 无法解析的 HEVC sequence header
+        assert!(resolution_from_video_tag(TagType::Video, &[0x1c, 0, 0, 0, 0, 1, 2, 3]).is_err());
+    }
+}
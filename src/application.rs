@@ -1,5 +1,14 @@
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, OnceLock};
+use notify::RecommendedWatcher;
+use sysinfo::{get_current_pid, System};
 use utils::chrono::{DateTime, Local};
-use crate::settings::SettingsManager;
+use utils::parking_lot::Mutex;
+use utils::tracing::info;
+use utils::BResult;
+use crate::settings::{SettingsEvent, SettingsManager};
+use crate::task::Manager;
 
 pub struct AppInfo {
     name: String,
@@ -7,13 +16,112 @@ pub struct AppInfo {
     create_time: DateTime<Local>
 }
 
+impl AppInfo {
+    pub fn new() -> Self {
+        Self {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            create_time: Local::now(),
+        }
+    }
+}
+
+impl Default for AppInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct AppStatus {
     cpu_percent: f64,
     memory_percent: f64,
-    num_threads: u8
+    num_threads: u32
+}
+
+impl AppStatus {
+    /// 采样当前进程的状态，cpu 占用按两次采样的间隔计算，第一次采样为 0
+    pub fn sample() -> AppStatus {
+        static SYSTEM: OnceLock<Mutex<System>> = OnceLock::new();
+        let mut system = SYSTEM.get_or_init(|| Mutex::new(System::new())).lock();
+        let Ok(pid) = get_current_pid() else {
+            return AppStatus { cpu_percent: 0.0, memory_percent: 0.0, num_threads: 0 };
+        };
+        system.refresh_memory();
+        system.refresh_process(pid);
+        let Some(process) = system.process(pid) else {
+            return AppStatus { cpu_percent: 0.0, memory_percent: 0.0, num_threads: 0 };
+        };
+        let total_memory = system.total_memory();
+        let memory_percent = if total_memory > 0 {
+            process.memory() as f64 / total_memory as f64 * 100.0
+        } else {
+            0.0
+        };
+        AppStatus {
+            cpu_percent: process.cpu_usage() as f64,
+            memory_percent,
+            // 非 Linux 平台无法获取线程数
+            num_threads: process.tasks().map_or(1, |tasks| tasks.len() as u32),
+        }
+    }
 }
 
 pub struct Application {
-    settings_manager: SettingsManager,
-    task_manager: 
-}
\ No newline at end of file
+    info: AppInfo,
+    settings_manager: Arc<Mutex<SettingsManager>>,
+    task_manager: Manager,
+    settings_watcher: Option<(RecommendedWatcher, Receiver<SettingsEvent>)>,
+}
+
+impl Default for Application {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Application {
+    pub fn new() -> Self {
+        // settings_manager 与 task_manager 共享同一份配置
+        let settings_manager = Arc::new(Mutex::new(SettingsManager::default()));
+        Self {
+            info: AppInfo::new(),
+            task_manager: Manager::new(settings_manager.clone()),
+            settings_manager,
+            settings_watcher: None,
+        }
+    }
+
+    /// 修改配置文件后无需重启，变化通过 `apply_settings_events` 应用到任务
+    pub fn watch_settings(&mut self, path: impl AsRef<Path>) -> BResult<()> {
+        self.settings_watcher = Some(SettingsManager::watch(&self.settings_manager, path)?);
+        Ok(())
+    }
+
+    /// 应用并返回监听到的配置变化
+    pub async fn apply_settings_events(&mut self) -> Vec<SettingsEvent> {
+        let Some((_, receiver)) = &self.settings_watcher else {
+            return Vec::new();
+        };
+        let events: Vec<SettingsEvent> = receiver.try_iter().collect();
+        for event in &events {
+            self.task_manager.apply_settings_event(event).await;
+        }
+        events
+    }
+
+    pub fn start(&mut self) -> BResult<()> {
+        info!("Starting {} v{} ...", self.info.name, self.info.version);
+        self.task_manager.load_all_tasks()?;
+        Ok(())
+    }
+
+    /// 先停止所有录制，等它们关闭并重命名当前文件后再移除任务
+    pub async fn shutdown(&mut self) -> BResult<()> {
+        self.settings_watcher = None;
+        self.task_manager.stop_all().await;
+        let count = self.task_manager.remove_all_tasks();
+        info!("{} stopped, {} tasks removed", self.info.name, count);
+        Ok(())
+    }
+}
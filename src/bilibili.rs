@@ -1,3 +1,5 @@
 pub mod models;
+pub mod danmaku;
+pub mod danmaku_writer;
 mod live;
 mod api;
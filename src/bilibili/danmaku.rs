@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// 弹幕服务器推送的消息，只解析录制需要的几种命令
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DanmakuMessage {
+    Danmu {
+        uid: i64,
+        uname: String,
+        text: String,
+        mode: i32,
+        font_size: i32,
+        color: u32,
+        timestamp: i64, // 毫秒
+    },
+    Gift {
+        uid: i64,
+        uname: String,
+        gift_name: String,
+        num: i64,
+        coin_type: String, // gold 或 silver（免费礼物）
+        total_coin: i64,
+        timestamp: i64,
+    },
+    GuardBuy {
+        uid: i64,
+        uname: String,
+        gift_name: String,
+        guard_level: i32,
+        num: i64,
+        price: i64,
+        timestamp: i64,
+    },
+    SuperChat {
+        uid: i64,
+        uname: String,
+        message: String,
+        price: i64,
+        duration: i64,
+        timestamp: i64,
+    },
+    Other(String),
+}
+
+/// 解析一条命令，`cmd` 可以带有 `DANMU_MSG:4:0:2:2:2:0` 这样的后缀；
+/// 未知命令返回 `Other`，已知命令的数据不完整时返回 `None`
+pub fn parse_command(cmd: &str, json: &Value) -> Option<DanmakuMessage> {
+    let name = cmd.split(':').next().unwrap_or(cmd);
+    let as_i64 = |v: &Value| v.as_i64().unwrap_or_default();
+    let as_string = |v: &Value| v.as_str().unwrap_or_default().to_string();
+    match name {
+        "DANMU_MSG" => {
+            let info = json["info"].as_array()?;
+            let meta = info.first()?.as_array()?;
+            let user = info.get(2)?.as_array()?;
+            Some(DanmakuMessage::Danmu {
+                uid: user.first()?.as_i64()?,
+                uname: as_string(user.get(1)?),
+                text: info.get(1)?.as_str()?.to_string(),
+                mode: as_i64(meta.get(1)?) as i32,
+                font_size: as_i64(meta.get(2)?) as i32,
+                color: as_i64(meta.get(3)?) as u32,
+                timestamp: as_i64(meta.get(4)?),
+            })
+        }
+        "SEND_GIFT" => {
+            let data = json.get("data")?;
+            Some(DanmakuMessage::Gift {
+                uid: data["uid"].as_i64()?,
+                uname: as_string(&data["uname"]),
+                gift_name: data["giftName"].as_str()?.to_string(),
+                num: as_i64(&data["num"]),
+                coin_type: as_string(&data["coin_type"]),
+                total_coin: as_i64(&data["total_coin"]),
+                timestamp: as_i64(&data["timestamp"]),
+            })
+        }
+        "GUARD_BUY" => {
+            let data = json.get("data")?;
+            Some(DanmakuMessage::GuardBuy {
+                uid: data["uid"].as_i64()?,
+                uname: as_string(&data["username"]),
+                gift_name: as_string(&data["gift_name"]),
+                guard_level: data["guard_level"].as_i64()? as i32,
+                num: as_i64(&data["num"]),
+                price: as_i64(&data["price"]),
+                timestamp: as_i64(&data["start_time"]),
+            })
+        }
+        "SUPER_CHAT_MESSAGE" => {
+            let data = json.get("data")?;
+            Some(DanmakuMessage::SuperChat {
+                uid: data["uid"].as_i64()?,
+                uname: as_string(&data["user_info"]["uname"]),
+                message: data["message"].as_str()?.to_string(),
+                price: as_i64(&data["price"]),
+                duration: as_i64(&data["time"]),
+                timestamp: as_i64(&data["start_time"]),
+            })
+        }
+        _ => Some(DanmakuMessage::Other(name.to_string())),
+    }
+}
+
+/// 按任务的弹幕设置决定哪些消息需要记录
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DanmakuFilter {
+    pub record_gift_send: bool,
+    pub record_free_gifts: bool,
+    pub record_guard_buy: bool,
+    pub record_super_chat: bool,
+}
+
+impl DanmakuFilter {
+    pub fn accepts(&self, message: &DanmakuMessage) -> bool {
+        match message {
+            DanmakuMessage::Danmu { .. } => true,
+            DanmakuMessage::Gift { coin_type, .. } => {
+                self.record_gift_send && (coin_type != "silver" || self.record_free_gifts)
+            }
+            DanmakuMessage::GuardBuy { .. } => self.record_guard_buy,
+            DanmakuMessage::SuperChat { .. } => self.record_super_chat,
+            DanmakuMessage::Other(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::{parse_command, DanmakuFilter, DanmakuMessage};
+
+    #[test]
+    fn parse_and_filter() {
+        let danmu = json!({
+            "cmd": "DANMU_MSG:4:0:2:2:2:0",
+            "info": [[0, 1, 25, 16777215, 1700000000000i64], "hello", [42, "viewer"]],
+        });
+        let message = parse_command("DANMU_MSG:4:0:2:2:2:0", &danmu).unwrap();
+        assert_eq!(
+            message,
+            DanmakuMessage::Danmu {
+                uid: 42,
+                uname: "viewer".to_string(),
+                text: "hello".to_string(),
+                mode: 1,
+                font_size: 25,
+                color: 16777215,
+                timestamp: 1700000000000,
+            }
+        );
+
+        let gift = json!({"data": {"uid": 42, "uname": "viewer", "giftName": "辣条", "num": 3, "coin_type": "silver", "total_coin": 0, "timestamp": 1700000000}});
+        let gift = parse_command("SEND_GIFT", &gift).unwrap();
+        let super_chat = json!({"data": {"uid": 42, "user_info": {"uname": "viewer"}, "message": "hi", "price": 30, "time": 60, "start_time": 1700000000}});
+        let super_chat = parse_command("SUPER_CHAT_MESSAGE", &super_chat).unwrap();
+        assert_eq!(parse_command("ONLINE_RANK_COUNT", &json!({})), Some(DanmakuMessage::Other("ONLINE_RANK_COUNT".to_string())));
+        assert_eq!(parse_command("SEND_GIFT", &json!({})), None);
+
+        let filter = DanmakuFilter {
+            record_gift_send: true,
+            record_super_chat: true,
+            ..Default::default()
+        };
+        assert!(filter.accepts(&message));
+        assert!(!filter.accepts(&gift));
+        assert!(filter.accepts(&super_chat));
+        assert!(DanmakuFilter { record_free_gifts: true, ..filter }.accepts(&gift));
+    }
+}
@@ -0,0 +1,177 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use serde_json::Value;
+use utils::parking_lot::Mutex;
+use utils::tokio;
+use utils::tokio::task::JoinHandle;
+use crate::bilibili::danmaku::DanmakuMessage;
+
+const XML_HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<i>\n<chatserver>chat.bilibili.com</chatserver>\n<chatid>0</chatid>\n";
+const XML_FOOTER: &str = "</i>\n";
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DanmakuWriterOptions {
+    /// 在弹幕内容前加上用户名
+    pub danmu_uname: bool,
+    /// 同时把原始消息写入同名的 `.jsonl` 文件
+    pub save_raw_danmaku: bool,
+}
+
+/// 以 B 站 XML 格式保存弹幕，时间为相对于录制开始的秒数
+pub struct DanmakuWriter {
+    path: PathBuf,
+    xml: BufWriter<File>,
+    raw: Option<BufWriter<File>>,
+    options: DanmakuWriterOptions,
+    closed: bool,
+}
+
+impl DanmakuWriter {
+    pub fn create(path: impl AsRef<Path>, options: DanmakuWriterOptions) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut xml = BufWriter::new(File::create(&path)?);
+        xml.write_all(XML_HEADER.as_bytes())?;
+        let raw = if options.save_raw_danmaku {
+            Some(BufWriter::new(File::create(path.with_extension("jsonl"))?))
+        } else {
+            None
+        };
+        Ok(Self { path, xml, raw, options, closed: false })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `time` 为消息相对于录制开始的秒数，`raw` 为服务器推送的原始 JSON
+    pub fn write(&mut self, message: &DanmakuMessage, time: f64, raw: Option<&Value>) -> std::io::Result<()> {
+        if let (Some(writer), Some(raw)) = (self.raw.as_mut(), raw) {
+            serde_json::to_writer(&mut *writer, raw)?;
+            writer.write_all(b"\n")?;
+        }
+        let line = match message {
+            DanmakuMessage::Danmu { uid, uname, text, mode, font_size, color, timestamp } => {
+                let text = if self.options.danmu_uname {
+                    format!("{}: {}", uname, text)
+                } else {
+                    text.clone()
+                };
+                format!(
+                    "<d p=\"{:.3},{},{},{},{},0,{},0\">{}</d>\n",
+                    time, mode, font_size, color, timestamp / 1000, uid, escape(&text)
+                )
+            }
+            DanmakuMessage::Gift { uid, uname, gift_name, num, .. } => format!(
+                "<gift ts=\"{:.3}\" uid=\"{}\" user=\"{}\" giftname=\"{}\" giftcount=\"{}\"/>\n",
+                time, uid, escape(uname), escape(gift_name), num
+            ),
+            DanmakuMessage::GuardBuy { uid, uname, guard_level, num, .. } => format!(
+                "<guard ts=\"{:.3}\" uid=\"{}\" user=\"{}\" level=\"{}\" count=\"{}\"/>\n",
+                time, uid, escape(uname), guard_level, num
+            ),
+            DanmakuMessage::SuperChat { uid, uname, message, price, duration, .. } => format!(
+                "<sc ts=\"{:.3}\" uid=\"{}\" user=\"{}\" price=\"{}\" time=\"{}\">{}</sc>\n",
+                time, uid, escape(uname), price, duration, escape(message)
+            ),
+            DanmakuMessage::Other(_) => return Ok(()),
+        };
+        self.xml.write_all(line.as_bytes())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.xml.flush()?;
+        if let Some(raw) = self.raw.as_mut() {
+            raw.flush()?;
+        }
+        Ok(())
+    }
+
+    /// 写入结尾的 `</i>`，之后不能再写入
+    pub fn close(&mut self) -> std::io::Result<()> {
+        if !self.closed {
+            self.closed = true;
+            self.xml.write_all(XML_FOOTER.as_bytes())?;
+        }
+        self.flush()
+    }
+}
+
+impl Drop for DanmakuWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            utils::tracing::error!("close danmaku file {} failed: {}", self.path.display(), e)
+        }
+    }
+}
+
+/// 定时刷新缓冲，崩溃时最多丢失 `period` 内的弹幕；writer 被释放后任务自动结束
+pub fn spawn_flush_timer(writer: &Arc<Mutex<DanmakuWriter>>, period: Duration) -> JoinHandle<()> {
+    let writer: Weak<Mutex<DanmakuWriter>> = Arc::downgrade(writer);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let Some(writer) = writer.upgrade() else {
+                break;
+            };
+            let mut writer = writer.lock();
+            if let Err(e) = writer.flush() {
+                utils::tracing::error!("flush danmaku file {} failed: {}", writer.path().display(), e)
+            }
+        }
+    })
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // XML 1.0 不允许的控制字符
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::bilibili::danmaku::DanmakuMessage;
+    use super::{DanmakuWriter, DanmakuWriterOptions};
+
+    #[test]
+    fn write_xml_and_raw() {
+        let path = std::env::temp_dir().join(format!("blzbj-danmaku-{}.xml", std::process::id()));
+        let options = DanmakuWriterOptions { danmu_uname: true, save_raw_danmaku: true };
+        let mut writer = DanmakuWriter::create(&path, options).unwrap();
+        let message = DanmakuMessage::Danmu {
+            uid: 42,
+            uname: "viewer".to_string(),
+            text: "<3 & hi".to_string(),
+            mode: 1,
+            font_size: 25,
+            color: 16777215,
+            timestamp: 1700000000123,
+        };
+        writer.write(&message, 1.5, Some(&json!({"cmd": "DANMU_MSG"}))).unwrap();
+        writer.write(&DanmakuMessage::Other("ONLINE_RANK_COUNT".to_string()), 2.0, None).unwrap();
+        drop(writer);
+
+        let xml = std::fs::read_to_string(&path).unwrap();
+        assert!(xml.contains("<d p=\"1.500,1,25,16777215,1700000000,0,42,0\">viewer: &lt;3 &amp; hi</d>\n"));
+        assert!(xml.ends_with("</i>\n"));
+        let raw = std::fs::read_to_string(path.with_extension("jsonl")).unwrap();
+        assert_eq!(raw, "{\"cmd\":\"DANMU_MSG\"}\n");
+        std::fs::remove_file(path.with_extension("jsonl")).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,17 +1,16 @@
+use std::sync::Arc;
 use serde::Deserialize;
+use blbl::client::build_http_client;
 use utils::{reqwest, TError};
-use utils::error::LiveError;
+use utils::error::{ApiRequestError, LiveError};
 use utils::reqwest::Client;
 use crate::bilibili::api::{BaseApi, WebApi};
-use crate::bilibili::models::{RoomInfo, UserInfo};
+use crate::bilibili::models::{LiveStatus, RoomInfo, UserInfo};
 
 
 #[derive(Debug, Deserialize)]
 struct ResponseData {}
 
-#[derive(Debug, Deserialize)]
-struct LiveStatus(i32);
-
 struct Live {
     room_id: i32,
     room_info: Option<RoomInfo>,
@@ -21,29 +20,38 @@ struct Live {
 }
 
 impl Live {
-    pub fn new(room_id: i32, user_agent: String, cookie: String) -> Self {
-        let client = Client::builder().build().unwrap();
+    /// `client` 为空时单独创建一个，录制多个直播间时应传入同一个以复用连接
+    pub fn new(
+        room_id: i32,
+        user_agent: String,
+        cookie: String,
+        client: Option<Arc<Client>>,
+    ) -> Result<Self, ApiRequestError> {
+        let client = client.unwrap_or_else(build_http_client);
         let headers = Self::update_headers(room_id, &user_agent, &cookie);
-        Self {
+        Ok(Self {
             room_id,
             room_info: None,
             user_info: None,
             no_flv_stream: false,
-            webapi: WebApi::new(client, headers, Some(room_id)),
-        }
+            webapi: WebApi::new(client, headers, Some(room_id))?,
+        })
     }
 
     fn update_headers(room_id: i32, user_agent: &str, cookie: &str) -> reqwest::header::HeaderMap {
         let mut headers = reqwest::header::HeaderMap::new();
         headers.insert("Referer", format!("https://live.bilibili.com/{}", room_id).parse().unwrap());
         headers.insert("User-Agent", user_agent.parse().unwrap());
-        headers.insert("Cookie", cookie.parse().unwrap());
+        let mut cookie: reqwest::header::HeaderValue = cookie.parse().unwrap();
+        cookie.set_sensitive(true);
+        headers.insert("Cookie", cookie);
         headers
     }
 
     async fn init(&mut self) -> Result<(), LiveError> {
-        self.room_info = Some(self.get_room_info().await?);
-        self.user_info = Some(self.get_user_info(&self.room_info.unwrap().uid).await?);
+        let (room_info, user_info) = self.get_room_info().await?;
+        self.user_info = Some(user_info);
+        self.room_info = Some(room_info);
 
         if self.is_living() {
             let streams = self.get_live_streams(None).await?;
@@ -61,22 +69,23 @@ impl Live {
     }
 
     fn is_living(&self) -> bool {
-        matches!(self.room_info, Some(RoomInfo { live_status: 1, .. }))
+        self.room_info.as_ref().map_or(false, RoomInfo::is_living)
     }
 
     async fn get_live_status(&self) -> Result<LiveStatus, LiveError> {
         // Implement the logic to get live status
-        Ok(LiveStatus(1))
+        Ok(LiveStatus::Live)
     }
 
-    async fn get_room_info(&self) -> Result<RoomInfo, LiveError> {
-        let res = self.webapi.get_info_by_room(self.room_id).await?;
-        Ok(res)
+    async fn get_room_info(&self) -> Result<(RoomInfo, UserInfo), LiveError> {
+        self.webapi.get_info_by_room(self.room_id).await
+            .map_err(|_| LiveError::InvalidRoomInfoResponse)
     }
 
-    async fn get_user_info(&self, uid: u64) -> Result<UserInfo, LiveError> {
-        // Implement the logic to get user info
-        Ok(UserInfo {})
+    async fn get_user_info(&self, uid: i32) -> Result<UserInfo, LiveError> {
+        let data = self.webapi.client().get_user_info(uid).await
+            .map_err(|_| LiveError::InvalidRoomInfoResponse)?;
+        UserInfo::from_web_api_data(&data).map_err(|_| LiveError::InvalidRoomInfoResponse)
     }
 
     async fn get_live_streams(&self, qn: Option<i32>) -> Result<Vec<Stream>, LiveError> {
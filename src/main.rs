@@ -1,3 +1,6 @@
+// parking_lot 的锁在 await 期间持有会阻塞整个 worker 线程，状态查询等接口依赖这一点不被违反
+#![deny(clippy::await_holding_lock)]
+
 mod application;
 mod settings;
 
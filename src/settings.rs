@@ -1,2 +1,5 @@
 mod models;
 mod manager;
+
+pub use manager::{Settings, SettingsManager};
+pub use models::{diff_tasks, BiliApiSettings, HeaderSettings, OutputSettings, RecorderSettings, SettingsEvent, TaskSettings};
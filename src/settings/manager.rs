@@ -1,28 +1,157 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use utils::anyhow::{anyhow, Context};
+use utils::parking_lot::Mutex;
+use utils::tracing::{info, warn};
 use utils::BResult;
+use crate::settings::models::{BiliApiSettings, HeaderSettings, OutputSettings};
+use crate::settings::{diff_tasks, SettingsEvent, TaskSettings};
 
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
-
+    pub output: OutputSettings,
+    pub bili_api: BiliApiSettings,
+    pub header: HeaderSettings,
+    /// 同时录制的直播间数上限，0 表示不限制；重新加载任务后生效
+    pub max_concurrent_recordings: usize,
+    pub tasks: Vec<TaskSettings>,
 }
 
 impl Settings {
     pub fn init() -> Self {
-        Self {}
+        Self::default()
     }
 }
 
 pub struct SettingsManager {
+    path: Option<PathBuf>,
     settings: Settings
 }
 
 impl Default for SettingsManager {
     fn default() -> Self {
         Self {
+            path: None,
             settings: Settings::init()
         }
     }
 }
 impl SettingsManager {
-    pub fn get_setting(&self, key: &str) -> BResult<String> {
-        Ok("".to_string())
+    /// 配置文件不存在时使用默认配置，保存时再创建
+    pub fn load_from(path: impl AsRef<Path>) -> BResult<Self> {
+        let path = path.as_ref();
+        let settings = if path.exists() {
+            toml::from_str(&fs::read_to_string(path)?)
+                .with_context(|| format!("Invalid settings file {}", path.display()))?
+        } else {
+            info!("Settings file {} not found, using defaults", path.display());
+            Settings::init()
+        };
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            settings,
+        })
+    }
+
+    /// 先写入临时文件再重命名，保存中途崩溃不会损坏原配置
+    pub fn save(&self) -> BResult<()> {
+        let path = self.path.as_ref().ok_or_else(|| anyhow!("Settings file path is not set"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = toml::to_string_pretty(&self.settings).context("Failed to serialize settings")?;
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// 按 `.` 分隔的路径读取配置项，如 `output.out_dir`
+    pub fn get_setting<T: DeserializeOwned>(&self, key: &str) -> BResult<T> {
+        let mut value = toml::Value::try_from(&self.settings).context("Failed to serialize settings")?;
+        for part in key.split('.').filter(|part| !part.is_empty()) {
+            value = value
+                .get(part)
+                .cloned()
+                .ok_or_else(|| anyhow!("Setting {key} not found"))?;
+        }
+        Ok(value.try_into().with_context(|| format!("Invalid value for setting {key}"))?)
+    }
+
+    /// 重新读取配置文件，返回任务的变化
+    pub fn reload(&mut self) -> BResult<Vec<SettingsEvent>> {
+        let path = self.path.as_ref().ok_or_else(|| anyhow!("Settings file path is not set"))?;
+        let settings: Settings = toml::from_str(&fs::read_to_string(path)?)
+            .with_context(|| format!("Invalid settings file {}", path.display()))?;
+        let events = diff_tasks(&self.settings.tasks, &settings.tasks);
+        self.settings = settings;
+        Ok(events)
     }
-}
\ No newline at end of file
+
+    /// 监听配置文件，修改后自动重新加载并发送任务的变化。
+    /// 返回的 watcher 被 drop 后停止监听
+    pub fn watch(
+        settings_manager: &Arc<Mutex<SettingsManager>>,
+        path: impl AsRef<Path>,
+    ) -> BResult<(RecommendedWatcher, Receiver<SettingsEvent>)> {
+        let path = path.as_ref().to_path_buf();
+        let file_name = path.file_name().map(|name| name.to_os_string());
+        let (sender, receiver) = channel();
+        let settings_manager = settings_manager.clone();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            let event = match res {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Watch settings error: {e}");
+                    return;
+                }
+            };
+            // 编辑器和 save 都可能通过重命名替换文件，所以监听的是所在目录
+            let modified = (event.kind.is_create() || event.kind.is_modify())
+                && event.paths.iter().any(|p| p.file_name() == file_name.as_deref());
+            if !modified {
+                return;
+            }
+            match settings_manager.lock().reload() {
+                Ok(events) => {
+                    for event in events {
+                        info!("Settings changed: {:?}", event);
+                        let _ = sender.send(event);
+                    }
+                }
+                Err(e) => warn!("Failed to reload settings: {e}"),
+            }
+        })
+        .context("Failed to create settings watcher")?;
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Failed to watch {}", dir.display()))?;
+        Ok((watcher, receiver))
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    pub fn task_settings(&self) -> &[TaskSettings] {
+        &self.settings.tasks
+    }
+}
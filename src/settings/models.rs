@@ -1,5 +1,122 @@
+use serde::{Deserialize, Serialize};
+use stream_core::live::{CoverSaveStrategy, QualityNumber, StreamFormat};
+use utils::secret::Secret;
+
 pub struct EnvSettings {
     settings_file: String,
     out_dir: String,
     log_dir: String
-}
\ No newline at end of file
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputSettings {
+    pub out_dir: String,
+    pub path_template: String,
+    pub filesize_limit: usize, // 0 表示不限制
+    pub duration_limit: usize,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self {
+            out_dir: ".".to_string(),
+            path_template: "{room_id} - {title}/blive_{room_id}_{year}-{month}-{day}-{HH}{MM}{SS}".to_string(),
+            filesize_limit: 0,
+            duration_limit: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BiliApiSettings {
+    pub base_api_urls: Vec<String>,
+    pub base_live_api_urls: Vec<String>,
+    pub base_play_info_api_urls: Vec<String>,
+}
+
+impl Default for BiliApiSettings {
+    fn default() -> Self {
+        Self {
+            base_api_urls: vec!["https://api.bilibili.com".to_string()],
+            base_live_api_urls: vec!["https://api.live.bilibili.com".to_string()],
+            base_play_info_api_urls: vec!["https://api.live.bilibili.com".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderSettings {
+    pub user_agent: String,
+    pub cookie: Secret<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecorderSettings {
+    pub stream_format: StreamFormat,
+    pub quality_number: QualityNumber,
+    /// 每次开始录制时在录像旁边保存直播间封面
+    pub save_cover: bool,
+    pub cover_save_strategy: CoverSaveStrategy,
+    /// 丢弃编码器卡住时重复推送的音视频 tag，可能误删静音等内容相同的帧，默认关闭
+    pub remove_repeating_data: bool,
+}
+
+impl Default for RecorderSettings {
+    fn default() -> Self {
+        Self {
+            stream_format: StreamFormat::Flv,
+            quality_number: QualityNumber::P10000,
+            save_cover: false,
+            cover_save_strategy: CoverSaveStrategy::DEFAULT,
+            remove_repeating_data: false,
+        }
+    }
+}
+
+/// 单个直播间的任务配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskSettings {
+    pub room_id: i32,
+    #[serde(default = "enabled")]
+    pub enable_monitor: bool,
+    #[serde(default = "enabled")]
+    pub enable_recorder: bool,
+    #[serde(default)]
+    pub recorder: RecorderSettings,
+}
+
+/// 配置文件重新加载后的任务变化
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingsEvent {
+    TaskAdded(TaskSettings),
+    TaskRemoved(i32),
+    TaskChanged(TaskSettings),
+}
+
+/// 按 room_id 对比新旧任务列表
+pub fn diff_tasks(old: &[TaskSettings], new: &[TaskSettings]) -> Vec<SettingsEvent> {
+    let mut events = Vec::new();
+    for task in old {
+        if !new.iter().any(|t| t.room_id == task.room_id) {
+            events.push(SettingsEvent::TaskRemoved(task.room_id));
+        }
+    }
+    for task in new {
+        match old.iter().find(|t| t.room_id == task.room_id) {
+            None => events.push(SettingsEvent::TaskAdded(task.clone())),
+            Some(old_task) if old_task != task => {
+                events.push(SettingsEvent::TaskChanged(task.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+    events
+}
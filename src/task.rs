@@ -1,3 +1,5 @@
 mod manager;
 mod models;
-mod task;
\ No newline at end of file
+mod task;
+
+pub use manager::Manager;
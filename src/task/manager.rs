@@ -1,32 +1,261 @@
 use std::collections::HashMap;
-use std::ops::Deref;
 use std::sync::Arc;
 use utils::BResult;
-use utils::parking_lot::lock_api::MutexGuard;
-use utils::parking_lot::{Mutex, RawMutex};
-use crate::settings::SettingsManager;
-use crate::task::task::TaskTait;
+use utils::parking_lot::Mutex;
+use utils::tokio;
+use utils::tokio::sync::Semaphore;
+use utils::tracing::{info, warn};
+use crate::settings::{SettingsEvent, SettingsManager, TaskSettings};
+use crate::task::models::TaskStatus;
+use crate::task::task::{RecordingTask, TaskTrait};
 
 pub struct Manager {
-    task_pool: HashMap<String, Box<dyn TaskTait>>,
+    task_pool: HashMap<String, Box<dyn TaskTrait>>,
     settings_manager: Arc<Mutex<SettingsManager>>, // 会被多线程中共享使用
+    /// 所有任务共享，限制同时录制的直播间数
+    recording_limit: Option<Arc<Semaphore>>,
+    /// `run_all` 之后新增的任务立即启动，`stop_all` 后清除
+    running: bool,
 }
 
 impl Default for Manager {
     fn default() -> Self {
+        Self::new(Arc::new(Mutex::new(SettingsManager::default())))
+    }
+}
+impl Manager {
+    pub fn new(settings_manager: Arc<Mutex<SettingsManager>>) -> Self {
         Self {
             task_pool: HashMap::new(),
-            settings_manager: Arc::new(Mutex::new(SettingsManager::default()))
+            settings_manager,
+            recording_limit: None,
+            running: false,
+        }
+    }
+
+    /// 输出、请求头和接口地址使用全局配置
+    fn create_task(&self, settings: TaskSettings) -> Box<dyn TaskTrait> {
+        let global = self.settings_manager.lock();
+        let global = global.settings();
+        let task = RecordingTask::new(settings, global.output.clone(), global.header.clone())
+            .with_bili_api(global.bili_api.clone())
+            .with_recording_limit(self.recording_limit.clone());
+        Box::new(task)
+    }
+
+    /// 为每个配置的直播间创建任务，已存在的直播间跳过，返回新加载的任务数
+    pub fn load_all_tasks(&mut self) -> BResult<usize> {
+        if self.task_pool.is_empty() {
+            let limit = self.settings_manager.lock().settings().max_concurrent_recordings;
+            self.recording_limit = (limit > 0).then(|| Arc::new(Semaphore::new(limit)));
+        }
+        let task_settings = self.settings_manager.lock().task_settings().to_vec();
+        let mut count = 0;
+        for settings in task_settings {
+            let room_id = settings.room_id.to_string();
+            if self.task_pool.contains_key(&room_id) {
+                warn!("Task for room {} already exists, skipped", room_id);
+                continue;
+            }
+            let task = self.create_task(settings);
+            self.task_pool.insert(room_id, task);
+            count += 1;
+        }
+        info!("Loaded {} tasks", count);
+        Ok(count)
+    }
+
+    /// 根据配置文件的变化增加、移除或更新任务，运行中新增的任务立即启动，移除的任务先停止
+    pub async fn apply_settings_event(&mut self, event: &SettingsEvent) {
+        match event {
+            SettingsEvent::TaskAdded(settings) => {
+                if self.task_pool.contains_key(&settings.room_id.to_string()) {
+                    warn!("Task for room {} already exists, skipped", settings.room_id);
+                    return;
+                }
+                let task = self.create_task(settings.clone());
+                self.add_task(task).await;
+            }
+            SettingsEvent::TaskRemoved(room_id) => {
+                self.remove_task(*room_id).await;
+            }
+            SettingsEvent::TaskChanged(settings) => {
+                if let Some(task) = self.task_pool.get_mut(&settings.room_id.to_string()) {
+                    task.update_settings(settings.clone());
+                }
+            }
+        }
+    }
+
+    /// 加入任务池，`run_all` 之后加入的任务立即启动
+    async fn add_task(&mut self, mut task: Box<dyn TaskTrait>) {
+        let room_id = task.room_id();
+        if self.running {
+            if let Err(e) = task.start().await {
+                warn!("Failed to start task of room {}: {e}", room_id);
+            }
+        }
+        self.task_pool.insert(room_id.to_string(), task);
+    }
+
+    /// 停止并移除任务；只丢弃任务不会结束后台的录制，返回任务是否存在
+    async fn remove_task(&mut self, room_id: i32) -> bool {
+        match self.task_pool.remove(&room_id.to_string()) {
+            Some(mut task) => {
+                task.stop().await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 所有任务及其当前状态，按直播间号排序。
+    /// 每个任务只在复制状态时短暂持有自己的锁，不会跨越 await
+    pub fn list_tasks(&self) -> Vec<(String, TaskStatus)> {
+        let mut tasks: Vec<_> = self
+            .task_pool
+            .iter()
+            .map(|(room_id, task)| (room_id.clone(), task.status()))
+            .collect();
+        tasks.sort_by_key(|(room_id, _)| room_id.parse::<i32>().unwrap_or_default());
+        tasks
+    }
+
+    pub fn task_status(&self, room_id: i32) -> Option<TaskStatus> {
+        self.task_pool.get(&room_id.to_string()).map(|task| task.status())
+    }
+
+    /// 移除并释放所有任务，返回移除的任务数；只丢弃任务不会结束后台的录制，需要先 `stop_all`
+    pub fn remove_all_tasks(&mut self) -> usize {
+        let count = self.task_pool.len();
+        self.task_pool.clear();
+        count
+    }
+
+    /// 启动所有任务，每个任务在 tokio 上独立运行，一个任务出错或 panic 不影响其它任务；
+    /// 返回启动成功的任务数
+    pub async fn run_all(&mut self) -> usize {
+        self.running = true;
+        let mut count = 0;
+        for (room_id, task) in self.task_pool.iter_mut() {
+            match task.start().await {
+                Ok(_) => count += 1,
+                Err(e) => warn!("Failed to start task of room {}: {e}", room_id),
+            }
+        }
+        info!("Started {} tasks", count);
+        count
+    }
+
+    /// 停止一个直播间的任务，任务仍保留在任务池中，返回任务是否存在
+    pub async fn stop(&mut self, room_id: i32) -> bool {
+        match self.task_pool.get_mut(&room_id.to_string()) {
+            Some(task) => {
+                task.stop().await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 同时停止所有任务，等待开播的任务需要等到超时，逐个停止会很慢
+    pub async fn stop_all(&mut self) {
+        self.running = false;
+        let handles: Vec<_> = self
+            .task_pool
+            .drain()
+            .map(|(room_id, mut task)| {
+                tokio::spawn(async move {
+                    task.stop().await;
+                    (room_id, task)
+                })
+            })
+            .collect();
+        for handle in handles {
+            match handle.await {
+                Ok((room_id, task)) => {
+                    self.task_pool.insert(room_id, task);
+                }
+                Err(e) => warn!("Failed to stop task: {e}"),
+            }
         }
     }
 }
-impl Manager {
 
-    pub fn load_all_tasks(&self) -> BResult<bool> {
-        let res = self.settings_manager.lock();
-         for i in res.get_setting(&"task") {
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use utils::async_trait::async_trait;
+    use utils::parking_lot::Mutex;
+    use utils::BResult;
+    use crate::settings::{SettingsEvent, TaskSettings};
+    use crate::task::models::{RunningStatus, TaskStatus};
+    use crate::task::task::TaskTrait;
+    use super::Manager;
+
+    /// 记录 `start`、`stop` 调用的任务
+    struct FakeTask {
+        room_id: i32,
+        calls: Arc<Mutex<Vec<(i32, &'static str)>>>,
+    }
+
+    #[async_trait]
+    impl TaskTrait for FakeTask {
+        fn room_id(&self) -> i32 {
+            self.room_id
+        }
+
+        async fn start(&mut self) -> BResult<()> {
+            self.calls.lock().push((self.room_id, "start"));
+            Ok(())
+        }
+
+        async fn stop(&mut self) {
+            self.calls.lock().push((self.room_id, "stop"));
+        }
 
-         }
-        Ok(true)
+        fn status(&self) -> TaskStatus {
+            TaskStatus::new(true, true)
+        }
+
+        fn update_settings(&mut self, _settings: TaskSettings) {}
+
+        fn apply_pending_settings(&mut self) -> bool {
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn start_added_and_stop_removed_tasks() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut manager = Manager::default();
+        manager.add_task(Box::new(FakeTask { room_id: 1, calls: calls.clone() })).await;
+        assert!(calls.lock().is_empty());
+
+        assert_eq!(manager.run_all().await, 1);
+        manager.add_task(Box::new(FakeTask { room_id: 2, calls: calls.clone() })).await;
+        manager.apply_settings_event(&SettingsEvent::TaskRemoved(1)).await;
+        assert_eq!(*calls.lock(), [(1, "start"), (2, "start"), (1, "stop")]);
+        assert!(manager.task_status(1).is_none());
+        assert!(manager.task_status(2).is_some());
+    }
+
+    #[tokio::test]
+    async fn list_tasks_by_room_id() {
+        let mut manager = Manager::default();
+        for room_id in [300, 21, 1000] {
+            let settings = TaskSettings {
+                room_id,
+                enable_monitor: true,
+                enable_recorder: room_id != 21,
+                recorder: Default::default(),
+            };
+            manager.apply_settings_event(&SettingsEvent::TaskAdded(settings)).await;
+        }
+        let rooms: Vec<String> = manager.list_tasks().into_iter().map(|(room_id, _)| room_id).collect();
+        assert_eq!(rooms, ["21", "300", "1000"]);
+        let status = manager.task_status(21).unwrap();
+        assert_eq!(status.running_status(), &RunningStatus::Stop);
+        assert!(manager.task_status(1).is_none());
     }
 }
@@ -1,6 +1,15 @@
+use serde::{Deserialize, Serialize};
+use stream_core::live::{CoverSaveStrategy, QualityNumber};
+pub use stream_core::live::{StreamFormat, VideoFileDetail, VideoFileStatus};
+use utils::secret::Secret;
+use utils::throughput::ThroughputSnapshot;
+use crate::bilibili::danmaku::DanmakuFilter;
+use crate::bilibili::danmaku_writer::DanmakuWriterOptions;
 use crate::bilibili::models::{RoomInfo, UserInfo};
+use crate::settings::BiliApiSettings;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RunningStatus {
     Stop,
     Wait,
@@ -9,30 +18,7 @@ pub enum RunningStatus {
     Inject,
 }
 
-#[derive(Debug, Clone)]
-pub enum StreamFormat {
-    Flv,
-    Ts,
-    Fmp4,
-}
-
-#[derive(Debug, Clone)]
-enum QualityNumber {
-    K4 = 20000,
-    Original = 10000,
-    BluRayDolby = 401,
-    BluRay = 400,
-    UltraHD = 250,
-    HD = 150,
-    Smooth = 80,
-}
-#[derive(Debug, Clone)]
-enum CoverSaveStrategy {
-    DEFAULT,
-    DEDUP
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TaskStatus {
     monitor_enabled: bool,
     recorder_enabled: bool,
@@ -46,12 +32,62 @@ pub struct TaskStatus {
     rec_rate: u64,
     danmu_total: u64,
     danmu_rate: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     real_stream_format: Option<StreamFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     real_quality_number: Option<QualityNumber>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     recording_path: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+impl TaskStatus {
+    pub fn new(monitor_enabled: bool, recorder_enabled: bool) -> Self {
+        Self {
+            monitor_enabled,
+            recorder_enabled,
+            running_status: RunningStatus::Stop,
+            stream_url: String::new(),
+            stream_host: String::new(),
+            dl_total: 0,
+            dl_rate: 0,
+            rec_elapsed: 0.0,
+            rec_total: 0,
+            rec_rate: 0,
+            danmu_total: 0,
+            danmu_rate: 0.0,
+            real_stream_format: None,
+            real_quality_number: None,
+            recording_path: None,
+        }
+    }
+
+    pub fn running_status(&self) -> &RunningStatus {
+        &self.running_status
+    }
+
+    pub fn set_running_status(&mut self, running_status: RunningStatus) {
+        self.running_status = running_status;
+    }
+
+    pub fn recording_path(&self) -> Option<&str> {
+        self.recording_path.as_deref()
+    }
+
+    pub fn set_recording_path(&mut self, recording_path: Option<String>) {
+        self.recording_path = recording_path;
+    }
+
+    /// 用录制器的统计更新下载和写入相关字段，速率为字节/秒
+    pub fn update_throughput(&mut self, snapshot: &ThroughputSnapshot) {
+        self.dl_total = snapshot.downloaded;
+        self.dl_rate = snapshot.download_rate as u64;
+        self.rec_total = snapshot.written;
+        self.rec_rate = snapshot.write_rate as u64;
+        self.rec_elapsed = snapshot.elapsed.as_secs_f64();
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TaskParam {
     // OutputSettings
     out_dir: String,
@@ -64,7 +100,7 @@ pub struct TaskParam {
     base_play_info_api_urls: Vec<String>,
     // HeaderSettings
     user_agent: String,
-    cookie: String,
+    cookie: Secret<String>,
     // DanmakuSettings
     danmu_uname: bool,
     record_gift_send: bool,
@@ -77,6 +113,7 @@ pub struct TaskParam {
     quality_number: QualityNumber,
     fmp4_stream_timeout: i32,
     read_timeout: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     disconnection_timeout: Option<i32>,
     buffer_size: i32,
     save_cover: bool,
@@ -86,22 +123,44 @@ pub struct TaskParam {
     inject_extra_metadata: bool,
 }
 
+impl TaskParam {
+    pub fn danmaku_filter(&self) -> DanmakuFilter {
+        DanmakuFilter {
+            record_gift_send: self.record_gift_send,
+            record_free_gifts: self.record_free_gifts,
+            record_guard_buy: self.record_guard_buy,
+            record_super_chat: self.record_super_chat,
+        }
+    }
+
+    /// 空列表在创建客户端时保留默认地址
+    pub fn bili_api(&self) -> BiliApiSettings {
+        BiliApiSettings {
+            base_api_urls: self.base_api_urls.clone(),
+            base_live_api_urls: self.base_live_api_urls.clone(),
+            base_play_info_api_urls: self.base_play_info_api_urls.clone(),
+        }
+    }
+
+    /// 不保存封面时为 `None`
+    pub fn cover_save_strategy(&self) -> Option<CoverSaveStrategy> {
+        self.save_cover.then_some(self.cover_save_strategy)
+    }
+
+    pub fn danmaku_writer_options(&self) -> DanmakuWriterOptions {
+        DanmakuWriterOptions {
+            danmu_uname: self.danmu_uname,
+            save_raw_danmaku: self.save_raw_danmaku,
+        }
+    }
+}
+
 pub struct TaskData {
     user_info: UserInfo,
     room_info: RoomInfo,
     task_status: TaskStatus,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum VideoFileStatus {
-    Recording,
-    Remixing,
-    Injecting,
-    Completed,
-    Missing,
-    Unknown,
-}
-
 #[derive(Debug, Clone, PartialEq)]
 pub enum DanmukuFileStatus {
     Recording,
@@ -110,16 +169,42 @@ pub enum DanmukuFileStatus {
     Unknown,
 }
 
-#[derive(Debug, Clone)]
-pub struct VideoFileDetail {
-    pub path: String,
-    pub size: i64,
-    pub status: VideoFileStatus,
-}
-
 #[derive(Debug, Clone)]
 pub struct DanmakuFileDetail {
     pub path: String,
     pub size: i64,
     pub status: DanmukuFileStatus,
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use stream_core::live::{CoverSaveStrategy, QualityNumber, StreamFormat};
+    use super::{RunningStatus, TaskParam, TaskStatus};
+
+    #[test]
+    fn serde_task_models() {
+        let status = serde_json::to_value(TaskStatus::new(true, false)).unwrap();
+        assert_eq!(status["running_status"], "stop");
+        assert!(status.get("recording_path").is_none());
+        let status: TaskStatus = serde_json::from_value(status).unwrap();
+        assert_eq!(status.running_status(), &RunningStatus::Stop);
+
+        let param: TaskParam = serde_json::from_value(json!({
+            "out_dir": ".", "path_template": "{room_id}", "filesize_limit": 0, "duration_limit": 0,
+            "base_api_urls": [], "base_live_api_urls": [], "base_play_info_api_urls": [],
+            "user_agent": "", "cookie": "",
+            "danmu_uname": false, "record_gift_send": true, "record_free_gifts": false,
+            "record_guard_buy": true, "record_super_chat": true, "save_raw_danmaku": false,
+            "stream_format": "fmp4", "quality_number": 10000, "fmp4_stream_timeout": 10,
+            "read_timeout": 3, "buffer_size": 8192, "save_cover": true, "cover_save_strategy": "dedup",
+            "remix_to_mp4": true, "inject_extra_metadata": false,
+        }))
+        .unwrap();
+        assert_eq!(param.stream_format, StreamFormat::Fmp4);
+        assert_eq!(param.quality_number, QualityNumber::P10000);
+        assert_eq!(param.cover_save_strategy, CoverSaveStrategy::DEDUP);
+        assert_eq!(param.disconnection_timeout, None);
+        assert!(param.bili_api().base_play_info_api_urls.is_empty());
+    }
+}
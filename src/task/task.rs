@@ -1,15 +1,277 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use blbl::client::{build_http_client, BiliClient, RawJson};
+use blbl::live::Live;
+use blbl::monitor::BiliLiveMonitor;
+use stream_core::flv_stream_recorder::{FlvRecorderOptions, FlvStreamRecorder, QualityHook};
+use stream_core::live::RecorderEvent;
 use utils::async_trait::async_trait;
+use utils::parking_lot::Mutex;
+use utils::reqwest::header::HeaderMap;
+use utils::throughput::Throughput;
+use utils::tokio;
+use utils::tokio::sync::{broadcast, Semaphore};
+use utils::tokio::task::{AbortHandle, JoinHandle};
+use utils::tracing::{error, info, info_span, warn, Instrument};
+use utils::BResult;
+use crate::settings::{BiliApiSettings, HeaderSettings, OutputSettings, TaskSettings};
+use crate::task::models::{RunningStatus, TaskStatus};
+
+/// 轮询直播状态的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// `stop` 等待录制写完当前 tag 的最长时间，超时后直接中止
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[async_trait]
-pub trait TaskTait {
+pub trait TaskTrait: Send {
+    fn room_id(&self) -> i32;
+    /// 开始监控并在开播后录制，已经在运行时不做任何事
+    async fn start(&mut self) -> BResult<()>;
+    /// 停止录制并关闭当前文件
+    async fn stop(&mut self);
+    fn status(&self) -> TaskStatus;
+    /// 录制中的任务在下一个分段（重新连接）开始时应用新配置，未在录制时立即应用
+    fn update_settings(&mut self, settings: TaskSettings);
+    /// 分段切换时调用，返回是否应用了新配置
+    fn apply_pending_settings(&mut self) -> bool;
+}
+
+/// 后台运行中的录制，`recorder` 等待录制结束并处理 panic，`abort` 用于中止录制本身
+struct Running {
+    cancel: Arc<AtomicBool>,
+    throughput: Arc<Throughput>,
+    recorder: JoinHandle<()>,
+    abort: AbortHandle,
+    events: JoinHandle<()>,
+}
+
+/// 一个直播间的录制任务，持有直播间、状态监控和录制器
+pub struct RecordingTask {
+    /// 与录制器的分段回调共享，回调在重新连接前应用 `pending_settings`
+    settings: Arc<Mutex<TaskSettings>>,
+    pending_settings: Arc<Mutex<Option<TaskSettings>>>,
+    output: OutputSettings,
+    header: HeaderSettings,
+    bili_api: BiliApiSettings,
+    status: Arc<Mutex<TaskStatus>>,
+    recording_limit: Option<Arc<Semaphore>>,
+    running: Option<Running>,
+}
+
+impl RecordingTask {
+    pub fn new(settings: TaskSettings, output: OutputSettings, header: HeaderSettings) -> Self {
+        let status = TaskStatus::new(settings.enable_monitor, settings.enable_recorder);
+        Self {
+            settings: Arc::new(Mutex::new(settings)),
+            pending_settings: Default::default(),
+            output,
+            header,
+            bili_api: BiliApiSettings::default(),
+            status: Arc::new(Mutex::new(status)),
+            recording_limit: None,
+            running: None,
+        }
+    }
+
+    /// 接口地址，play info 等请求依次尝试其中的地址
+    pub fn with_bili_api(mut self, bili_api: BiliApiSettings) -> Self {
+        self.bili_api = bili_api;
+        self
+    }
+
+    /// 与其它任务共享的同时录制数限制，`None` 表示不限制
+    pub fn with_recording_limit(mut self, limit: Option<Arc<Semaphore>>) -> Self {
+        self.recording_limit = limit;
+        self
+    }
+
+    async fn create_recorder(&self) -> BResult<FlvStreamRecorder<Live, BiliLiveMonitor<RawJson>>> {
+        let settings = self.settings.lock().clone();
+        let room_id = settings.room_id;
+        let http_client = build_http_client();
+        let api = &self.bili_api;
+        let mut live = Live::with_client(http_client.clone());
+        live.set_base_urls(&api.base_api_urls, &api.base_live_api_urls, &api.base_play_info_api_urls);
+        let mut live = live.init(room_id).await?;
+        live.update_user_info(&self.header.user_agent, self.header.cookie.expose())?;
+        let mut client = BiliClient::new(http_client, HeaderMap::new());
+        client.set_base_urls(&api.base_api_urls, &api.base_live_api_urls, &api.base_play_info_api_urls);
+        let monitor = BiliLiveMonitor::new(Arc::new(client), room_id);
+        let recorder_settings = &settings.recorder;
+        let options = FlvRecorderOptions {
+            out_dir: self.output.out_dir.clone(),
+            path_template: self.output.path_template.clone(),
+            quality_number: recorder_settings.quality_number,
+            save_cover: recorder_settings.save_cover.then_some(recorder_settings.cover_save_strategy),
+            remove_repeating_data: recorder_settings.remove_repeating_data,
+            filesize_limit: self.output.filesize_limit,
+            duration_limit: self.output.duration_limit,
+            ..FlvRecorderOptions::default()
+        };
+        let mut recorder = FlvStreamRecorder::new(live, monitor, options);
+        if let Some(limit) = &self.recording_limit {
+            recorder.set_recording_limit(limit.clone());
+        }
+        recorder.on_segment_boundary(self.quality_hook());
+        Ok(recorder)
+    }
+
+    /// 录制器每次连接直播流前调用，有待应用的配置时应用并返回其中的画质；
+    /// 封装格式等其它配置在下次创建录制器时生效
+    fn quality_hook(&self) -> QualityHook {
+        let settings = self.settings.clone();
+        let pending = self.pending_settings.clone();
+        Box::new(move || apply_pending(&settings, &pending).map(|settings| settings.recorder.quality_number))
+    }
+}
+
+/// 用待应用的配置替换当前配置，返回新配置
+fn apply_pending(settings: &Mutex<TaskSettings>, pending: &Mutex<Option<TaskSettings>>) -> Option<TaskSettings> {
+    let new = pending.lock().take()?;
+    *settings.lock() = new.clone();
+    Some(new)
+}
 
+/// 根据录制器事件更新任务状态
+fn track_events(mut events: broadcast::Receiver<RecorderEvent>, status: Arc<Mutex<TaskStatus>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let mut status = status.lock();
+            match event {
+                RecorderEvent::LiveBegan => status.set_running_status(RunningStatus::Record),
+                RecorderEvent::RecordingStarted { path } | RecorderEvent::FileSplit { new: path, .. } => {
+                    status.set_running_status(RunningStatus::Record);
+                    status.set_recording_path(Some(path));
+                }
+                RecorderEvent::RecordingStopped => {
+                    status.set_running_status(RunningStatus::Wait);
+                    status.set_recording_path(None);
+                }
+                // 转封装在后台进行，重新连接后已经在录制时不改变状态
+                RecorderEvent::RemuxStarted { .. } if *status.running_status() == RunningStatus::Wait => {
+                    status.set_running_status(RunningStatus::Remix)
+                }
+                RecorderEvent::RemuxFinished { .. } if *status.running_status() == RunningStatus::Remix => {
+                    status.set_running_status(RunningStatus::Wait)
+                }
+                RecorderEvent::RemuxStarted { .. } | RecorderEvent::RemuxFinished { .. } | RecorderEvent::Error(_) => {}
+            }
+        }
+    })
 }
 
+#[async_trait]
+impl TaskTrait for RecordingTask {
+    fn room_id(&self) -> i32 {
+        self.settings.lock().room_id
+    }
+
+    async fn start(&mut self) -> BResult<()> {
+        if self.running.is_some() || !self.settings.lock().enable_recorder {
+            return Ok(());
+        }
+        let mut recorder = self.create_recorder().await?;
+        let cancel = recorder.cancel_token();
+        let throughput = recorder.throughput_handle();
+        let events = track_events(recorder.subscribe(), self.status.clone());
+        let event_sender = recorder.event_sender();
+        let room_id = self.room_id();
+        // 录制器内部的日志都带上直播间号，多个直播间同时录制时便于区分
+        let span = info_span!("room", room_id);
+        let run = tokio::spawn(async move { recorder.run(POLL_INTERVAL).await }.instrument(span.clone()));
+        let abort = run.abort_handle();
+        // panic 只会结束这一个任务，转为错误事件，不影响其它直播间
+        let recorder = tokio::spawn(async move {
+            match run.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Recording task stopped: {e}"),
+                Err(e) if e.is_panic() => {
+                    error!("Recording task panicked: {e}");
+                    let _ = event_sender.send(RecorderEvent::Error(format!("Recording task panicked: {e}")));
+                }
+                Err(_) => {}
+            }
+        }.instrument(span));
+        self.status.lock().set_running_status(RunningStatus::Wait);
+        self.running = Some(Running { cancel, throughput, recorder, abort, events });
+        info!("Task of room {room_id} started");
+        Ok(())
+    }
+
+    async fn stop(&mut self) {
+        let Some(mut running) = self.running.take() else {
+            return;
+        };
+        running.cancel.store(true, Ordering::Relaxed);
+        // 等待开播时不会检查取消标志，超时后直接中止
+        if tokio::time::timeout(STOP_TIMEOUT, &mut running.recorder).await.is_err() {
+            running.abort.abort();
+            let _ = running.recorder.await;
+        }
+        running.events.abort();
+        let mut status = self.status.lock();
+        status.set_running_status(RunningStatus::Stop);
+        status.set_recording_path(None);
+        info!("Task of room {} stopped", self.room_id());
+    }
 
-pub struct RecordTask {
+    fn status(&self) -> TaskStatus {
+        let mut status = self.status.lock().clone();
+        if let Some(running) = &self.running {
+            status.update_throughput(&running.throughput.snapshot());
+        }
+        status
+    }
 
+    fn update_settings(&mut self, settings: TaskSettings) {
+        *self.pending_settings.lock() = Some(settings);
+        if self.running.is_none() {
+            self.apply_pending_settings();
+        }
+    }
+
+    fn apply_pending_settings(&mut self) -> bool {
+        apply_pending(&self.settings, &self.pending_settings).is_some()
+    }
 }
 
-impl TaskTait for RecordTask {
+#[cfg(test)]
+mod tests {
+    use stream_core::live::QualityNumber;
+    use crate::settings::{HeaderSettings, OutputSettings, RecorderSettings, TaskSettings};
+    use super::{RecordingTask, TaskTrait};
+
+    fn settings(quality_number: QualityNumber) -> TaskSettings {
+        TaskSettings {
+            room_id: 6,
+            enable_monitor: true,
+            enable_recorder: true,
+            recorder: RecorderSettings { quality_number, ..Default::default() },
+        }
+    }
+
+    #[test]
+    fn pending_quality_applied_at_segment_boundary() {
+        let mut task = RecordingTask::new(settings(QualityNumber::P10000), OutputSettings::default(), HeaderSettings::default());
+        let hook = task.quality_hook();
+        assert_eq!(hook(), None);
 
-}
\ No newline at end of file
+        // 录制中收到的新配置等到录制器下一次连接时才生效
+        *task.pending_settings.lock() = Some(settings(QualityNumber::P250));
+        assert_eq!(task.settings.lock().recorder.quality_number, QualityNumber::P10000);
+        assert_eq!(hook(), Some(QualityNumber::P250));
+        assert_eq!(task.settings.lock().recorder.quality_number, QualityNumber::P250);
+        assert_eq!(hook(), None);
+        assert!(!task.apply_pending_settings());
+
+        // 没有在录制时立即生效
+        task.update_settings(settings(QualityNumber::P400));
+        assert_eq!(task.settings.lock().recorder.quality_number, QualityNumber::P400);
+    }
+}
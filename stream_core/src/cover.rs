@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use utils::reqwest::header::HeaderMap;
+use utils::reqwest::Client;
+use utils::tracing::info;
+use utils::BResult;
+use crate::live::{CoverSaveStrategy, RoomInfo};
+
+/// 下载直播间封面，保存在录像文件旁边并使用相同的文件名，保留封面原本的扩展名（如 webp）。
+/// `DEDUP` 策略下同目录已有内容相同的封面时不再保存，返回已有的文件。
+pub async fn download_cover(
+    client: &Client,
+    headers: HeaderMap,
+    room_info: &RoomInfo,
+    video_path: &Path,
+    strategy: CoverSaveStrategy,
+) -> BResult<Option<PathBuf>> {
+    if room_info.cover.is_empty() {
+        return Ok(None);
+    }
+    let extension = cover_extension(&room_info.cover);
+    let cover_path = video_path.with_extension(extension);
+    let data = client
+        .get(&room_info.cover)
+        .headers(headers)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    if let Some(dir) = cover_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    if strategy == CoverSaveStrategy::DEDUP {
+        if let Some(existing) = find_same_cover(&cover_path, extension, &data)? {
+            info!("Cover already exists: {}", existing.display());
+            return Ok(Some(existing));
+        }
+    }
+    fs::write(&cover_path, &data)?;
+    info!("Cover saved to {}", cover_path.display());
+    Ok(Some(cover_path))
+}
+
+fn cover_extension(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    match path.rsplit_once('.') {
+        Some((_, extension)) if !extension.is_empty() && !extension.contains('/') => extension,
+        _ => "jpg",
+    }
+}
+
+/// 在封面所在目录查找 md5 相同的同类型文件
+fn find_same_cover(cover_path: &Path, extension: &str, data: &[u8]) -> BResult<Option<PathBuf>> {
+    let dir = match cover_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !dir.exists() {
+        return Ok(None);
+    }
+    let digest = md5::compute(data);
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(extension) {
+            continue;
+        }
+        if fs::metadata(&path)?.len() != data.len() as u64 {
+            continue;
+        }
+        if md5::compute(fs::read(&path)?) == digest {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::{cover_extension, find_same_cover};
+
+    #[test]
+    fn extension_from_url() {
+        assert_eq!(cover_extension("https://i0.hdslb.com/bfs/live/a.webp"), "webp");
+        assert_eq!(cover_extension("https://i0.hdslb.com/bfs/live/a.png?x=1.2"), "png");
+        assert_eq!(cover_extension("https://i0.hdslb.com/bfs/live/cover"), "jpg");
+    }
+
+    #[test]
+    fn dedup_by_content() -> utils::BResult<()> {
+        let dir = std::env::temp_dir().join(format!("cover_dedup_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("old.jpg"), b"cover")?;
+
+        let cover_path = dir.join("new.jpg");
+        assert_eq!(find_same_cover(&cover_path, "jpg", b"cover")?, Some(dir.join("old.jpg")));
+        assert_eq!(find_same_cover(&cover_path, "jpg", b"other")?, None);
+        assert_eq!(find_same_cover(&cover_path, "webp", b"cover")?, None);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,39 +1,721 @@
-use utils::chrono::OutOfRange;
-use crate::live::{LiveMonitorTrait, LiveTrait, QualityNumber, RecordingMode, StreamFormat};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use flv::error::FlvError;
+use flv::flv_donload::{copy_raw, parse_flv, FlvConnection};
+use flv::flv_parser::{header, CodecId};
+use flv::keyframe::{KeyframeCallback, KeyframeSampler};
+use flv::pipline::rules::RemoveRepeatingDataRule;
+use flv::pipline::RepairPipeline;
+use flv::probe::probe_flv;
+use utils::anyhow::anyhow;
+use utils::parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+use utils::error::LiveError;
+use utils::tokio::io::AsyncRead;
+use utils::tokio::sync::{broadcast, Semaphore};
+use utils::tokio::time::sleep;
+use utils::throughput::{Throughput, ThroughputSnapshot};
+use utils::tokio;
+use utils::tracing::{info, warn, Instrument};
+use utils::{BResult, CallbackFn, LifecycleFile, Segmentable};
+use crate::live::{
+    CoverSaveStrategy, LiveMonitorTrait, LiveStatus, LiveTrait, QualityNumber, RecorderEvent, RecordingMode, RoomInfo,
+    VideoFileDetail, VideoFileStatus,
+};
+use crate::cover::download_cover;
+use crate::path_template::path_format;
+use crate::{DEFAULT_ACCEPTED_CODECS, DEFAULT_BUFFER_SIZE, DEFAULT_READ_TIMEOUT};
+use crate::postprocess::{remix_to_mp4, Remuxer};
+
+/// 每次连接直播流前调用，返回 `Some` 时之后的连接改用该画质
+pub type QualityHook = Box<dyn Fn() -> Option<QualityNumber> + Send + Sync>;
+
+fn find_file<'a>(files: &'a Mutex<Vec<VideoFileDetail>>, path: &str) -> Option<MappedMutexGuard<'a, VideoFileDetail>> {
+    MutexGuard::try_map(files.lock(), |files| files.iter_mut().rev().find(|d| d.path == path)).ok()
+}
+
+/// `FlvStreamRecorder` 的录制参数，时间均以秒为单位
+#[derive(Debug, Clone)]
+pub struct FlvRecorderOptions {
+    pub out_dir: String,
+    pub path_template: String,
+    pub recording_mode: RecordingMode,
+    /// 每次（重新）获取直播流地址时请求的画质
+    pub quality_number: QualityNumber,
+    pub buffer_size: usize,
+    pub read_timeout: Option<usize>,
+    pub disconnection_timeout: Option<usize>,
+    /// 为 0 表示不限制
+    pub filesize_limit: usize,
+    pub duration_limit: usize,
+    /// 每次开始录制时按该策略保存直播间封面，`None` 表示不保存
+    pub save_cover: Option<CoverSaveStrategy>,
+    /// `Standard` 模式下丢弃编码器卡住时重复推送的音视频 tag，默认关闭
+    pub remove_repeating_data: bool,
+}
+
+impl Default for FlvRecorderOptions {
+    fn default() -> Self {
+        Self {
+            out_dir: ".".to_string(),
+            path_template: "{room_id}".to_string(),
+            recording_mode: RecordingMode::Standard,
+            quality_number: QualityNumber::P10000,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            read_timeout: Some(DEFAULT_READ_TIMEOUT),
+            disconnection_timeout: None,
+            filesize_limit: 0,
+            duration_limit: 0,
+            save_cover: None,
+            remove_repeating_data: false,
+        }
+    }
+}
 
 pub struct FlvStreamRecorder<Live, Monitor> {
     live: Live,
     live_monitor: Monitor,
     out_dir: String,
     path_template: String,
-    stream_format: StreamFormat,
     recording_mode: RecordingMode,
     quality_number: QualityNumber,
-    stream_timeout: usize,
     buffer_size: usize,
     read_timeout: Option<usize>,
     disconnection_timeout: Option<usize>,
     filesize_limit: usize,
     duration_limit: usize,
+    save_cover: Option<CoverSaveStrategy>,
+    remove_repeating_data: bool,
+    quality_hook: Option<QualityHook>,
+    remuxer: Option<Arc<dyn Remuxer>>,
+    keyframe_hook: Option<(Duration, KeyframeCallback)>,
+    recording_limit: Option<Arc<Semaphore>>,
+    accepted_codecs: Vec<CodecId>,
+    cancel: Arc<AtomicBool>,
+    throughput: Arc<Throughput>,
+    events: broadcast::Sender<RecorderEvent>,
+    files: Arc<Mutex<Vec<VideoFileDetail>>>,
     // stream_param_holder
 }
 
 impl<Live: LiveTrait, Monitor: LiveMonitorTrait> FlvStreamRecorder<Live, Monitor> {
-    pub fn new(
-        live: Live,
-        live_monitor: Monitor,
-        out_dir: String,
-        path_template: String,
-        stream_format: StreamFormat,
-        recording_mode: RecordingMode,
+    pub fn new(live: Live, live_monitor: Monitor, options: FlvRecorderOptions) -> Self {
+        let FlvRecorderOptions {
+            out_dir,
+            path_template,
+            recording_mode,
+            quality_number,
+            buffer_size,
+            read_timeout,
+            disconnection_timeout,
+            filesize_limit,
+            duration_limit,
+            save_cover,
+            remove_repeating_data,
+        } = options;
+        let (events, _) = broadcast::channel(64);
+        Self {
+            live,
+            live_monitor,
+            out_dir,
+            path_template,
+            recording_mode,
+            quality_number,
+            buffer_size,
+            read_timeout,
+            disconnection_timeout,
+            filesize_limit,
+            duration_limit,
+            save_cover,
+            remove_repeating_data,
+            quality_hook: None,
+            remuxer: None,
+            keyframe_hook: None,
+            recording_limit: None,
+            accepted_codecs: DEFAULT_ACCEPTED_CODECS.to_vec(),
+            cancel: Default::default(),
+            throughput: Default::default(),
+            events,
+            files: Default::default(),
+        }
+    }
+
+    /// 在每次（重新）连接、开始新分段前检查是否需要切换画质，用于不重启录制地应用新配置
+    pub fn on_segment_boundary(&mut self, hook: QualityHook) {
+        self.quality_hook = Some(hook);
+    }
+
+    /// 设置后每个录制完成的文件都会在后台转封装为 mp4，开始和结束时发出 `RemuxStarted`、`RemuxFinished`
+    pub fn set_remuxer(&mut self, remuxer: Box<dyn Remuxer>) {
+        self.remuxer = Some(Arc::from(remuxer));
+    }
+
+    /// 录制时每隔 `every` 把一个 H.264 关键帧（已拼上 SPS/PPS）交给 `cb`，可用于生成预览图；
+    /// 只在 `Standard` 模式下生效
+    pub fn on_keyframe(&mut self, every: Duration, cb: KeyframeCallback) {
+        self.keyframe_hook = Some((every, cb));
+    }
+
+    /// 多个录制器共享同一个信号量时，同时录制的直播间数不超过其许可数，
+    /// 开播后取得许可才开始录制，直播结束后释放
+    pub fn set_recording_limit(&mut self, limit: Arc<Semaphore>) {
+        self.recording_limit = Some(limit);
+    }
+
+    /// 开始录制前探测视频编码，不在列表中时拒绝录制，默认为 `DEFAULT_ACCEPTED_CODECS`；
+    /// 为空时不探测
+    pub fn set_accepted_codecs(&mut self, codecs: Vec<CodecId>) {
+        self.accepted_codecs = codecs;
+    }
+
+    /// 置位后录制在当前 tag 结束处停止并关闭文件，`start` 和 `run` 返回 `FlvError::Cancelled`；
+    /// 需要在调用 `start` 之前取得
+    pub fn cancel_token(&self) -> Arc<AtomicBool> {
+        self.cancel.clone()
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// 累计及最近几秒的下载、写入速度，重连后继续累计
+    pub fn throughput(&self) -> ThroughputSnapshot {
+        self.throughput.snapshot()
+    }
+
+    /// 录制器移入后台任务后仍可通过它读取速度
+    pub fn throughput_handle(&self) -> Arc<Throughput> {
+        self.throughput.clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RecorderEvent> {
+        self.events.subscribe()
+    }
+
+    /// 录制器移入后台任务后，外部仍可通过它发送事件，例如报告录制任务 panic
+    pub fn event_sender(&self) -> broadcast::Sender<RecorderEvent> {
+        self.events.clone()
+    }
+
+    fn emit(&self, event: RecorderEvent) {
+        // 没有订阅者时发送失败，可以忽略
+        let _ = self.events.send(event);
+    }
+
+    /// 本次录制产生的所有文件及其状态
+    pub fn files(&self) -> MappedMutexGuard<'_, [VideoFileDetail]> {
+        let mut files = self.files.lock();
+        files.iter_mut().for_each(VideoFileDetail::check_missing);
+        MutexGuard::map(files, |files| files.as_mut_slice())
+    }
+
+    /// 等待开播后录制，每场直播结束后继续等待下一场；
+    /// 一场录制出错（如断流超时、拒绝录制）时发出 `RecorderEvent::Error` 后同样继续等待，
+    /// 只有取消时返回 `FlvError::Cancelled`
+    pub async fn run(&mut self, poll_interval: Duration) -> BResult<()> {
+        loop {
+            self.live_monitor.wait_for_live(poll_interval).await;
+            if self.cancelled() {
+                return Err(FlvError::Cancelled.into());
+            }
+            let _permit = match &self.recording_limit {
+                Some(limit) => {
+                    if limit.available_permits() == 0 {
+                        info!("Waiting for a free recording slot");
+                    }
+                    Some(limit.clone().acquire_owned().await.map_err(utils::anyhow::Error::from)?)
+                }
+                None => None,
+            };
+            self.emit(RecorderEvent::LiveBegan);
+            match self.start().await {
+                Ok(()) => {}
+                Err(e) if self.cancelled() => return Err(e),
+                Err(e) => {
+                    warn!("Recording stopped: {e}");
+                    self.emit(RecorderEvent::Error(format!("Recording stopped: {e}")));
+                    // 出错时直播可能仍在进行，间隔一次轮询再重新开始
+                    sleep(poll_interval).await;
+                }
+            }
+        }
+    }
+
+    /// 录制直到直播结束，`Standard` 模式解析并修复 tag，超过大小或时长限制时在关键帧处切分文件，
+    /// `Raw` 模式原样保存收到的字节。
+    /// 直播中断流会重新获取直播流地址并写入新文件，
+    /// 超过 `disconnection_timeout` 秒没有收到完整的 tag 才放弃。
+    /// 探测到的编码不在 `accepted_codecs` 中时不录制，返回 `FlvError::UnsupportedCodec`
+    pub async fn start(&mut self) -> BResult<()> {
+        self.update_quality();
+        self.check_codec().await?;
+        let mut failing_since: Option<Instant> = None;
+        loop {
+            if self.cancelled() {
+                return Err(FlvError::Cancelled.into());
+            }
+            self.update_quality();
+            match self.connect().await {
+                Ok((connection, stream_url, flv_header)) => {
+                    info!("Recording {} ...", stream_url);
+                    let written = self.throughput.snapshot().written;
+                    let result = self.record(connection, &flv_header).await;
+                    self.emit(RecorderEvent::RecordingStopped);
+                    // 写入过完整的 tag 才算恢复，连上后立即断开的流仍受 `disconnection_timeout` 限制
+                    if self.throughput.snapshot().written > written {
+                        failing_since = None;
+                    }
+                    match result {
+                        Ok(()) => info!("Stream closed by server"),
+                        Err(e) if self.cancelled() => {
+                            info!("Recording cancelled");
+                            return Err(e);
+                        }
+                        Err(e) => {
+                            // 读取超时与断流一样是暂时的，重新获取地址后继续录制
+                            match e.downcast_ref::<FlvError>() {
+                                Some(FlvError::ReadTimeout(_)) => {
+                                    warn!("No data for {:?}, reconnecting", self.read_timeout())
+                                }
+                                _ => warn!("Stream interrupted: {e}"),
+                            }
+                            self.emit(RecorderEvent::Error(format!("Stream interrupted: {e}")));
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to connect stream: {e}");
+                    self.emit(RecorderEvent::Error(format!("Failed to connect stream: {e}")));
+                }
+            }
+
+            // 服务端正常关闭和中途断流都可能发生在直播中，以直播状态为准决定结束还是重连；
+            // 查询失败时当作仍在直播，由 `disconnection_timeout` 限制重试时间
+            match self.live_monitor.poll_status().await {
+                Ok(LiveStatus::Live) => {}
+                Ok(_) => {
+                    info!("Live ended");
+                    return Ok(());
+                }
+                Err(e) => warn!("Failed to poll live status: {e}"),
+            }
+            let failing_since = *failing_since.get_or_insert_with(Instant::now);
+            if let Some(timeout) = self.disconnection_timeout {
+                if failing_since.elapsed() >= Duration::from_secs(timeout as u64) {
+                    return Err(anyhow!("Disconnected for more than {timeout} seconds").into());
+                }
+            }
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    fn update_quality(&mut self) {
+        if let Some(quality_number) = self.quality_hook.as_ref().and_then(|hook| hook()) {
+            if quality_number != self.quality_number {
+                info!("Quality changed from {:?} to {quality_number:?}", self.quality_number);
+                self.quality_number = quality_number;
+            }
+        }
+        self.live.set_quality_number(self.quality_number);
+    }
+
+    /// 用单独的连接读取流开头的 sequence header，探测失败时不阻止录制，交给录制过程处理
+    async fn check_codec(&self) -> BResult<()> {
+        if self.accepted_codecs.is_empty() {
+            return Ok(());
+        }
+        let codec = match self.probe_codec().await {
+            Ok(codec) => codec,
+            Err(e) => {
+                warn!("Failed to probe stream: {e}");
+                return Ok(());
+            }
+        };
+        if self.accepted_codecs.contains(&codec) {
+            return Ok(());
+        }
+        Err(FlvError::UnsupportedCodec(codec).into())
+    }
+
+    async fn probe_codec(&self) -> BResult<CodecId> {
+        let (mut connection, _) = self.open().await?;
+        Ok(probe_flv(&mut connection).await?.codec)
+    }
+
+    async fn open(&self) -> BResult<(FlvConnection, String)> {
+        let stream_urls = self.live.live_streams().await?;
+        let stream_url = stream_urls.first().ok_or(LiveError::NoStreamAvailable)?;
+
+        let response = self.live.http_client()
+            .get(stream_url)
+            .headers(self.live.http_headers())
+            .send()
+            .await?
+            .error_for_status()?;
+        let connection = FlvConnection::new(response)
+            .with_buffer_size(self.buffer_size)
+            .with_read_timeout(Some(self.read_timeout()));
+        Ok((connection, stream_url.clone()))
+    }
+
+    async fn connect(&self) -> BResult<(FlvConnection, String, Vec<u8>)> {
+        let (connection, stream_url) = self.open().await?;
+        let mut connection = connection.with_throughput(self.throughput.clone());
+        let header_bytes = connection.read_frame(9).await?;
+        header(&header_bytes).map_err(|_| FlvError::InvalidData("flv header".to_string()))?;
+        Ok((connection, stream_url, header_bytes.to_vec()))
+    }
+
+    /// 以与直播录制相同的流程（修复、切分、后处理和事件）处理本地文件或管道中的 FLV，
+    /// 输出到 `file_name`（不含扩展名），不需要直播间信息
+    pub async fn record_from_reader<R>(&self, reader: R, file_name: &str) -> BResult<()>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let mut connection = FlvConnection::from_reader(reader)
+            .with_buffer_size(self.buffer_size)
+            .with_throughput(self.throughput.clone());
+        let header_bytes = connection.read_frame(9).await?;
+        header(&header_bytes).map_err(|_| FlvError::InvalidData("flv header".to_string()))?;
+        let result = self.record_to(connection, &header_bytes, file_name).await;
+        self.emit(RecorderEvent::RecordingStopped);
+        result
+    }
+
+    async fn record(&self, connection: FlvConnection, flv_header: &[u8]) -> BResult<()> {
+        let room_info = self.live.room_info().await?;
+        let file_name = self.fmt_file_name(&room_info);
+        if let Some(strategy) = self.save_cover {
+            // 封面只是附带的，下载失败不影响录制
+            let client = self.live.http_client();
+            let video_path = format!("{file_name}.flv");
+            let cover = download_cover(&client, self.live.http_headers(), &room_info, Path::new(&video_path), strategy);
+            if let Err(e) = cover.await {
+                warn!("Failed to save cover: {e}");
+            }
+        }
+        self.record_to(connection, flv_header, &file_name).await
+    }
+
+    async fn record_to(&self, connection: FlvConnection, flv_header: &[u8], file_name: &str) -> BResult<()> {
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        let hook_completed = completed.clone();
+        let hook_files = self.files.clone();
+        let remix = self.remuxer.is_some();
+        let hook: CallbackFn = Box::new(move |file_name| {
+            if let Some(detail) = hook_files.lock().iter_mut().rev().find(|d| d.path == file_name) {
+                detail.size = std::fs::metadata(file_name).map_or(0, |m| m.len() as i64);
+                detail.status = if remix {
+                    VideoFileStatus::Remixing
+                } else {
+                    VideoFileStatus::Completed
+                };
+            }
+            hook_completed.lock().push(file_name.to_string());
+        });
+        let create_files = self.files.clone();
+        let create_events = self.events.clone();
+        let previous_file: Mutex<Option<String>> = Mutex::new(None);
+        let create_hook: CallbackFn = Box::new(move |file_name| {
+            create_files.lock().push(VideoFileDetail::new(file_name));
+            let event = match previous_file.lock().replace(file_name.to_string()) {
+                Some(old) => RecorderEvent::FileSplit { old, new: file_name.to_string() },
+                None => RecorderEvent::RecordingStarted { path: file_name.to_string() },
+            };
+            let _ = create_events.send(event);
+        });
+        let file = LifecycleFile::new(file_name, "flv", Some(hook))
+            .with_create_hook(create_hook);
+        let result = match self.recording_mode {
+            RecordingMode::Standard => {
+                let keyframes = self
+                    .keyframe_hook
+                    .as_ref()
+                    .map(|(every, cb)| KeyframeSampler::new(*every, cb.clone()));
+                parse_flv(connection, file, self.segmentable(), self.pipeline(), keyframes, &self.cancel).await
+            }
+            RecordingMode::Raw => copy_raw(connection, file, flv_header, &self.cancel).await,
+        };
+        // 中断时已写完的文件同样需要后处理
+        let completed = std::mem::take(&mut *completed.lock());
+        self.postprocess(completed);
+        Ok(result?)
+    }
+
+    /// 转封装大文件可能需要很久，在单独的任务中依次处理，不阻塞重新连接
+    fn postprocess(&self, files: Vec<String>) {
+        let Some(remuxer) = self.remuxer.clone() else {
+            return;
+        };
+        if files.is_empty() {
+            return;
+        }
+        let tracked = self.files.clone();
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            for path in files {
+                let Some(mut detail) = find_file(&tracked, &path).map(|d| d.clone()) else {
+                    continue;
+                };
+                let _ = events.send(RecorderEvent::RemuxStarted { path: path.clone() });
+                match remix_to_mp4(&mut detail, remuxer.as_ref()).await {
+                    Ok(_) => {
+                        let _ = events.send(RecorderEvent::RemuxFinished { path: path.clone() });
+                    }
+                    Err(e) => {
+                        warn!("Failed to remix {}: {e}", detail.path);
+                        let _ = events.send(RecorderEvent::Error(format!("Failed to remix {}: {e}", detail.path)));
+                    }
+                }
+                if let Some(mut tracked) = find_file(&tracked, &path) {
+                    *tracked = detail;
+                }
+            }
+        }.in_current_span());
+    }
+
+    fn fmt_file_name(&self, room_info: &RoomInfo) -> String {
+        Path::new(&self.out_dir)
+            .join(path_format(&self.path_template, room_info))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// 每次读取的超时，未设置时为 `DEFAULT_READ_TIMEOUT` 秒
+    fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT) as u64)
+    }
+
+    fn pipeline(&self) -> RepairPipeline {
+        let pipeline = RepairPipeline::standard();
+        if self.remove_repeating_data {
+            return pipeline.add_rule(Box::new(RemoveRepeatingDataRule::default()));
+        }
+        pipeline
+    }
+
+    /// 限制为 0 表示不限制
+    fn segmentable(&self) -> Segmentable {
+        let expected_time = (self.duration_limit > 0)
+            .then(|| Duration::from_secs(self.duration_limit as u64));
+        let expected_size = (self.filesize_limit > 0).then_some(self.filesize_limit as u64);
+        Segmentable::new(expected_time, expected_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use utils::async_trait::async_trait;
+    use utils::parking_lot::Mutex;
+    use utils::reqwest::Client;
+    use std::path::Path;
+    use tokio::sync::Notify;
+    use utils::BResult;
+    use flv::error::FlvError;
+    use crate::live::{
+        LiveMonitorTrait, LiveStatus, LiveTrait, QualityNumber, RecorderEvent, RecordingMode, RoomInfo, StreamFormat,
+    };
+    use crate::postprocess::Remuxer;
+    use super::{FlvRecorderOptions, FlvStreamRecorder};
+
+    /// 记录每次获取地址时的画质
+    struct TestLive {
+        url: String,
         quality_number: QualityNumber,
-        stream_timeout: usize,
-        buffer_size: usize,
-        read_timeout: Option<usize>,
-        disconnection_timeout: Option<usize>,
-        filesize_limit: usize,
-        duration_limit: usize,
-    ) {
+        requested: Arc<Mutex<Vec<QualityNumber>>>,
+    }
+
+    impl TestLive {
+        fn new(url: String) -> Self {
+            Self { url, quality_number: QualityNumber::P10000, requested: Default::default() }
+        }
+    }
+
+    #[async_trait]
+    impl LiveTrait for TestLive {
+        async fn room_info(&self) -> BResult<RoomInfo> {
+            Ok(RoomInfo::try_from(&serde_json::json!({ "room_id": 1, "live_start_time": 0 }))?)
+        }
+
+        fn stream_format(&self) -> BResult<StreamFormat> {
+            Ok(StreamFormat::Flv)
+        }
+
+        async fn is_living(&self) -> BResult<bool> {
+            Ok(true)
+        }
 
+        async fn live_streams(&self) -> BResult<Vec<String>> {
+            self.requested.lock().push(self.quality_number);
+            Ok(vec![self.url.clone()])
+        }
+
+        fn set_quality_number(&mut self, quality_number: QualityNumber) {
+            self.quality_number = quality_number;
+        }
+
+        fn http_client(&self) -> Arc<Client> {
+            Arc::new(Client::new())
+        }
+    }
+
+    struct AlwaysLive;
+
+    #[async_trait]
+    impl LiveMonitorTrait for AlwaysLive {
+        async fn poll_status(&self) -> BResult<LiveStatus> {
+            Ok(LiveStatus::Live)
+        }
+    }
+
+    /// 每个连接只返回 FLV 头和 PreviousTagSize0 后关闭
+    async fn serve_header_only() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/live.flv", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let _ = socket.read(&mut request).await;
+                let mut response = b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\nConnection: close\r\n\r\n".to_vec();
+                response.extend([b'F', b'L', b'V', 1, 5, 0, 0, 0, 9, 0, 0, 0, 0]);
+                let _ = socket.write_all(&response).await;
+            }
+        });
+        url
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn give_up_when_every_connection_closes_at_once() {
+        let dir = std::env::temp_dir().join(format!("recorder_reconnect_{}", std::process::id()));
+        let options = FlvRecorderOptions {
+            out_dir: dir.to_string_lossy().to_string(),
+            disconnection_timeout: Some(1),
+            ..FlvRecorderOptions::default()
+        };
+        let live = TestLive::new(serve_header_only().await);
+        let mut recorder = FlvStreamRecorder::new(live, AlwaysLive, options);
+        recorder.set_accepted_codecs(Vec::new());
+
+        let result = tokio::time::timeout(Duration::from_secs(10), recorder.start())
+            .await
+            .expect("reconnected forever");
+        assert!(result.unwrap_err().to_string().contains("Disconnected"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn run_continues_after_a_failed_session() {
+        let dir = std::env::temp_dir().join(format!("recorder_run_{}", std::process::id()));
+        let options = FlvRecorderOptions {
+            out_dir: dir.to_string_lossy().to_string(),
+            disconnection_timeout: Some(0),
+            ..FlvRecorderOptions::default()
+        };
+        let mut recorder = FlvStreamRecorder::new(TestLive::new(serve_header_only().await), AlwaysLive, options);
+        recorder.set_accepted_codecs(Vec::new());
+        let mut events = recorder.subscribe();
+        let cancel = recorder.cancel_token();
+        let run = tokio::spawn(async move { recorder.run(Duration::from_millis(10)).await });
+
+        // 断流超时结束一场录制后报告错误，然后重新开始
+        let mut errors = 0;
+        let mut began = 0;
+        while began < 2 {
+            match events.recv().await.unwrap() {
+                RecorderEvent::LiveBegan => began += 1,
+                RecorderEvent::Error(message) if message.contains("Disconnected") => errors += 1,
+                _ => {}
+            }
+        }
+        assert_eq!(errors, 1);
+        assert!(!run.is_finished());
+
+        cancel.store(true, Ordering::Relaxed);
+        let result = tokio::time::timeout(Duration::from_secs(10), run).await.unwrap().unwrap();
+        assert!(matches!(result.unwrap_err().downcast_ref(), Some(FlvError::Cancelled)));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn quality_change_applies_on_reconnect() {
+        let dir = std::env::temp_dir().join(format!("recorder_quality_{}", std::process::id()));
+        let options = FlvRecorderOptions {
+            out_dir: dir.to_string_lossy().to_string(),
+            disconnection_timeout: Some(1),
+            ..FlvRecorderOptions::default()
+        };
+        let live = TestLive::new(serve_header_only().await);
+        let requested = live.requested.clone();
+        let mut recorder = FlvStreamRecorder::new(live, AlwaysLive, options);
+        recorder.set_accepted_codecs(Vec::new());
+        // 第一次连接之后才有待应用的新画质
+        let calls = AtomicUsize::new(0);
+        recorder.on_segment_boundary(Box::new(move || {
+            (calls.fetch_add(1, Ordering::Relaxed) == 2).then_some(QualityNumber::P250)
+        }));
+
+        assert!(recorder.start().await.is_err());
+        let requested = requested.lock().clone();
+        assert_eq!(requested[0], QualityNumber::P10000);
+        assert!(requested[1..].iter().all(|&qn| qn == QualityNumber::P250));
+        assert!(requested.len() >= 2);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// 收到通知后才完成的转封装
+    struct GatedRemuxer(Arc<Notify>);
+
+    #[async_trait]
+    impl Remuxer for GatedRemuxer {
+        async fn remux(&self, _input: &Path, output: &Path) -> BResult<()> {
+            self.0.notified().await;
+            std::fs::write(output, b"mp4")?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn remux_does_not_block_the_next_session() {
+        let dir = std::env::temp_dir().join(format!("recorder_remux_{}", std::process::id()));
+        let options = FlvRecorderOptions {
+            out_dir: dir.to_string_lossy().to_string(),
+            recording_mode: RecordingMode::Raw,
+            ..FlvRecorderOptions::default()
+        };
+        let mut recorder = FlvStreamRecorder::new(TestLive::new(String::new()), AlwaysLive, options);
+        let gate = Arc::new(Notify::new());
+        recorder.set_remuxer(Box::new(GatedRemuxer(gate.clone())));
+        let mut events = recorder.subscribe();
+
+        let flv = vec![b'F', b'L', b'V', 1, 5, 0, 0, 0, 9, 0, 0, 0, 0];
+        let file_name = dir.join("record").to_string_lossy().to_string();
+        tokio::time::timeout(Duration::from_secs(10), recorder.record_from_reader(std::io::Cursor::new(flv), &file_name))
+            .await
+            .expect("waited for the remux")
+            .unwrap();
+
+        // 录制已经返回，转封装仍在后台等待
+        loop {
+            match events.recv().await.unwrap() {
+                RecorderEvent::RemuxStarted { .. } => break,
+                RecorderEvent::RemuxFinished { .. } => panic!("remux finished before it was released"),
+                _ => {}
+            }
+        }
+        gate.notify_one();
+        loop {
+            if let RecorderEvent::RemuxFinished { path } = events.recv().await.unwrap() {
+                assert!(path.ends_with("record.flv"));
+                break;
+            }
+        }
+        assert!(recorder.files()[0].path.ends_with("record.mp4"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,188 @@
+use std::path::Path;
+use std::time::Duration;
+use flv::hls_download::download;
+use utils::error::LiveError;
+use utils::tokio::time::sleep;
+use utils::tracing::{info, warn};
+use utils::{BResult, Segmentable};
+use crate::live::{LiveMonitorTrait, LiveStatus, LiveTrait, QualityNumber, RoomInfo};
+use crate::path_template::path_format;
+
+/// `HlsStreamRecorder` 的录制参数，时间均以秒为单位
+#[derive(Debug, Clone)]
+pub struct HlsRecorderOptions {
+    pub out_dir: String,
+    pub path_template: String,
+    /// 每次（重新）获取 m3u8 地址时请求的画质
+    pub quality_number: QualityNumber,
+    /// 超过该时间没有新分片时重新获取地址
+    pub stream_timeout: usize,
+    /// 为 0 表示不限制
+    pub filesize_limit: usize,
+    pub duration_limit: usize,
+}
+
+pub struct HlsStreamRecorder<Live, Monitor> {
+    live: Live,
+    live_monitor: Monitor,
+    out_dir: String,
+    path_template: String,
+    quality_number: QualityNumber,
+    stream_timeout: usize,
+    filesize_limit: usize,
+    duration_limit: usize,
+}
+
+impl<Live: LiveTrait, Monitor: LiveMonitorTrait> HlsStreamRecorder<Live, Monitor> {
+    pub fn new(live: Live, live_monitor: Monitor, options: HlsRecorderOptions) -> Self {
+        let HlsRecorderOptions {
+            out_dir,
+            path_template,
+            quality_number,
+            stream_timeout,
+            filesize_limit,
+            duration_limit,
+        } = options;
+        Self {
+            live,
+            live_monitor,
+            out_dir,
+            path_template,
+            quality_number,
+            stream_timeout,
+            filesize_limit,
+            duration_limit,
+        }
+    }
+
+    /// 轮询 m3u8 下载新分片，`stream_timeout` 秒内没有新分片、列表结束或下载出错时查询直播状态，
+    /// 仍在直播则重新获取地址写入新文件，否则结束；与 FLV 录制相同，查询失败时当作仍在直播
+    pub async fn start(&mut self) -> BResult<()> {
+        self.live.set_quality_number(self.quality_number);
+        loop {
+            match self.record().await {
+                Ok(()) => info!("Playlist stopped"),
+                Err(e) => warn!("Stream interrupted: {e}"),
+            }
+            match self.live_monitor.poll_status().await {
+                Ok(LiveStatus::Live) => info!("Still live, reconnecting"),
+                Ok(_) => {
+                    info!("Live ended");
+                    return Ok(());
+                }
+                Err(e) => warn!("Failed to poll live status: {e}"),
+            }
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    async fn record(&self) -> BResult<()> {
+        let stream_urls = self.live.live_streams().await?;
+        let stream_url = stream_urls.first().ok_or(LiveError::NoStreamAvailable)?;
+        let room_info = self.live.room_info().await?;
+
+        info!("Recording {} ...", stream_url);
+        download(
+            stream_url,
+            &self.live.http_client(),
+            &self.live.http_headers(),
+            &self.fmt_file_name(&room_info),
+            self.segmentable(),
+            Duration::from_secs(self.stream_timeout as u64),
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn fmt_file_name(&self, room_info: &RoomInfo) -> String {
+        Path::new(&self.out_dir)
+            .join(path_format(&self.path_template, room_info))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// 限制为 0 表示不限制
+    fn segmentable(&self) -> Segmentable {
+        let expected_time = (self.duration_limit > 0)
+            .then(|| Duration::from_secs(self.duration_limit as u64));
+        let expected_size = (self.filesize_limit > 0).then_some(self.filesize_limit as u64);
+        Segmentable::new(expected_time, expected_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use utils::anyhow::anyhow;
+    use utils::async_trait::async_trait;
+    use utils::parking_lot::Mutex;
+    use utils::reqwest::Client;
+    use utils::BResult;
+    use crate::live::{LiveMonitorTrait, LiveStatus, LiveTrait, QualityNumber, RoomInfo, StreamFormat};
+    use super::{HlsRecorderOptions, HlsStreamRecorder};
+
+    /// 地址指向没有监听的端口，每次下载都失败
+    struct UnreachableLive {
+        requested: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl LiveTrait for UnreachableLive {
+        async fn room_info(&self) -> BResult<RoomInfo> {
+            Ok(RoomInfo::try_from(&serde_json::json!({ "room_id": 1, "live_start_time": 0 }))?)
+        }
+
+        fn stream_format(&self) -> BResult<StreamFormat> {
+            Ok(StreamFormat::Fmp4)
+        }
+
+        async fn is_living(&self) -> BResult<bool> {
+            Ok(true)
+        }
+
+        async fn live_streams(&self) -> BResult<Vec<String>> {
+            self.requested.fetch_add(1, Ordering::Relaxed);
+            Ok(vec!["http://127.0.0.1:1/live.m3u8".to_string()])
+        }
+
+        fn http_client(&self) -> Arc<Client> {
+            Arc::new(Client::new())
+        }
+    }
+
+    /// 依次返回预设的状态
+    struct Statuses(Mutex<VecDeque<BResult<LiveStatus>>>);
+
+    #[async_trait]
+    impl LiveMonitorTrait for Statuses {
+        async fn poll_status(&self) -> BResult<LiveStatus> {
+            self.0.lock().pop_front().unwrap_or(Ok(LiveStatus::Offline))
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect_until_live_ends() {
+        let requested = Arc::new(AtomicUsize::new(0));
+        let live = UnreachableLive { requested: requested.clone() };
+        let statuses = Statuses(Mutex::new(VecDeque::from([
+            Err(anyhow!("network error").into()),
+            Ok(LiveStatus::Live),
+            Ok(LiveStatus::Offline),
+        ])));
+        let options = HlsRecorderOptions {
+            out_dir: std::env::temp_dir().to_string_lossy().to_string(),
+            path_template: "{room_id}".to_string(),
+            quality_number: QualityNumber::P10000,
+            stream_timeout: 1,
+            filesize_limit: 0,
+            duration_limit: 0,
+        };
+        let mut recorder = HlsStreamRecorder::new(live, statuses, options);
+
+        // 下载出错和查询状态失败都重新连接，直播结束后正常返回
+        recorder.start().await.unwrap();
+        assert_eq!(requested.load(Ordering::Relaxed), 3);
+    }
+}
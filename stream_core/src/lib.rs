@@ -1,11 +1,21 @@
+use flv::flv_parser::CodecId;
+
 mod stream_recorder;
 pub mod live;
-mod flv_stream_recorder;
-mod hls_stream_recorder;
-mod op;
+pub mod flv_stream_recorder;
+pub mod hls_stream_recorder;
+pub mod op;
+pub mod path_template;
+pub mod cover;
+pub mod postprocess;
 
-pub const DEFAULT_BUFFER_SIZE: usize = 8192;
+/// 4K 等高码率的流每秒有几 MB，8K 的缓冲区需要频繁读取，本地测试 256K 时吞吐约为 8K 的 2.5 倍
+pub const DEFAULT_BUFFER_SIZE: usize = 256 * 1024;
 
+/// 录制器每次读取的超时（秒），超时后重新连接
 pub const DEFAULT_READ_TIMEOUT: usize = 3;
 
+/// 能够解析和修复的视频编码，AV1 等其它编码的流会被拒绝录制
+pub const DEFAULT_ACCEPTED_CODECS: [CodecId; 2] = [CodecId::H264, CodecId::HEVC];
+
 
@@ -1,35 +1,122 @@
 use std::cmp::PartialEq;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
 use utils::async_trait::async_trait;
 use utils::BResult;
+use utils::chrono::{Local, NaiveDateTime, TimeZone};
+use utils::error::LiveError;
+use utils::regex::Regex;
+use utils::reqwest::header::HeaderMap;
+use utils::reqwest::Client;
+use utils::tokio::time::sleep;
+use utils::tracing::warn;
 use crate::live::LiveStatus::Live;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum StreamFormat {
     Flv,
+    Ts,
     Fmp4,
 }
+impl StreamFormat {
+    /// `getRoomPlayInfo` 中 `format_name` 的取值
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StreamFormat::Flv => "flv",
+            StreamFormat::Ts => "ts",
+            StreamFormat::Fmp4 => "fmp4",
+        }
+    }
+}
+impl FromStr for StreamFormat {
+    type Err = LiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "flv" => Ok(StreamFormat::Flv),
+            "ts" => Ok(StreamFormat::Ts),
+            "fmp4" => Ok(StreamFormat::Fmp4),
+            _ => Err(LiveError::NoStreamFormatAvailable),
+        }
+    }
+}
+impl fmt::Display for StreamFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 #[derive(Debug, Copy, Clone)]
 pub enum RecordingMode {
     Standard,
     Raw,
 }
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverSaveStrategy {
+    DEFAULT,
+    DEDUP, // 已有相同的封面时不再保存
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum VideoFileStatus {
+    Recording,
+    Remixing,
+    Completed,
+    Missing,
+    Unknown,
+}
+#[derive(Debug, Clone)]
+pub struct VideoFileDetail {
+    pub path: String,
+    pub size: i64,
+    pub status: VideoFileStatus,
+}
+
+impl VideoFileDetail {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            size: 0,
+            status: VideoFileStatus::Recording,
+        }
+    }
+
+    /// 文件被删除或移走后标记为 `Missing`，录制中检查的是 `.part` 文件
+    pub fn check_missing(&mut self) {
+        let path = match self.status {
+            VideoFileStatus::Missing => return,
+            VideoFileStatus::Recording => format!("{}.part", self.path),
+            _ => self.path.clone(),
+        };
+        if !Path::new(&path).exists() {
+            self.status = VideoFileStatus::Missing;
+        }
+    }
+}
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum LiveStatus {
+    Offline = 0,
     Live = 1,
-    Offline = 2,
+    Round = 2, // 轮播
     Unknown = 3,
 }
 impl From<i32> for LiveStatus {
     fn from(value: i32) -> Self {
         match value {
+            0 => LiveStatus::Offline,
             1 => LiveStatus::Live,
-            2 => LiveStatus::Offline,
-            3 => LiveStatus::Unknown,
+            2 => LiveStatus::Round,
             _ => LiveStatus::Unknown
         }
     }
 }
-#[derive(Debug, Copy, Clone)]
+/// 按画质数值比较，`P20000` 最高
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "i32", into = "i32")]
 pub enum QualityNumber {
     P20000, // 4K
     P10000, // 原画
@@ -39,9 +126,12 @@ pub enum QualityNumber {
     P150, // 高清
     P80, // 流畅
 }
-impl From<i32> for QualityNumber {
-    fn from(value: i32) -> Self {
-        match value {
+/// 接口返回未知的 qn 时报错，不再猜测为某个画质
+impl TryFrom<i32> for QualityNumber {
+    type Error = LiveError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        Ok(match value {
             20000 => QualityNumber::P20000,
             10000 => QualityNumber::P10000,
             401 => QualityNumber::P401,
@@ -49,14 +139,38 @@ impl From<i32> for QualityNumber {
             250 => QualityNumber::P250,
             150 => QualityNumber::P150,
             80 => QualityNumber::P80,
-            _ => QualityNumber::P250
-        }
+            _ => return Err(LiveError::NoStreamQualityAvailable),
+        })
+    }
+}
+impl QualityNumber {
+    /// 所有画质，从高到低
+    pub fn all() -> [QualityNumber; 7] {
+        [
+            QualityNumber::P20000,
+            QualityNumber::P10000,
+            QualityNumber::P401,
+            QualityNumber::P400,
+            QualityNumber::P250,
+            QualityNumber::P150,
+            QualityNumber::P80,
+        ]
+    }
+}
+impl PartialOrd for QualityNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for QualityNumber {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        i32::from(*self).cmp(&i32::from(*other))
     }
 }
 impl From<QualityNumber> for i32 {
     fn from(value: QualityNumber) -> Self {
         match value {
-              QualityNumber::P20000 => 20000,
+            QualityNumber::P20000 => 20000,
             QualityNumber::P10000 => 10000,
             QualityNumber::P401 => 401,
             QualityNumber::P400 => 400,
@@ -71,20 +185,20 @@ impl From<QualityNumber> for i32 {
 
 #[derive(Debug, Clone)]
 pub struct RoomInfo {
-    uid: i32,
-    room_id: i32,
-    short_room_id: i32,
-    area_id: i32,
-    area_name: String,
-    parent_area_id: i32,
-    parent_area_name: String,
-    live_status: LiveStatus,
-    live_start_time: i64,
-    online: i32,
-    title: String,
-    cover: String,
-    tags: String,
-    description: String,
+    pub uid: i32,
+    pub room_id: i32,
+    pub short_room_id: i32,
+    pub area_id: i32,
+    pub area_name: String,
+    pub parent_area_id: i32,
+    pub parent_area_name: String,
+    pub live_status: LiveStatus,
+    pub live_start_time: i64,
+    pub online: i32,
+    pub title: String,
+    pub cover: String,
+    pub tags: String,
+    pub description: String,
 }
 impl RoomInfo {
     pub fn new(uid: i32,
@@ -119,20 +233,159 @@ impl RoomInfo {
         }
     }
 
+    /// 轮播（`Round`）不算作直播
     pub fn is_living(&self) -> bool {
         self.live_status == Live
     }
 }
 
+impl TryFrom<&serde_json::Value> for RoomInfo {
+    type Error = LiveError;
+
+    /// 同时兼容 `getInfoByRoom` 的 `room_info` 与 `get_info` 的 `data`
+    fn try_from(data: &serde_json::Value) -> Result<Self, Self::Error> {
+        if !data.is_object() {
+            return Err(LiveError::InvalidRoomInfoResponse);
+        }
+        let as_i32 = |key: &str| data[key].as_i64().unwrap_or_default() as i32;
+        let as_string = |key: &str| data[key].as_str().unwrap_or_default().to_string();
+
+        let live_start_time = if let Some(timestamp) = data["live_start_time"].as_i64() {
+            timestamp
+        } else if let Some(time_string) = data["live_time"].as_str() {
+            if time_string == "0000-00-00 00:00:00" {
+                0
+            } else {
+                let naive = NaiveDateTime::parse_from_str(time_string, "%Y-%m-%d %H:%M:%S")
+                    .map_err(|_| LiveError::InvalidRoomInfoResponse)?;
+                Local
+                    .from_local_datetime(&naive)
+                    .single()
+                    .ok_or(LiveError::InvalidRoomInfoResponse)?
+                    .timestamp()
+            }
+        } else {
+            return Err(LiveError::InvalidRoomInfoResponse);
+        };
+
+        let cover = data["cover"]
+            .as_str()
+            .or_else(|| data["user_cover"].as_str())
+            .unwrap_or_default()
+            .to_string();
+        let description = match data["description"].as_str() {
+            Some(desc) => Regex::new(r"<br\s*/?>")
+                .unwrap()
+                .replace_all(desc, "\n")
+                .to_string(),
+            None => "".to_string(),
+        };
+
+        Ok(RoomInfo {
+            uid: as_i32("uid"),
+            room_id: as_i32("room_id"),
+            short_room_id: as_i32("short_id"),
+            area_id: as_i32("area_id"),
+            area_name: as_string("area_name"),
+            parent_area_id: as_i32("parent_area_id"),
+            parent_area_name: as_string("parent_area_name"),
+            live_status: LiveStatus::from(as_i32("live_status")),
+            live_start_time,
+            online: as_i32("online"),
+            title: as_string("title"),
+            cover,
+            tags: as_string("tags"),
+            description,
+        })
+    }
+}
+
+/// 一个直播间的数据来源，实现持有房间号与请求客户端，可以作为 `dyn LiveTrait` 使用
 #[async_trait]
-pub trait  LiveTrait  {
-    async fn room_info() -> BResult<RoomInfo>;
+pub trait LiveTrait: Send + Sync {
+    async fn room_info(&self) -> BResult<RoomInfo>;
+
+    fn stream_format(&self) -> BResult<StreamFormat>;
+
+    async fn is_living(&self) -> BResult<bool>;
+
+    /// 按画质从高到低或主/备用顺序排列的直播流地址
+    async fn live_streams(&self) -> BResult<Vec<String>>;
 
-    fn stream_format() -> BResult<StreamFormat>;
+    /// 之后的 `live_streams` 请求该画质，不支持选择画质的直播源可以忽略
+    fn set_quality_number(&mut self, _quality_number: QualityNumber) {}
 
-    async fn is_living() -> BResult<bool>;
+    /// 下载直播流和封面使用的连接池，与直播源的接口请求共用
+    fn http_client(&self) -> Arc<Client>;
 
-    async fn live_streams() -> BResult<Vec<String>>;
+    /// 下载直播流和封面时附带的请求头，如 Referer、User-Agent 和 Cookie
+    fn http_headers(&self) -> HeaderMap {
+        HeaderMap::new()
+    }
+}
+
+/// 两次轮询之间直播状态的变化
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LiveStatusChange {
+    pub previous: Option<LiveStatus>,
+    pub current: LiveStatus,
 }
 
-pub trait LiveMonitorTrait {}
\ No newline at end of file
+/// 录制器生命周期中的事件，通过 `FlvStreamRecorder::subscribe` 订阅
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecorderEvent {
+    LiveBegan,
+    RecordingStarted { path: String },
+    /// 按大小、时长或 sequence header 变化切分出新文件
+    FileSplit { old: String, new: String },
+    RecordingStopped,
+    RemuxStarted { path: String },
+    RemuxFinished { path: String },
+    Error(String),
+}
+
+#[async_trait]
+pub trait LiveMonitorTrait: Send + Sync {
+    async fn poll_status(&self) -> BResult<LiveStatus>;
+
+    /// 每隔 `interval` 轮询一次，直到直播开始
+    async fn wait_for_live(&self, interval: Duration) {
+        loop {
+            match self.poll_status().await {
+                Ok(Live) => return,
+                Ok(_) => {}
+                Err(e) => warn!("Failed to poll live status: {e}"),
+            }
+            sleep(interval).await;
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::{QualityNumber, StreamFormat};
+
+    #[test]
+    fn quality_order() {
+        let all = QualityNumber::all();
+        assert!(all.windows(2).all(|pair| pair[0] > pair[1]));
+        assert_eq!(all.iter().max(), Some(&QualityNumber::P20000));
+
+        let urls: HashMap<QualityNumber, &str> = all.iter().map(|&qn| (qn, "")).collect();
+        assert_eq!(urls.len(), all.len());
+
+        for qn in all {
+            assert_eq!(QualityNumber::try_from(i32::from(qn)).unwrap(), qn);
+        }
+        assert!(QualityNumber::try_from(30000).is_err());
+    }
+
+    #[test]
+    fn stream_format_from_str() {
+        for format in [StreamFormat::Flv, StreamFormat::Ts, StreamFormat::Fmp4] {
+            assert_eq!(format.to_string().parse::<StreamFormat>().unwrap(), format);
+        }
+        assert_eq!("FMP4".parse::<StreamFormat>().unwrap(), StreamFormat::Fmp4);
+        assert!("mp4".parse::<StreamFormat>().is_err());
+    }
+}
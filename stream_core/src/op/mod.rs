@@ -1 +1 @@
-mod stream_param_resolver;
\ No newline at end of file
+pub mod stream_param_resolver;
\ No newline at end of file
@@ -1,4 +1,11 @@
-use crate::live::{QualityNumber, StreamFormat};
+use utils::BResult;
+use utils::error::LiveError;
+use utils::reqwest::Url;
+use utils::tracing::warn;
+use crate::live::{LiveTrait, QualityNumber, StreamFormat};
+
+/// 连续拿不到直播流的次数上限，超过后 `resolve` 返回错误
+pub const MAX_ATTEMPTS_FOR_NO_STREAM: u8 = 3;
 
 pub struct StreamParamHolder<Live, Monitor > {
     stream_format: StreamFormat,
@@ -9,4 +16,140 @@ pub struct StreamParamHolder<Live, Monitor > {
     attempts_for_no_stream: u8,
     live: Live,
     live_monitor: Monitor,
-}
\ No newline at end of file
+}
+
+impl<Live: LiveTrait, Monitor> StreamParamHolder<Live, Monitor> {
+    pub fn new(live: Live, live_monitor: Monitor, stream_format: StreamFormat, quality_number: QualityNumber) -> Self {
+        Self {
+            stream_format,
+            quality_number,
+            stream_url: String::new(),
+            stream_host: String::new(),
+            use_alternative_stream: false,
+            attempts_for_no_stream: 0,
+            live,
+            live_monitor,
+        }
+    }
+
+    pub fn live(&self) -> &Live {
+        &self.live
+    }
+
+    pub fn live_monitor(&self) -> &Monitor {
+        &self.live_monitor
+    }
+
+    pub fn stream_format(&self) -> StreamFormat {
+        self.stream_format
+    }
+
+    pub fn quality_number(&self) -> QualityNumber {
+        self.quality_number
+    }
+
+    pub fn stream_url(&self) -> &str {
+        &self.stream_url
+    }
+
+    pub fn stream_host(&self) -> &str {
+        &self.stream_host
+    }
+
+    pub fn use_alternative_stream(&self) -> bool {
+        self.use_alternative_stream
+    }
+
+    /// 获取直播流地址与 host，失败或没有可用的流时在主/备用流之间切换重试，
+    /// 连续失败 `MAX_ATTEMPTS_FOR_NO_STREAM` 次后返回最后一次的错误
+    pub async fn resolve(&mut self) -> BResult<(String, String)> {
+        loop {
+            match self.select_stream().await {
+                Ok((url, host)) => {
+                    self.attempts_for_no_stream = 0;
+                    self.stream_url = url.clone();
+                    self.stream_host = host.clone();
+                    return Ok((url, host));
+                }
+                Err(e) => {
+                    self.attempts_for_no_stream += 1;
+                    if self.attempts_for_no_stream >= MAX_ATTEMPTS_FOR_NO_STREAM {
+                        self.attempts_for_no_stream = 0;
+                        return Err(e);
+                    }
+                    self.use_alternative_stream = !self.use_alternative_stream;
+                    warn!(
+                        "Failed to get {} stream: {e}, switch to {} stream",
+                        if self.use_alternative_stream { "primary" } else { "alternative" },
+                        if self.use_alternative_stream { "alternative" } else { "primary" },
+                    );
+                }
+            }
+        }
+    }
+
+    /// 第一个地址为主流，第二个为备用流
+    async fn select_stream(&self) -> BResult<(String, String)> {
+        let stream_urls = self.live.live_streams().await?;
+        let index = usize::from(self.use_alternative_stream);
+        let url = stream_urls.get(index).ok_or(LiveError::NoStreamAvailable)?;
+        let host = Url::parse(url).map_err(utils::anyhow::Error::from)?
+            .host_str()
+            .ok_or(LiveError::NoStreamAvailable)?
+            .to_string();
+        Ok((url.clone(), host))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use utils::async_trait::async_trait;
+    use utils::reqwest::Client;
+    use utils::tokio;
+    use utils::BResult;
+    use utils::error::LiveError;
+    use crate::live::{LiveTrait, QualityNumber, RoomInfo, StreamFormat};
+    use super::StreamParamHolder;
+
+    /// 固定返回给定的直播流地址
+    struct Streams(Vec<&'static str>);
+
+    #[async_trait]
+    impl LiveTrait for Streams {
+        async fn room_info(&self) -> BResult<RoomInfo> {
+            Err(LiveError::InvalidRoomInfoResponse.into())
+        }
+
+        fn stream_format(&self) -> BResult<StreamFormat> {
+            Ok(StreamFormat::Flv)
+        }
+
+        async fn is_living(&self) -> BResult<bool> {
+            Ok(true)
+        }
+
+        async fn live_streams(&self) -> BResult<Vec<String>> {
+            Ok(self.0.iter().map(|url| url.to_string()).collect())
+        }
+
+        fn http_client(&self) -> Arc<Client> {
+            Arc::new(Client::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn fallback_and_give_up() {
+        let mut holder = StreamParamHolder::new(Streams(vec!["https://cn-gotcha01.bilivideo.com/live-bvc/live.flv?expires=1"]), (), StreamFormat::Flv, QualityNumber::P10000);
+        holder.use_alternative_stream = true;
+        let (url, host) = holder.resolve().await.unwrap();
+        assert_eq!(url, "https://cn-gotcha01.bilivideo.com/live-bvc/live.flv?expires=1");
+        assert_eq!(host, "cn-gotcha01.bilivideo.com");
+        assert!(!holder.use_alternative_stream());
+        assert_eq!(holder.stream_host(), host);
+
+        let mut holder = StreamParamHolder::new(Streams(vec![]), (), StreamFormat::Flv, QualityNumber::P10000);
+        assert!(holder.resolve().await.is_err());
+        assert_eq!(holder.attempts_for_no_stream, 0);
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+use utils::chrono::{DateTime, Local};
+use crate::live::RoomInfo;
+
+const TIME_PLACEHOLDERS: &[(&str, &str)] = &[
+    ("{year}", "%Y"),
+    ("{month}", "%m"),
+    ("{day}", "%d"),
+    ("{HH}", "%H"),
+    ("{MM}", "%M"),
+    ("{SS}", "%S"),
+];
+
+/// 渲染输出路径模板，支持 `{room_id}` `{uid}` `{title}` `{area_name}`
+/// `{year}` `{month}` `{day}` `{HH}` `{MM}` `{SS}`
+pub fn render_path(template: &str, room_info: &RoomInfo, datetime: &DateTime<Local>) -> PathBuf {
+    PathBuf::from(datetime.format(&path_format(template, room_info)).to_string())
+}
+
+/// 只替换房间信息占位符，时间占位符转换成 strftime 格式，
+/// 交给 `LifecycleFile` 在每次创建文件时再格式化，切分出的文件才不会重名
+pub fn path_format(template: &str, room_info: &RoomInfo) -> String {
+    let room_placeholders = [
+        ("{room_id}", room_info.room_id.to_string()),
+        ("{uid}", room_info.uid.to_string()),
+        ("{title}", room_info.title.clone()),
+        ("{area_name}", room_info.area_name.clone()),
+    ];
+    let mut path = template.to_string();
+    for (placeholder, value) in room_placeholders {
+        path = path.replace(placeholder, &sanitize(&value).replace('%', "%%"));
+    }
+    for (placeholder, specifier) in TIME_PLACEHOLDERS {
+        path = path.replace(placeholder, specifier);
+    }
+    path
+}
+
+/// 替换 Windows/NTFS 文件名中的非法字符
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use utils::chrono::{Local, TimeZone};
+    use crate::live::{LiveStatus, RoomInfo};
+    use super::{path_format, render_path};
+
+    fn room_info(title: &str) -> RoomInfo {
+        RoomInfo::new(
+            1, 2297410, 0, 0, "单机游戏".to_string(), 0, "".to_string(), LiveStatus::Live, 0, 0,
+            title.to_string(), "".to_string(), "".to_string(), "".to_string(),
+        )
+    }
+
+    #[test]
+    fn render_placeholders() {
+        let datetime = Local.with_ymd_and_hms(2024, 5, 6, 7, 8, 9).unwrap();
+        let path = render_path(
+            "{room_id}/{year}{month}{day}-{HH}{MM}{SS}_{uid}_{area_name}_{title}",
+            &room_info("标题"),
+            &datetime,
+        );
+        assert_eq!(path, PathBuf::from("2297410/20240506-070809_1_单机游戏_标题"));
+    }
+
+    #[test]
+    fn sanitize_title() {
+        let datetime = Local.with_ymd_and_hms(2024, 5, 6, 7, 8, 9).unwrap();
+        let path = render_path("{room_id}/{title}", &room_info(r#"a<b>c:"d/e\f|g?h*100%"#), &datetime);
+        assert_eq!(path, PathBuf::from("2297410/a_b_c__d_e_f_g_h_100%"));
+    }
+
+    #[test]
+    fn keep_time_for_file_creation() {
+        assert_eq!(path_format("{room_id}_{HH}{MM}", &room_info("")), "2297410_%H%M");
+    }
+}
@@ -0,0 +1,83 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use utils::anyhow::anyhow;
+use utils::async_trait::async_trait;
+use utils::tokio::process::Command;
+use utils::tracing::info;
+use utils::BResult;
+use crate::live::{VideoFileDetail, VideoFileStatus};
+
+/// 转封装，只复制音视频流，不重新编码
+#[async_trait]
+pub trait Remuxer: Send + Sync {
+    async fn remux(&self, input: &Path, output: &Path) -> BResult<()>;
+}
+
+/// 调用 ffmpeg 可执行文件 `ffmpeg -i input -c copy output`
+pub struct FfmpegRemuxer {
+    ffmpeg_path: PathBuf,
+}
+
+impl FfmpegRemuxer {
+    pub fn new(ffmpeg_path: impl Into<PathBuf>) -> Self {
+        Self {
+            ffmpeg_path: ffmpeg_path.into(),
+        }
+    }
+}
+
+impl Default for FfmpegRemuxer {
+    fn default() -> Self {
+        Self::new("ffmpeg")
+    }
+}
+
+#[async_trait]
+impl Remuxer for FfmpegRemuxer {
+    async fn remux(&self, input: &Path, output: &Path) -> BResult<()> {
+        let result = Command::new(&self.ffmpeg_path)
+            .args(["-y", "-loglevel", "error", "-i"])
+            .arg(input)
+            .args(["-c", "copy", "-movflags", "+faststart"])
+            .arg(output)
+            .output()
+            .await?;
+        if !result.status.success() {
+            return Err(anyhow!(
+                "ffmpeg exited with {}: {}",
+                result.status,
+                String::from_utf8_lossy(&result.stderr)
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// 纯 Rust 转封装，尚未实现
+#[cfg(feature = "native-remux")]
+pub struct NativeRemuxer;
+
+#[cfg(feature = "native-remux")]
+#[async_trait]
+impl Remuxer for NativeRemuxer {
+    async fn remux(&self, _input: &Path, _output: &Path) -> BResult<()> {
+        Err(anyhow!("Native remux is not implemented yet").into())
+    }
+}
+
+/// 转封装为同名 mp4，状态 `Recording -> Remixing -> Completed`。
+/// 失败时保留原文件，状态为 `Completed`。
+pub async fn remix_to_mp4(detail: &mut VideoFileDetail, remuxer: &dyn Remuxer) -> BResult<()> {
+    let input = PathBuf::from(&detail.path);
+    let output = input.with_extension("mp4");
+    detail.status = VideoFileStatus::Remixing;
+    info!("Remixing {} to {}", input.display(), output.display());
+    let result = remuxer.remux(&input, &output).await;
+    if result.is_ok() {
+        detail.path = output.to_string_lossy().to_string();
+        detail.size = fs::metadata(&output).map_or(0, |m| m.len() as i64);
+    }
+    detail.status = VideoFileStatus::Completed;
+    result
+}
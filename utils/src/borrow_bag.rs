@@ -0,0 +1,69 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// 运行时按类型存取的容器，每种类型只保存一个值
+#[derive(Default)]
+pub struct DynBorrowBag {
+    items: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl DynBorrowBag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 返回被替换的旧值
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.items
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast::<T>().ok())
+            .map(|old| *old)
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.items.get(&TypeId::of::<T>()).and_then(|item| item.downcast_ref::<T>())
+    }
+
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.items.get_mut(&TypeId::of::<T>()).and_then(|item| item.downcast_mut::<T>())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.items
+            .remove(&TypeId::of::<T>())
+            .and_then(|item| item.downcast::<T>().ok())
+            .map(|item| *item)
+    }
+
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.items.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DynBorrowBag;
+
+    #[test]
+    fn lookup_by_type() {
+        let mut bag = DynBorrowBag::new();
+        assert_eq!(bag.insert(1u32), None);
+        assert_eq!(bag.insert("live".to_string()), None);
+        assert_eq!(bag.get::<u32>(), Some(&1));
+        assert_eq!(bag.get::<String>().map(String::as_str), Some("live"));
+        assert_eq!(bag.get::<i64>(), None);
+
+        *bag.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(bag.insert(5u32), Some(2));
+        assert_eq!(bag.remove::<u32>(), Some(5));
+        assert_eq!(bag.len(), 1);
+    }
+}
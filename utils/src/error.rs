@@ -1,5 +1,44 @@
+use std::any::Any;
+use std::fmt::{Debug, Display};
 use crate::TError;
 
+/// `BResult` 的错误类型。`FlvError` 等依赖 utils 的 crate 中的错误由该 crate 实现 `From`，放在 `Other` 中
+#[derive(Debug, TError)]
+pub enum BError {
+    #[error(transparent)]
+    Live(#[from] LiveError),
+    #[error(transparent)]
+    ApiRequest(#[from] ApiRequestError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl BError {
+    /// 取回具体的错误类型，包括 `Other` 中的错误
+    pub fn downcast_ref<E: Display + Debug + Send + Sync + 'static>(&self) -> Option<&E> {
+        match self {
+            BError::Live(e) => (e as &dyn Any).downcast_ref(),
+            BError::ApiRequest(e) => (e as &dyn Any).downcast_ref(),
+            BError::Io(e) => (e as &dyn Any).downcast_ref(),
+            BError::Other(e) => e.downcast_ref(),
+        }
+    }
+}
+
+impl From<reqwest::Error> for BError {
+    fn from(e: reqwest::Error) -> Self {
+        BError::Live(e.into())
+    }
+}
+
+impl From<serde_json::Error> for BError {
+    fn from(e: serde_json::Error) -> Self {
+        BError::Live(e.into())
+    }
+}
+
 #[derive(Debug, TError)]
 pub enum LiveError {
     #[error("HTTP request failed")]
@@ -24,6 +63,8 @@ pub enum LiveError {
     InvalidRoomInfoResponse,
     #[error("Cannot extract info from HTML page")]
     CannotExtractInfo,
+    #[error(transparent)]
+    ApiRequestError(#[from] ApiRequestError),
 }
 
 #[derive(Debug, TError)]
@@ -36,4 +77,14 @@ pub enum ApiRequestError {
     ApiError(i32, String),
     #[error("No base URLs provided")]
     NoBaseUrls,
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("Invalid header: {0}")]
+    InvalidHeader(String),
+    /// 用户配置的请求头名称无效
+    #[error("Invalid header name")]
+    InvalidHeaderName(#[from] reqwest::header::InvalidHeaderName),
+    /// 用户配置的请求头内容无效，例如包含换行
+    #[error("Invalid header value")]
+    InvalidHeaderValue(#[from] reqwest::header::InvalidHeaderValue),
 }
\ No newline at end of file
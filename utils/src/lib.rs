@@ -1,4 +1,7 @@
 pub mod error;
+pub mod borrow_bag;
+pub mod throughput;
+pub mod secret;
 
 pub use chrono;
 pub use regex;
@@ -6,16 +9,17 @@ pub use async_trait;
 pub use reqwest;
 pub use parking_lot;
 pub use tokio;
-pub use anyhow::Result as BResult;
+/// 跨 crate 的统一返回类型，`LiveError`、`ApiRequestError`、`FlvError` 等具体错误都可以用 `?` 转换
+pub type BResult<T, E = error::BError> = Result<T, E>;
+/// 各 crate 定义具体错误类型时使用的 derive，即 `thiserror::Error`
 pub use thiserror::Error as TError;
-pub use tracing::{error, info};
-
 pub use tracing;
 
 pub use anyhow;
 
 
 use chrono::{DateTime, Local};
+use tracing::{error, info};
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -63,12 +67,17 @@ impl Segmentable {
         }
     }
 
+    /// 时长或大小任一超过限制即需要切分
     pub fn needed(&self) -> bool {
         if let Some(expected_time) = self.time.expected {
-            return (self.time.current - self.time.start) >= expected_time;
+            if self.time.current.saturating_sub(self.time.start) >= expected_time {
+                return true;
+            }
         }
         if let Some(expected_size) = self.size.expected {
-            return self.size.current > expected_size;
+            if self.size.current > expected_size {
+                return true;
+            }
         }
         false
     }
@@ -115,55 +124,71 @@ impl Default for Segmentable {
     }
 }
 
-// pub struct LifecycleFile {
-//     pub fmt_file_name: String,
-//     pub file_name: String,
-//     pub path: PathBuf,
-//     pub hook: CallbackFn,
-//     pub extension: &'static str,
-// }
-//
-// impl LifecycleFile {
-//     pub fn new(fmt_file_name: &str, extension: &'static str, hook: Option<CallbackFn>) -> Self {
-//         let hook: Box<dyn Fn(&str) + Send> = if let Some(hook) = hook {
-//             hook
-//         } else {
-//             Box::new(|_| {})
-//         };
-//         Self {
-//             fmt_file_name: fmt_file_name.to_string(),
-//             file_name: "".to_string(),
-//             path: Default::default(),
-//             hook,
-//             extension,
-//         }
-//     }
-//
-//     pub fn create(&mut self) -> Result<&Path, std::io::Error> {
-//         self.file_name = format!(
-//             "{}.{}",
-//             format_filename(&self.fmt_file_name),
-//             self.extension
-//         );
-//         self.path = PathBuf::from(&self.file_name);
-//         if let Some(parent) = self.path.parent() {
-//             fs::create_dir_all(parent)?
-//         }
-//         // path.set_extension(&self.extension);
-//         self.path.set_extension(format!("{}.part", self.extension));
-//         info!("Save to {}", self.path.display());
-//         Ok(self.path.as_path())
-//     }
-//
-//     pub fn rename(&self) {
-//         match fs::rename(&self.path, &self.file_name) {
-//             Ok(_) => (self.hook)(&self.file_name),
-//             Err(e) => {
-//                 error!("drop {} {e}", self.path.display())
-//             }
-//         }
-//     }
-// }
+pub type CallbackFn = Box<dyn Fn(&str) + Send>;
+
+pub struct LifecycleFile {
+    pub fmt_file_name: String,
+    pub file_name: String,
+    pub path: PathBuf,
+    pub hook: CallbackFn,
+    pub create_hook: Option<CallbackFn>,
+    pub extension: &'static str,
+}
+
+impl LifecycleFile {
+    pub fn new(fmt_file_name: &str, extension: &'static str, hook: Option<CallbackFn>) -> Self {
+        let hook: Box<dyn Fn(&str) + Send> = if let Some(hook) = hook {
+            hook
+        } else {
+            Box::new(|_| {})
+        };
+        Self {
+            fmt_file_name: fmt_file_name.to_string(),
+            file_name: "".to_string(),
+            path: Default::default(),
+            hook,
+            create_hook: None,
+            extension,
+        }
+    }
+
+    /// 每次创建新文件时以最终文件名调用
+    pub fn with_create_hook(mut self, create_hook: CallbackFn) -> Self {
+        self.create_hook = Some(create_hook);
+        self
+    }
+
+    pub fn create(&mut self) -> Result<&Path, std::io::Error> {
+        let file_name = format_filename(&self.fmt_file_name);
+        self.file_name = format!("{}.{}", file_name, self.extension);
+        // 同一秒内切分出的文件加上序号，避免覆盖
+        let mut index = 1;
+        while Path::new(&self.file_name).exists() {
+            self.file_name = format!("{}_{}.{}", file_name, index, self.extension);
+            index += 1;
+        }
+        self.path = PathBuf::from(&self.file_name);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?
+        }
+        // path.set_extension(&self.extension);
+        self.path.set_extension(format!("{}.part", self.extension));
+        info!("Save to {}", self.path.display());
+        if let Some(create_hook) = &self.create_hook {
+            create_hook(&self.file_name)
+        }
+        Ok(self.path.as_path())
+    }
+
+    pub fn rename(&self) {
+        match fs::rename(&self.path, &self.file_name) {
+            Ok(_) => (self.hook)(&self.file_name),
+            Err(e) => {
+                error!("drop {} {e}", self.path.display())
+            }
+        }
+    }
+}
 
 pub fn format_filename(file_name: &str) -> String {
     let local: DateTime<Local> = Local::now();
@@ -175,8 +200,11 @@ pub fn format_filename(file_name: &str) -> String {
 
 #[cfg(test)]
 mod tests {
+    use crate::error::{ApiRequestError, BError, LiveError};
+    use crate::Segmentable;
     use anyhow::Result;
     use std::path::{Path, PathBuf};
+    use std::time::Duration;
 
     #[test]
     fn it_works() -> Result<()> {
@@ -190,4 +218,40 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn typed_errors_through_bresult() {
+        fn api() -> crate::BResult<()> {
+            Err(ApiRequestError::NoBaseUrls)?
+        }
+        fn live() -> crate::BResult<()> {
+            api()?;
+            Ok(())
+        }
+
+        let error = live().unwrap_err();
+        assert!(matches!(error, BError::ApiRequest(ApiRequestError::NoBaseUrls)));
+        let error = BError::from(LiveError::LiveRoomLocked);
+        assert!(matches!(error.downcast_ref::<LiveError>(), Some(LiveError::LiveRoomLocked)));
+        // 其它 crate 的错误放在 `Other` 中，同样可以取回
+        let error = BError::from(anyhow::Error::from(ApiRequestError::NoBaseUrls));
+        assert!(matches!(error.downcast_ref::<ApiRequestError>(), Some(ApiRequestError::NoBaseUrls)));
+        assert!(error.downcast_ref::<LiveError>().is_none());
+    }
+
+    #[test]
+    fn segmentable_checks_time_and_size() {
+        let mut segment = Segmentable::new(Some(Duration::from_secs(10)), Some(100));
+        segment.increase_size(101);
+        assert!(segment.needed());
+
+        let mut segment = Segmentable::new(Some(Duration::from_secs(10)), Some(100));
+        segment.set_start_time(Duration::from_secs(5));
+        segment.set_time_position(Duration::from_secs(15));
+        assert!(segment.needed());
+
+        // 时间戳回退不会 panic
+        segment.set_time_position(Duration::from_secs(1));
+        assert!(!segment.needed());
+    }
 }
@@ -0,0 +1,64 @@
+use std::fmt;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Cookie 等敏感值，`Debug` 和 `Display` 只输出 `[REDACTED]`，避免写入日志；
+/// 序列化时保持原值，配置文件可以正常保存
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// 取出原值，只在真正需要时调用，例如设置请求头
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl<T: Serialize> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Secret;
+
+    #[test]
+    fn redacted_but_serialized() {
+        let cookie = Secret::new("SESSDATA=abc123".to_string());
+        assert_eq!(format!("{cookie:?} {cookie}"), "[REDACTED] [REDACTED]");
+        assert_eq!(cookie.expose(), "SESSDATA=abc123");
+
+        let json = serde_json::to_string(&cookie).unwrap();
+        assert_eq!(json, r#""SESSDATA=abc123""#);
+        assert_eq!(serde_json::from_str::<Secret<String>>(&json).unwrap(), cookie);
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+
+/// 计算速率的滑动窗口长度（秒）
+pub const RATE_WINDOW_SECS: u64 = 5;
+
+/// 每秒一个桶，记录这一秒内下载和写入的字节数
+struct Bucket {
+    second: u64,
+    downloaded: u64,
+    written: u64,
+}
+
+/// 录制过程中的下载量和写入量，可在多个线程间共享；
+/// 下载量为从连接收到的字节，写入量为修复后实际写入文件的字节
+pub struct Throughput {
+    started: Instant,
+    downloaded: AtomicU64,
+    written: AtomicU64,
+    buckets: Mutex<VecDeque<Bucket>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ThroughputSnapshot {
+    pub elapsed: Duration,
+    pub downloaded: u64,
+    pub written: u64,
+    /// 最近 `RATE_WINDOW_SECS` 秒的平均速率，字节/秒
+    pub download_rate: f64,
+    pub write_rate: f64,
+}
+
+impl Default for Throughput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Throughput {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            downloaded: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+            buckets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn add_downloaded(&self, bytes: u64) {
+        self.downloaded.fetch_add(bytes, Ordering::Relaxed);
+        self.add(bytes, 0);
+    }
+
+    pub fn add_written(&self, bytes: u64) {
+        self.written.fetch_add(bytes, Ordering::Relaxed);
+        self.add(0, bytes);
+    }
+
+    fn add(&self, downloaded: u64, written: u64) {
+        let second = self.started.elapsed().as_secs();
+        let mut buckets = self.buckets.lock();
+        match buckets.back_mut() {
+            Some(bucket) if bucket.second == second => {
+                bucket.downloaded += downloaded;
+                bucket.written += written;
+            }
+            _ => buckets.push_back(Bucket { second, downloaded, written }),
+        }
+        while buckets.front().is_some_and(|bucket| bucket.second + RATE_WINDOW_SECS <= second) {
+            buckets.pop_front();
+        }
+    }
+
+    pub fn snapshot(&self) -> ThroughputSnapshot {
+        self.snapshot_at(Instant::now())
+    }
+
+    fn snapshot_at(&self, now: Instant) -> ThroughputSnapshot {
+        let elapsed = now.saturating_duration_since(self.started);
+        let second = elapsed.as_secs();
+        let window_start = second.saturating_sub(RATE_WINDOW_SECS - 1);
+        let (downloaded, written) = self
+            .buckets
+            .lock()
+            .iter()
+            .filter(|bucket| bucket.second >= window_start)
+            .fold((0, 0), |(d, w), bucket| (d + bucket.downloaded, w + bucket.written));
+        // 窗口为最近几个完整的秒加上当前这一秒已经过去的部分
+        let span = (elapsed.as_secs_f64() - window_start as f64).max(1e-3);
+        ThroughputSnapshot {
+            elapsed,
+            downloaded: self.downloaded.load(Ordering::Relaxed),
+            written: self.written.load(Ordering::Relaxed),
+            download_rate: downloaded as f64 / span,
+            write_rate: written as f64 / span,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{Bucket, Throughput};
+
+    #[test]
+    fn rolling_rate() {
+        let throughput = Throughput::new();
+        throughput.add_downloaded(1000);
+        throughput.add_written(800);
+        {
+            // 模拟第 0 到 9 秒每秒下载 1000 字节
+            let mut buckets = throughput.buckets.lock();
+            buckets.clear();
+            buckets.extend((0..10).map(|second| Bucket { second, downloaded: 1000, written: 800 }));
+        }
+        let snapshot = throughput.snapshot_at(throughput.started + Duration::from_secs(10));
+        assert_eq!(snapshot.downloaded, 1000);
+        assert_eq!(snapshot.written, 800);
+        // 窗口为第 6 到 10 秒，第 10 秒刚开始，只有 6~9 秒的 4000 字节
+        assert_eq!(snapshot.download_rate, 4000.0 / 4.0);
+        assert_eq!(snapshot.write_rate, 3200.0 / 4.0);
+    }
+}